@@ -3,18 +3,273 @@
 extern crate engine_runtime;
 extern crate utilities;
 
-use std::sync::Arc;
+use std::{process, sync::Arc};
 
-use engine_runtime::RuntimeServer;
-use utilities::result::Result;
-use utilities::setup::CommonSetup;
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use engine_runtime::{
+    config::RuntimeConfig,
+    deploy::Validator,
+    root::{RootManager, WorkspaceStores},
+    runtimes::RouteTable,
+    RuntimeLogger, RuntimeServer, ServerContext,
+};
+use tokio::runtime::Builder;
+use utilities::{
+    errors, http,
+    hyper::{body, Body, Method, Request},
+    result::{Context, Result},
+    setup::CommonSetup,
+};
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logger.
-    env_logger::init();
+fn main() -> Result<()> {
+    let matches = cli().get_matches();
 
-    let setup = Arc::new(CommonSetup::new().await?);
-    let server = RuntimeServer::new(setup);
-    server.listen().await
+    // Initialize logger. Records are written as JSON lines.
+    RuntimeLogger::init()?;
+
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+
+    if let Some(workers) = matches
+        .subcommand_matches("serve")
+        .and_then(|serve| serve.value_of("workers"))
+    {
+        builder.worker_threads(workers.parse().context("parsing worker count")?);
+    }
+
+    let tokio_rt = builder.build().context("creating the main tokio runtime")?;
+
+    tokio_rt.block_on(run(&matches))
+}
+
+fn cli() -> App<'static, 'static> {
+    let workspace = Arg::with_name("workspace")
+        .required(true)
+        .help("The workspace id. Ignored if workspaces are not multiplexed on the volume or db");
+
+    let socket_address = Arg::with_name("socket-address")
+        .long("socket-address")
+        .takes_value(true)
+        .help("Overrides the address the server binds to, like 127.0.0.1:5051");
+
+    App::new("runtime_server")
+        .version(crate_version!())
+        .about("The serverless runtime of Gigamono")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("volume-root")
+                .long("volume-root")
+                .takes_value(true)
+                .global(true)
+                .help("Overrides the folder that holds the workspaces"),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serves workspace apis. The default if no subcommand is given")
+                .arg(socket_address.clone())
+                .arg(
+                    Arg::with_name("workers")
+                        .long("workers")
+                        .takes_value(true)
+                        .help("The number of threads that accept and serve connections"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dev")
+                .about("Serves workspace apis with detailed error pages. Only binds to loopback addresses")
+                .arg(socket_address),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Validates the manifests and scripts of a workspace without running them")
+                .arg(workspace.clone())
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["human", "json"])
+                        .default_value("human")
+                        .help("How problems are printed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("invoke")
+                .about("Handles one request without a network listener and prints the response")
+                .arg(workspace.clone())
+                .arg(Arg::with_name("method").required(true))
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("The url path and query, like /api/users?limit=10"),
+                )
+                .arg(
+                    Arg::with_name("header")
+                        .short("H")
+                        .long("header")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("A request header, like 'Content-Type: application/json'"),
+                )
+                .arg(
+                    Arg::with_name("body")
+                        .long("body")
+                        .takes_value(true)
+                        .help("The request body"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("routes")
+                .about("Prints the apis of a workspace and how requests reach them")
+                .arg(workspace),
+        )
+}
+
+async fn run(matches: &ArgMatches<'static>) -> Result<()> {
+    let mut setup = CommonSetup::new().await?;
+    let mut config = RuntimeConfig::load()?;
+
+    if let Some(volume_root) = matches.value_of("volume-root") {
+        setup.config.volume.root = volume_root.to_string();
+    }
+
+    match matches.subcommand() {
+        ("check", Some(args)) => check(setup, config, args),
+        ("invoke", Some(args)) => invoke(setup, config, args).await,
+        ("routes", Some(args)) => routes(setup, config, args),
+        (subcommand, args) => {
+            if subcommand == "dev" {
+                config.dev = true;
+            }
+
+            if let Some(socket_address) = args.and_then(|args| args.value_of("socket-address")) {
+                setup.config.engines.runtime.socket_address = socket_address.to_string();
+            }
+
+            let server = RuntimeServer::new(Arc::new(setup), config)?;
+            server.listen().await
+        }
+    }
+}
+
+/// Validates a workspace and exits with an error status if there are problems.
+///
+/// Problems are printed as `path:line: message`, or as a JSON array for editors and CI.
+fn check(setup: CommonSetup, config: RuntimeConfig, args: &ArgMatches) -> Result<()> {
+    let root_mgr = open_workspace(&setup, &config, args)?;
+    let problems = Validator::new().validate(&root_mgr)?;
+
+    if args.value_of("format") == Some("json") {
+        let json = serde_json::to_string_pretty(&problems).context("serializing problems")?;
+        println!("{}", json);
+    } else if problems.is_empty() {
+        println!("No problems found");
+    } else {
+        for problem in &problems {
+            println!("{}", problem);
+        }
+
+        eprintln!("{} problems found", problems.len());
+    }
+
+    if !problems.is_empty() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Dispatches a request the way the server does and prints the response.
+async fn invoke(setup: CommonSetup, config: RuntimeConfig, args: &ArgMatches<'_>) -> Result<()> {
+    let method = args.value_of("method").unwrap().to_ascii_uppercase();
+    let path = args.value_of("path").unwrap();
+
+    let mut builder = Request::builder()
+        .method(Method::from_bytes(method.as_bytes()).context("parsing method")?)
+        .uri(path)
+        .header(
+            http::WORKSPACE_ID_HEADER,
+            args.value_of("workspace").unwrap(),
+        );
+
+    for header in args.values_of("header").into_iter().flatten() {
+        let (name, value) = match header.split_once(':') {
+            Some(pair) => pair,
+            None => return errors::new_error_t(format!(r#"header "{}" has no `:`"#, header)),
+        };
+
+        builder = builder.header(name.trim(), value.trim());
+    }
+
+    let body = Body::from(args.value_of("body").unwrap_or_default().to_string());
+    let request = builder.body(body).context("creating request")?;
+
+    let context = Arc::new(ServerContext::new(Arc::new(setup), config)?);
+    let response = RuntimeServer::dispatch(request, context).await;
+
+    println!("{:?} {}", response.version(), response.status());
+    for (name, value) in response.headers() {
+        println!("{}: {}", name, value.to_str().unwrap_or("<binary>"));
+    }
+    println!();
+
+    let body = body::to_bytes(response.into_body())
+        .await
+        .context("reading response body")?;
+    println!("{}", String::from_utf8_lossy(&body));
+
+    Ok(())
+}
+
+/// Prints the route table of a workspace.
+fn routes(setup: CommonSetup, config: RuntimeConfig, args: &ArgMatches) -> Result<()> {
+    let root_mgr = open_workspace(&setup, &config, args)?;
+
+    println!(
+        "{:<40} {:<24} {:<6} {:<10} MIDDLEWARES",
+        "PATH", "METHODS", "AUTH", "WEBSOCKET"
+    );
+
+    for route in RouteTable::load(&root_mgr)? {
+        let mut methods = route.methods.join(",");
+        if route.has_index {
+            methods = if methods.is_empty() {
+                "*".to_string()
+            } else {
+                format!("{},*", methods)
+            };
+        }
+
+        println!(
+            "{:<40} {:<24} {:<6} {:<10} {}",
+            route.path,
+            methods,
+            route.authentication,
+            route.websocket,
+            route.middlewares.join(",")
+        );
+    }
+
+    Ok(())
+}
+
+/// Opens the workspace in the arguments, or the single workspace if workspaces are not multiplexed.
+fn open_workspace(
+    setup: &CommonSetup,
+    config: &RuntimeConfig,
+    args: &ArgMatches,
+) -> Result<RootManager> {
+    let multi_workspace = setup.config.volume.multi_workspace || setup.config.db.multi_workspace;
+
+    let workspace_id = if multi_workspace {
+        let workspace_id = args.value_of("workspace").unwrap();
+        RootManager::validate_workspace_id(workspace_id)?;
+        workspace_id
+    } else {
+        ""
+    };
+
+    let stores = WorkspaceStores::new(config.store, config.symlinks);
+
+    RootManager::open(&setup.config.volume.root, workspace_id, &stores)
 }
@@ -0,0 +1,19 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+mod admin;
+mod hosts;
+mod limits;
+mod manifest;
+mod runtime;
+mod tls;
+mod tracing;
+mod workspace;
+
+pub use admin::*;
+pub use hosts::*;
+pub use limits::*;
+pub use manifest::*;
+pub use runtime::*;
+pub use tls::*;
+pub use tracing::*;
+pub use workspace::*;
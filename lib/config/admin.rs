@@ -0,0 +1,50 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+use utilities::result::{Context, Result};
+
+/// Settings of the admin listener, which serves operations on the runtime itself rather than on workspaces' apis.
+///
+/// It should only be reachable from the operator's network.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminConfig {
+    /// The address the admin listener binds to, like `127.0.0.1:5052`.
+    pub socket_address: String,
+    /// The file that holds the token admin requests must send as `Authorization: Bearer <token>`.
+    pub token_path: PathBuf,
+}
+
+/// Settings of workspace bundle deployments.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DeployConfig {
+    /// Hex-encoded ed25519 public keys. A bundle must be signed by one of them.
+    pub public_keys: Vec<String>,
+    /// The number of previous versions of a workspace kept around for rollback.
+    pub keep_versions: usize,
+    /// The maximum size of an uploaded bundle in bytes.
+    pub max_bundle_size: u64,
+}
+
+impl AdminConfig {
+    /// Reads the admin token from its file, ignoring surrounding whitespace.
+    pub fn load_token(&self) -> Result<String> {
+        let token = fs::read_to_string(&self.token_path).context(format!(
+            r#"attempt to read admin token file {:?}"#,
+            self.token_path
+        ))?;
+
+        Ok(token.trim().to_string())
+    }
+}
+
+impl Default for DeployConfig {
+    fn default() -> Self {
+        Self {
+            public_keys: vec![],
+            keep_versions: 5,
+            max_bundle_size: 100 * 1024 * 1024,
+        }
+    }
+}
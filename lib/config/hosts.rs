@@ -0,0 +1,70 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::PathBuf};
+use utilities::result::{Context, Result};
+
+/// Routing of requests to workspaces by their `Host` header.
+///
+/// Only used when workspaces are multiplexed on the volume or db.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HostRoutingConfig {
+    /// The file that maps hosts to workspace ids.
+    pub mapping_path: PathBuf,
+    #[serde(default)]
+    pub precedence: HostPrecedence,
+}
+
+/// Which of the workspace id header and the `Host` header decides the workspace when both are present.
+///
+/// Either way, a request whose workspace cannot be determined gets a `421 Misdirected Request`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostPrecedence {
+    /// The workspace id header wins. The host is only looked up for requests without one.
+    Header,
+    /// A mapped host wins. The workspace id header is only used for hosts that are not mapped.
+    Host,
+}
+
+/// The content of a host mapping file.
+///
+/// ```yaml
+/// hosts:
+///   shop.acme.com: acme
+/// subdomains_of:
+///   - apps.example.com
+/// ```
+///
+/// Here `shop.acme.com` reaches the `acme` workspace and `<workspace>.apps.example.com` reaches `<workspace>`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct HostMapping {
+    /// Custom domains and the workspace ids they map to.
+    pub hosts: HashMap<String, String>,
+    /// Domains whose direct subdomains are workspace ids.
+    pub subdomains_of: Vec<String>,
+}
+
+impl Default for HostPrecedence {
+    fn default() -> Self {
+        HostPrecedence::Header
+    }
+}
+
+impl HostMapping {
+    /// Parses a host mapping from the content of a mapping file.
+    pub fn try_from(content: &str) -> Result<Self> {
+        serde_yaml::from_str(content).context("parsing host mapping")
+    }
+
+    /// Loads the host mapping file in the config.
+    pub fn load(config: &HostRoutingConfig) -> Result<Self> {
+        let content = fs::read_to_string(&config.mapping_path).context(format!(
+            r#"attempt to read host mapping file {:?}"#,
+            config.mapping_path
+        ))?;
+
+        Self::try_from(&content)
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use serde::Deserialize;
+
+/// A token-bucket rate limit.
+///
+/// A client starts with `capacity` tokens, each request takes one and tokens are refilled at `refill_per_second`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RateLimitConfig {
+    pub capacity: u64,
+    pub refill_per_second: f64,
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+/// What identifies a client for rate limiting purposes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The client's IP address.
+    Ip,
+    /// The value of the named request header, once the auth script of the api has accepted the request. Falls back to
+    /// the client's IP address if the api does not authenticate requests or the header is missing.
+    Header(String),
+    /// The credentials in the `Authorization` header, once the auth script of the api has accepted them. Falls back to
+    /// the client's IP address if the api does not authenticate requests or the header is missing.
+    Principal,
+}
+
+impl Default for RateLimitKey {
+    fn default() -> Self {
+        RateLimitKey::Ip
+    }
+}
+
+/// How the request body of an api is handled.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct BodyConfig {
+    /// Overrides the server's maximum request body size in bytes.
+    pub max_size: Option<u64>,
+    /// Hands the body to scripts as a stream of chunks instead of buffering it whole.
+    pub streaming: bool,
+}
@@ -0,0 +1,78 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::config::{BodyConfig, RateLimitConfig};
+use serde::Deserialize;
+use utilities::result::{Context, Result};
+
+/// Runtime-specific settings of an api manifest.
+///
+/// These live in the same `api.yaml` file as the fields parsed by `ApiManifest` and are handled natively by the runtime server.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ApiSettings {
+    pub cors: Option<CorsConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub body: BodyConfig,
+    pub websocket: WebSocketApiConfig,
+    pub permissions: PermissionSettings,
+}
+
+/// How websocket upgrades of an api are handled.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebSocketApiConfig {
+    /// The module that handles the connection, relative to the api folder. Defaults to `index.ws.js`.
+    pub handler: Option<String>,
+    pub max_message_size: Option<usize>,
+    pub idle_timeout: Option<u64>,
+}
+
+/// Permissions enforced by the runtime server rather than by the JavaScript runtime.
+///
+/// They are declared next to the other permissions of an api manifest.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct PermissionSettings {
+    pub http_event: HttpEventPermissionSettings,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpEventPermissionSettings {
+    /// Allows clients to upgrade requests to the api to websocket connections.
+    pub websocket: bool,
+}
+
+/// The CORS policy of an api.
+///
+/// Origins may contain `*` wildcards, e.g. `https://*.example.com`. A lone `*` allows any origin.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allow_origins: Vec<String>,
+    pub allow_methods: Vec<String>,
+    pub allow_headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+impl ApiSettings {
+    /// Parses the runtime settings from the content of an api manifest.
+    pub fn try_from(content: &str) -> Result<Self> {
+        serde_yaml::from_str(content).context("parsing runtime settings from api manifest")
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allow_origins: vec![],
+            allow_methods: vec!["GET".into(), "HEAD".into(), "POST".into()],
+            allow_headers: vec![],
+            expose_headers: vec![],
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
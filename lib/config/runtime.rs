@@ -0,0 +1,265 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{env, fs, path::PathBuf};
+
+use crate::config::{AdminConfig, DeployConfig, HostRoutingConfig, TlsConfig, TracingConfig};
+use serde::{Deserialize, Serialize};
+use utilities::{
+    errors,
+    result::{Context, Result},
+};
+
+/// Settings of the runtime server that are not part of the common config.
+///
+/// They are read from the `engines.runtime` section of the gigamono config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// The maximum size of a request body in bytes. Api manifests can override it.
+    pub max_body_size: u64,
+    pub sse: SseConfig,
+    pub websocket: WebSocketConfig,
+    pub http2: Http2Config,
+    pub health: HealthConfig,
+    /// Terminates TLS on the server's socket if set.
+    pub tls: Option<TlsConfig>,
+    /// Maps hosts to workspaces if set, alongside the workspace id header.
+    pub host_routing: Option<HostRoutingConfig>,
+    pub symlinks: SymlinkPolicy,
+    /// Where workspace files are kept.
+    pub store: StoreKind,
+    /// Serves admin operations on a listener of its own if set.
+    pub admin: Option<AdminConfig>,
+    pub deploy: DeployConfig,
+    pub console: ConsoleConfig,
+    /// Exports request traces if set.
+    pub tracing: Option<TracingConfig>,
+    /// Answers failed requests with error pages that show the script stack, route and permissions.
+    /// Only enabled by the `dev` subcommand, never by the config file, and only allowed on loopback addresses.
+    #[serde(skip)]
+    pub dev: bool,
+}
+
+/// Settings of server-sent event streams.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SseConfig {
+    /// Seconds between keep-alive comments sent on an idle stream.
+    pub heartbeat_interval: u64,
+    /// Seconds after which a stream is closed by the server, freeing its runtime.
+    pub max_lifetime: u64,
+}
+
+/// Settings of websocket connections.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    /// The maximum size of a message in bytes. Api manifests can lower or raise it.
+    pub max_message_size: usize,
+    /// Seconds without any message from the client after which a connection is closed. Api manifests can override it.
+    pub idle_timeout: u64,
+}
+
+/// Settings of the console output of scripts.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConsoleConfig {
+    /// The folder that holds a `<workspace id>/console.log` for every workspace.
+    /// Defaults to `<workspace id>/logs/console.log` under the volume root if workspaces are on the local volume.
+    pub log_dir: Option<PathBuf>,
+    /// The size in bytes at which a log file is rotated.
+    pub max_file_size: u64,
+    /// The number of rotated log files kept besides the current one.
+    pub max_files: usize,
+    /// The size in bytes above which a message is truncated.
+    pub max_message_size: usize,
+    /// The number of bytes a request can log, after which its console output is dropped.
+    pub max_request_size: u64,
+    /// Also writes console output to the server log.
+    pub mirror: bool,
+}
+
+/// How symlinks inside workspaces are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Any path that goes through a symlink is rejected.
+    Deny,
+    /// Symlinks are followed as long as they resolve to a path within the workspace.
+    AllowWithinWorkspace,
+}
+
+/// The kind of store that holds workspace files.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreKind {
+    /// Workspaces are folders under the volume root.
+    Local,
+    /// Workspaces start empty and only live in memory. Meant for tests.
+    Memory,
+    /// Workspaces are read-only `<workspace id>.tar`, `.tar.gz`, `.tgz` or `.zip` archives under the volume root.
+    /// Without multiple workspaces, the volume root is the archive itself.
+    Archive,
+}
+
+/// Settings of the health endpoints and shutdown.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// The number of requests handled at once from which the server reports itself as not ready.
+    pub max_active_threads: usize,
+    /// Seconds the server keeps serving after a shutdown signal, reporting itself as not ready, so load balancers can stop sending it requests.
+    pub shutdown_delay: u64,
+}
+
+/// Settings of HTTP/2 connections.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Http2Config {
+    /// Accepts h2c with prior knowledge on plain connections and offers h2 during ALPN negotiation.
+    pub enabled: bool,
+    /// The maximum number of streams a client can have open on one connection at a time.
+    pub max_concurrent_streams: u32,
+    /// The flow-control window of each stream in bytes.
+    pub initial_stream_window_size: u32,
+    /// The flow-control window of a whole connection in bytes.
+    pub initial_connection_window_size: u32,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    engines: EnginesSection,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct EnginesSection {
+    runtime: RuntimeConfig,
+}
+
+impl RuntimeConfig {
+    /// The environment variable that holds the path of the gigamono config file.
+    pub const CONFIG_PATH_ENV: &'static str = "GIGAMONO_CONFIG_PATH";
+
+    /// Parses the runtime config from the content of a gigamono config file.
+    ///
+    /// Fails if the config file sets dev mode.
+    pub fn try_from(content: &str) -> Result<Self> {
+        let value: serde_yaml::Value =
+            serde_yaml::from_str(content).context("parsing runtime config from config file")?;
+
+        // SEC: Dev mode shows workspace code and permissions to clients, so a deployed config must not turn it on.
+        if !value["engines"]["runtime"]["dev"].is_null() {
+            return errors::new_error_t(
+                "dev mode cannot be set in the config file, use the `dev` subcommand instead",
+            );
+        }
+
+        let file: ConfigFile =
+            serde_yaml::from_value(value).context("parsing runtime config from config file")?;
+
+        if let Some(tracing) = &file.engines.runtime.tracing {
+            tracing.validate()?;
+        }
+
+        Ok(file.engines.runtime)
+    }
+
+    /// Loads the runtime config from the gigamono config file or the default config if there is no config file.
+    pub fn load() -> Result<Self> {
+        let path = match env::var(Self::CONFIG_PATH_ENV) {
+            Ok(path) => path,
+            Err(_) => return Ok(Self::default()),
+        };
+
+        let content = fs::read_to_string(&path)
+            .context(format!(r#"attempt to read config file {:?}"#, path))?;
+
+        Self::try_from(&content)
+    }
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: 10 * 1024 * 1024,
+            sse: SseConfig::default(),
+            websocket: WebSocketConfig::default(),
+            http2: Http2Config::default(),
+            health: HealthConfig::default(),
+            tls: None,
+            host_routing: None,
+            symlinks: SymlinkPolicy::default(),
+            store: StoreKind::default(),
+            admin: None,
+            deploy: DeployConfig::default(),
+            console: ConsoleConfig::default(),
+            tracing: None,
+            dev: false,
+        }
+    }
+}
+
+impl Default for SseConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: 15,
+            max_lifetime: 5 * 60,
+        }
+    }
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: 1024 * 1024,
+            idle_timeout: 60,
+        }
+    }
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        Self {
+            log_dir: None,
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 3,
+            max_message_size: 8 * 1024,
+            max_request_size: 256 * 1024,
+            mirror: false,
+        }
+    }
+}
+
+impl Default for SymlinkPolicy {
+    fn default() -> Self {
+        SymlinkPolicy::AllowWithinWorkspace
+    }
+}
+
+impl Default for StoreKind {
+    fn default() -> Self {
+        StoreKind::Local
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_active_threads: 1024,
+            shutdown_delay: 5,
+        }
+    }
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent_streams: 100,
+            initial_stream_window_size: 64 * 1024,
+            initial_connection_window_size: 1024 * 1024,
+        }
+    }
+}
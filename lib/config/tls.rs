@@ -0,0 +1,50 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// TLS termination settings of the runtime server.
+///
+/// Certificates and keys are PEM files. A self-signed pair for local testing can be generated with
+/// `openssl req -x509 -newkey rsa:2048 -nodes -days 365 -subj "/CN=localhost" -keyout key.pem -out cert.pem`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TlsConfig {
+    /// The certificate chain served when no SNI certificate matches the requested domain.
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Certificates selected by the server name the client asks for.
+    #[serde(default)]
+    pub certificates: Vec<SniCertificate>,
+    /// Protocols offered during ALPN negotiation, in order of preference. `h2` is left out if HTTP/2 is disabled.
+    #[serde(default = "TlsConfig::default_alpn_protocols")]
+    pub alpn_protocols: Vec<String>,
+    /// Seconds between checks for changed certificate files.
+    #[serde(default = "TlsConfig::default_reload_interval")]
+    pub reload_interval: u64,
+    /// Seconds a client has to complete the handshake before its connection is dropped.
+    #[serde(default = "TlsConfig::default_handshake_timeout")]
+    pub handshake_timeout: u64,
+}
+
+/// A certificate for the domains of a workspace.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SniCertificate {
+    /// Domains the certificate is served for. A leading `*.` matches any single subdomain.
+    pub domains: Vec<String>,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    fn default_alpn_protocols() -> Vec<String> {
+        vec!["h2".into(), "http/1.1".into()]
+    }
+
+    fn default_reload_interval() -> u64 {
+        30
+    }
+
+    fn default_handshake_timeout() -> u64 {
+        10
+    }
+}
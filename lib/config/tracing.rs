@@ -0,0 +1,76 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utilities::{
+    errors,
+    hyper::Uri,
+    result::{Context, Result},
+};
+
+/// Distributed tracing settings of the runtime server.
+///
+/// Spans are exported to an OpenTelemetry collector over OTLP/HTTP in its JSON encoding. The exporter only speaks plain
+/// HTTP, so the collector is expected to run next to the server, like a local agent or sidecar.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TracingConfig {
+    /// The base `http://` url of the collector, like `http://127.0.0.1:4318`. Spans are posted to `<endpoint>/v1/traces`.
+    pub endpoint: String,
+    /// The `service.name` spans are reported under.
+    #[serde(default = "TracingConfig::default_service_name")]
+    pub service_name: String,
+    /// Headers sent with every export. Never serialized, as they may hold secrets.
+    #[serde(default, skip_serializing)]
+    pub headers: HashMap<String, String>,
+    /// The maximum number of spans sent in one export.
+    #[serde(default = "TracingConfig::default_max_batch_size")]
+    pub max_batch_size: usize,
+    /// The maximum number of spans waiting to be exported. Spans beyond it are dropped.
+    #[serde(default = "TracingConfig::default_max_queue_size")]
+    pub max_queue_size: usize,
+    /// Seconds between exports.
+    #[serde(default = "TracingConfig::default_export_interval")]
+    pub export_interval: u64,
+    /// Records the statements of db queries as `db.statement` attributes. Off by default, since statements can hold
+    /// literal values like passwords.
+    #[serde(default)]
+    pub record_db_statements: bool,
+}
+
+impl TracingConfig {
+    /// Checks that the endpoint is a plain HTTP url.
+    ///
+    /// SEC: Spans and headers are sent unencrypted, so an endpoint that looks like it would be reached over TLS is
+    /// rejected rather than silently failing every export.
+    pub fn validate(&self) -> Result<()> {
+        let uri: Uri = self
+            .endpoint
+            .parse()
+            .context(format!(r#"parsing tracing endpoint "{}""#, self.endpoint))?;
+
+        if uri.scheme_str() != Some("http") || uri.host().is_none() {
+            return errors::new_error_t(format!(
+                r#"tracing endpoint "{}" must be an http:// url, as spans are exported over plain HTTP"#,
+                self.endpoint
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_service_name() -> String {
+        "gigamono-runtime".into()
+    }
+
+    fn default_max_batch_size() -> usize {
+        512
+    }
+
+    fn default_max_queue_size() -> usize {
+        2048
+    }
+
+    fn default_export_interval() -> u64 {
+        5
+    }
+}
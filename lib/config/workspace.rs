@@ -0,0 +1,37 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::path::Path;
+
+use crate::{config::RateLimitConfig, root::RootManager};
+use serde::Deserialize;
+use utilities::result::{Context, Result};
+
+/// Settings that apply to every api of a workspace.
+///
+/// They are read from an optional `workspace.yaml` file at the workspace root.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceConfig {
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl WorkspaceConfig {
+    /// The name of the workspace config file.
+    pub const FILENAME: &'static str = "workspace.yaml";
+
+    /// Parses the workspace config from the content of a workspace config file.
+    pub fn try_from(content: &str) -> Result<Self> {
+        serde_yaml::from_str(content).context("parsing workspace config")
+    }
+
+    /// Loads the workspace config of a workspace or the default config if the workspace does not have one.
+    pub fn load(root_mgr: &RootManager) -> Result<Self> {
+        if !root_mgr.exists(Path::new(Self::FILENAME))? {
+            return Ok(Self::default());
+        }
+
+        let content = root_mgr.read_file_from_workspace(Path::new(Self::FILENAME))?;
+
+        Self::try_from(&content)
+    }
+}
@@ -0,0 +1,9 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+mod bundle;
+mod deployer;
+mod validator;
+
+pub use bundle::*;
+pub use deployer::*;
+pub use validator::*;
@@ -0,0 +1,213 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::Problem;
+use crate::{
+    config::DeployConfig,
+    root::{ArchiveStore, RootManager, WorkspaceStore},
+};
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use utilities::{
+    hyper::{Body, Response, StatusCode},
+    result::Result,
+};
+
+/// A workspace packaged for deployment.
+///
+/// A bundle is a tar, gzipped tar or zip archive of the workspace files with two extra files at its root:
+///
+/// - `bundle.yaml`, the manifest, which names the version and lists the sha-256 hash of every file.
+/// - `bundle.sig`, the hex-encoded ed25519 signature of the manifest's exact bytes.
+///
+/// ```yaml
+/// version: 2022-01-20.1
+/// files:
+///   api/index.js: 5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03
+/// ```
+pub struct Bundle {
+    pub manifest: BundleManifest,
+    store: Arc<ArchiveStore>,
+}
+
+/// The manifest of a bundle.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BundleManifest {
+    /// The name of the workspace version the bundle holds.
+    pub version: String,
+    /// The hex-encoded sha-256 hash of every workspace file.
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+/// The error of a bundle that is malformed, tampered with or not signed by a trusted key.
+#[derive(Debug)]
+pub struct InvalidBundle {
+    pub reason: String,
+    /// The problems the validator found in the workspace files.
+    pub problems: Vec<Problem>,
+}
+
+impl Bundle {
+    /// The name of the manifest file.
+    pub const MANIFEST_FILENAME: &'static str = "bundle.yaml";
+
+    /// The name of the signature file.
+    pub const SIGNATURE_FILENAME: &'static str = "bundle.sig";
+
+    /// Unpacks a bundle in memory and checks its signature and file hashes.
+    ///
+    /// Fails with `InvalidBundle` if the bundle cannot be trusted.
+    pub fn try_from(bytes: Vec<u8>, config: &DeployConfig) -> Result<Self> {
+        let store = ArchiveStore::try_from(bytes, SystemTime::now())
+            .map_err(|err| InvalidBundle::new(format!("cannot unpack archive, {}", err)))?;
+
+        let manifest_bytes = Self::read_bundle_file(&store, Self::MANIFEST_FILENAME)?;
+        let signature = Self::read_bundle_file(&store, Self::SIGNATURE_FILENAME)?;
+
+        // SEC: Nothing in the manifest is trusted before its signature is checked.
+        Self::verify_signature(&manifest_bytes, &signature, &config.public_keys)?;
+
+        let manifest: BundleManifest = serde_yaml::from_slice(&manifest_bytes)
+            .map_err(|err| InvalidBundle::new(format!("cannot parse manifest, {}", err)))?;
+
+        let bundle = Self {
+            manifest,
+            store: Arc::new(store),
+        };
+
+        bundle.verify_files()?;
+
+        Ok(bundle)
+    }
+
+    /// Gets a read-only root manager over the workspace files of the bundle.
+    pub fn root_mgr(&self) -> RootManager {
+        RootManager::with_store(Arc::clone(&self.store) as Arc<dyn WorkspaceStore>)
+    }
+
+    /// Reads the workspace files of the bundle with their paths.
+    pub fn files(&self) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        self.manifest
+            .files
+            .keys()
+            .map(|path| Ok((path.clone(), self.store.read(path)?)))
+            .collect()
+    }
+
+    fn read_bundle_file(store: &ArchiveStore, filename: &str) -> Result<Vec<u8>> {
+        store
+            .read(Path::new(filename))
+            .map_err(|err| InvalidBundle::new(format!("cannot read {}, {}", filename, err)).into())
+    }
+
+    /// Checks that one of the trusted keys signed the manifest.
+    fn verify_signature(manifest: &[u8], signature: &[u8], public_keys: &[String]) -> Result<()> {
+        let signature = hex::decode(String::from_utf8_lossy(signature).trim())
+            .ok()
+            .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok())
+            .ok_or_else(|| {
+                InvalidBundle::new("signature is not a hex-encoded ed25519 signature")
+            })?;
+
+        let trusted = public_keys.iter().any(|public_key| {
+            hex::decode(public_key)
+                .ok()
+                .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+                .map(|public_key| public_key.verify(manifest, &signature).is_ok())
+                .unwrap_or(false)
+        });
+
+        if !trusted {
+            return Err(InvalidBundle::new("manifest is not signed by a trusted key").into());
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the archive holds exactly the files in the manifest and that their hashes match.
+    fn verify_files(&self) -> Result<()> {
+        let mut paths = vec![];
+        self.collect_files(Path::new(""), &mut paths)?;
+
+        for path in &paths {
+            if !self.manifest.files.contains_key(path) {
+                return Err(
+                    InvalidBundle::new(format!("{:?} is not in the manifest", path)).into(),
+                );
+            }
+        }
+
+        for (path, expected_hash) in &self.manifest.files {
+            let contents = self
+                .store
+                .read(path)
+                .map_err(|err| InvalidBundle::new(format!("cannot read {:?}, {}", path, err)))?;
+
+            let hash = hex::encode(Sha256::digest(&contents));
+            if !hash.eq_ignore_ascii_case(expected_hash) {
+                return Err(
+                    InvalidBundle::new(format!("hash of {:?} does not match", path)).into(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects the paths of the workspace files under a folder of the archive, leaving out the bundle files.
+    fn collect_files(&self, folder: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in self.store.list_dir(folder)? {
+            let path = folder.join(&entry.name);
+
+            if entry.is_dir {
+                self.collect_files(&path, paths)?;
+            } else if path != Path::new(Self::MANIFEST_FILENAME)
+                && path != Path::new(Self::SIGNATURE_FILENAME)
+            {
+                paths.push(path);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl InvalidBundle {
+    /// Creates a new invalid bundle error.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            problems: vec![],
+        }
+    }
+
+    /// Creates a `422 Unprocessable Entity` response.
+    pub fn as_hyper_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.to_string()));
+        *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+        response
+    }
+}
+
+impl fmt::Display for InvalidBundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid bundle, {}", self.reason)?;
+
+        for problem in &self.problems {
+            write!(f, "\n{}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for InvalidBundle {}
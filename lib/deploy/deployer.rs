@@ -0,0 +1,48 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::{Bundle, InvalidBundle, Validator};
+use crate::{config::DeployConfig, root::WorkspaceVersions};
+use log::info;
+use utilities::result::Result;
+
+/// Deploys bundles to workspaces on the local volume.
+pub struct Deployer;
+
+impl Deployer {
+    /// Checks a bundle, writes it as a new version of a workspace and switches the workspace to it.
+    ///
+    /// Versions beyond the configured number of previous ones are removed afterwards. Returns the deployed version.
+    /// Fails with `InvalidBundle` if the bundle cannot be trusted or its files do not validate.
+    ///
+    /// Blocks, and starts a V8 isolate to compile scripts, so it should run on a thread of its own.
+    pub fn deploy(
+        bytes: Vec<u8>,
+        root: &str,
+        workspace_id: &str,
+        config: &DeployConfig,
+    ) -> Result<String> {
+        let bundle = Bundle::try_from(bytes, config)?;
+
+        let problems = Validator::new().validate(&bundle.root_mgr())?;
+        if !problems.is_empty() {
+            return Err(InvalidBundle {
+                reason: format!("{} problems found in workspace files", problems.len()),
+                problems,
+            }
+            .into());
+        }
+
+        let version = &bundle.manifest.version;
+        let versions = WorkspaceVersions::open(root, workspace_id)?;
+
+        versions.create(version, bundle.files()?)?;
+        versions.prune(config.keep_versions)?;
+
+        info!(
+            r#"Deployed version {:?} of workspace {:?}"#,
+            version, workspace_id
+        );
+
+        Ok(version.clone())
+    }
+}
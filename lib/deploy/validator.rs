@@ -0,0 +1,586 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{
+    config::{ApiSettings, WorkspaceConfig},
+    permissions::DbPath,
+    root::RootManager,
+    runtimes::{ApiRuntime, RouteTable},
+};
+use deno_core::{v8, JsRuntime, RuntimeOptions};
+use regex::Regex;
+use serde::Serialize;
+use serde_yaml::Value;
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+use utilities::{config::ApiManifest, result::Result};
+
+/// Checks the files of a workspace before it is deployed, without running any of them.
+///
+/// Every `api.yaml` must parse, reference scripts that exist and declare well-formed permission paths. Every api must
+/// have an index module, and every script and module must compile. Middlewares and `auth.js` are compiled as scripts
+/// the way the api runtime runs them, every other `.js` file as a module.
+pub struct Validator {
+    runtime: JsRuntime,
+}
+
+/// A problem found in a workspace file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub path: PathBuf,
+    /// The line of the file the problem is on, starting at 1, if known.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Validator {
+    /// The name of the auth script at the workspace root.
+    pub const AUTH_SCRIPT: &'static str = "auth.js";
+
+    /// Creates a new validator.
+    ///
+    /// The validator holds a V8 isolate, so it must stay on the thread that created it.
+    pub fn new() -> Self {
+        Self {
+            runtime: JsRuntime::new(RuntimeOptions::default()),
+        }
+    }
+
+    /// Checks all files of a workspace and returns the problems found, if any.
+    pub fn validate(&mut self, root_mgr: &RootManager) -> Result<Vec<Problem>> {
+        let mut files = vec![];
+        Self::collect_files(root_mgr, Path::new(""), &mut files)?;
+
+        let existing: HashSet<PathBuf> = files.iter().cloned().collect();
+
+        let mut problems = vec![];
+        let mut scripts: HashSet<PathBuf> = HashSet::new();
+        scripts.insert(PathBuf::from(Self::AUTH_SCRIPT));
+
+        // Manifests first, since they tell which files are scripts.
+        for path in files.iter().filter(|path| path.ends_with("api.yaml")) {
+            let content = match root_mgr.read_file_from_workspace(path) {
+                Ok(content) => content,
+                Err(err) => {
+                    problems.push(Problem::new(path, err));
+                    continue;
+                }
+            };
+
+            // Syntax errors are reported on their own, since they are the only ones with a known line.
+            let value: Value = match serde_yaml::from_str(&content) {
+                Ok(value) => value,
+                Err(err) => {
+                    let line = err.location().map(|location| location.line());
+                    problems.push(Problem::new(path, err).on_line(line));
+                    continue;
+                }
+            };
+
+            let folder = path.parent().unwrap_or_else(|| Path::new(""));
+
+            match ApiManifest::try_from(&content) {
+                Ok(manifest) => {
+                    for middleware in manifest.middlewares.iter() {
+                        let script = Self::normalize(Path::new(&middleware.script));
+
+                        if !existing.contains(&script) {
+                            problems.push(
+                                Problem::new(
+                                    path,
+                                    format!(
+                                        r#"middleware script "{}" does not exist"#,
+                                        middleware.script
+                                    ),
+                                )
+                                .on_line(Self::find_line(&content, &middleware.script)),
+                            );
+                        }
+
+                        scripts.insert(script);
+                    }
+
+                    if manifest.authentication.enabled
+                        && !existing.contains(Path::new(Self::AUTH_SCRIPT))
+                    {
+                        problems.push(
+                            Problem::new(
+                                path,
+                                format!(
+                                    "authentication is enabled but {} does not exist at the workspace root",
+                                    Self::AUTH_SCRIPT
+                                ),
+                            )
+                            .on_line(Self::find_line(&content, "authentication")),
+                        );
+                    }
+                }
+                Err(err) => problems.push(Problem::new(path, err)),
+            }
+
+            match ApiSettings::try_from(&content) {
+                Ok(settings) => {
+                    if let Some(handler) = &settings.websocket.handler {
+                        if !existing.contains(&Self::normalize(&folder.join(handler))) {
+                            problems.push(
+                                Problem::new(
+                                    path,
+                                    format!(r#"websocket handler "{}" does not exist"#, handler),
+                                )
+                                .on_line(Self::find_line(&content, handler)),
+                            );
+                        }
+                    }
+                }
+                Err(err) => problems.push(Problem::new(path, err)),
+            }
+
+            let has_index = std::iter::once("index.js".to_string())
+                .chain(
+                    RouteTable::METHODS
+                        .iter()
+                        .map(|method| format!("index.{}.js", method)),
+                )
+                .any(|name| existing.contains(&folder.join(name)));
+
+            if !has_index {
+                problems.push(Problem::new(
+                    path,
+                    "api has no index.js or index.<method>.js next to it",
+                ));
+            }
+
+            Self::check_permissions(path, &content, &value, &mut problems);
+        }
+
+        if let Err(err) = WorkspaceConfig::load(root_mgr) {
+            problems.push(Problem::new(Path::new(WorkspaceConfig::FILENAME), err));
+        }
+
+        for path in files
+            .iter()
+            .filter(|path| path.extension() == Some("js".as_ref()))
+        {
+            let code = match root_mgr.read_file_from_workspace(path) {
+                Ok(code) => code,
+                Err(err) => {
+                    problems.push(Problem::new(path, err));
+                    continue;
+                }
+            };
+
+            let result = if scripts.contains(path) {
+                // The api runtime wraps scripts in a function that starts one line above the code.
+                self.compile(path, &ApiRuntime::format_code(&code), false)
+                    .map_err(|(line, message)| (line.map(|line| line.max(2) - 1), message))
+            } else {
+                self.compile(path, &code, true)
+            };
+
+            if let Err((line, message)) = result {
+                problems.push(Problem::new(path, message).on_line(line));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Checks the syntax of the fs and db paths an api manifest allows.
+    fn check_permissions(path: &Path, content: &str, value: &Value, problems: &mut Vec<Problem>) {
+        let permissions = &value["permissions"];
+
+        for operation in ["open", "create", "read", "write", "execute"] {
+            for glob in Self::string_list(&permissions["fs"][operation]) {
+                if let Some(message) = Self::check_fs_path(glob) {
+                    problems.push(
+                        Problem::new(
+                            path,
+                            format!(r#"fs.{} path "{}" {}"#, operation, glob, message),
+                        )
+                        .on_line(Self::find_line(content, glob)),
+                    );
+                }
+            }
+        }
+
+        if let Value::Mapping(db) = &permissions["db"] {
+            for (operation, globs) in db {
+                let operation = operation.as_str().unwrap_or_default();
+
+                for glob in Self::string_list(globs) {
+                    if let Some(message) = Self::check_db_path(glob) {
+                        problems.push(
+                            Problem::new(
+                                path,
+                                format!(r#"db.{} path "{}" {}"#, operation, glob, message),
+                            )
+                            .on_line(Self::find_line(content, glob)),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks a path of the fs permissions, returning what is wrong with it if anything.
+    fn check_fs_path(glob: &str) -> Option<String> {
+        if glob.is_empty() {
+            return Some("is empty".to_string());
+        }
+
+        if glob.contains("***") {
+            return Some("has more than two consecutive `*`".to_string());
+        }
+
+        if Path::new(glob)
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            return Some("must not leave the workspace with `..`".to_string());
+        }
+
+        None
+    }
+
+    /// Checks a path of the db permissions, returning what is wrong with it if anything.
+    ///
+    /// Db paths look like `/database[/table[/column]]`, where any segment can use `*` and `**` wildcards.
+    fn check_db_path(glob: &str) -> Option<String> {
+        if !glob.starts_with('/') {
+            return Some("must start with `/`".to_string());
+        }
+
+        let segments: Vec<&str> = glob[1..].split('/').collect();
+        if segments.len() > 3 || segments.iter().any(|segment| segment.is_empty()) {
+            return Some("must look like /database[/table[/column]]".to_string());
+        }
+
+        if glob.contains("***") {
+            return Some("has more than two consecutive `*`".to_string());
+        }
+
+        // The server separates the workspace namespace from the path with `$`.
+        if glob.contains('$') {
+            return Some("must not contain `$`".to_string());
+        }
+
+        if DbPath::db_name(glob).chars().count() > DbPath::MAX_DB_NAME_LEN {
+            return Some(format!(
+                "has a database name longer than {} characters",
+                DbPath::MAX_DB_NAME_LEN
+            ));
+        }
+
+        // The same pattern the db permission builds from the path.
+        let pattern = glob.replace("**", r".+").replace('*', r"[^/]+");
+        if Regex::new(&format!(r"^{}$", pattern)).is_err() {
+            return Some("is not a valid pattern".to_string());
+        }
+
+        None
+    }
+
+    /// Gets the strings of a yaml list, or nothing if the value is not a list.
+    fn string_list(value: &Value) -> impl Iterator<Item = &str> {
+        value
+            .as_sequence()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item.as_str())
+    }
+
+    /// Gets the line of the first occurrence of some text in a file, starting at 1.
+    fn find_line(content: &str, text: &str) -> Option<usize> {
+        content
+            .lines()
+            .position(|line| line.contains(text))
+            .map(|index| index + 1)
+    }
+
+    /// Removes the `.` components of a workspace path, the way reading the file would resolve them.
+    fn normalize(path: &Path) -> PathBuf {
+        path.components()
+            .filter(|component| *component != Component::CurDir)
+            .collect()
+    }
+
+    /// Compiles a script or module, returning the line and message of the compile error if it fails.
+    fn compile(
+        &mut self,
+        path: &Path,
+        code: &str,
+        is_module: bool,
+    ) -> std::result::Result<(), (Option<usize>, String)> {
+        let scope = &mut self.runtime.handle_scope();
+        let scope = &mut v8::TryCatch::new(scope);
+
+        let source = v8::String::new(scope, code)
+            .ok_or((None, "file is too large to compile".to_string()))?;
+        let name = v8::String::new(scope, &path.display().to_string()).unwrap();
+        let source_map_url = v8::undefined(scope);
+        let origin = v8::ScriptOrigin::new(
+            scope,
+            name.into(),
+            0,
+            0,
+            false,
+            0,
+            source_map_url.into(),
+            false,
+            false,
+            is_module,
+        );
+
+        let compiled = if is_module {
+            let source = v8::script_compiler::Source::new(source, Some(&origin));
+            v8::script_compiler::compile_module(scope, source).is_some()
+        } else {
+            v8::Script::compile(scope, source, Some(&origin)).is_some()
+        };
+
+        if compiled {
+            return Ok(());
+        }
+
+        Err(match scope.message() {
+            Some(message) => (
+                message.get_line_number(scope),
+                message.get(scope).to_rust_string_lossy(scope),
+            ),
+            None => (None, "failed to compile".to_string()),
+        })
+    }
+
+    /// Collects the paths of all files under a folder of the workspace.
+    fn collect_files(
+        root_mgr: &RootManager,
+        folder: &Path,
+        paths: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        for entry in root_mgr.list_dir(folder)? {
+            let path = folder.join(&entry.name);
+
+            if entry.is_dir {
+                Self::collect_files(root_mgr, &path, paths)?;
+            } else {
+                paths.push(path);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Problem {
+    /// Creates a new problem of a file.
+    pub fn new(path: &Path, message: impl fmt::Display) -> Self {
+        Self {
+            path: path.to_owned(),
+            line: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// Sets the line of the file the problem is on.
+    pub fn on_line(mut self, line: Option<usize>) -> Self {
+        self.line = line;
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::root::MemoryStore;
+    use std::sync::Arc;
+
+    const MANIFEST: &str = "authentication:\n  enabled: false\nmiddlewares: []\n";
+
+    fn validate(files: &[(&str, &str)]) -> Vec<Problem> {
+        let root_mgr = RootManager::with_store(Arc::new(MemoryStore::default()));
+
+        for (path, contents) in files {
+            root_mgr
+                .write_file_to_workspace(Path::new(path), contents.as_bytes())
+                .unwrap();
+        }
+
+        Validator::new().validate(&root_mgr).unwrap()
+    }
+
+    /// Gets the one problem found, failing if there are none or several.
+    fn single(problems: Vec<Problem>) -> Problem {
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        problems.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn valid_workspace_has_no_problems() {
+        let problems = validate(&[
+            ("api/users/api.yaml", MANIFEST),
+            ("api/users/index.js", "export default () => {};\n"),
+        ]);
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn yaml_syntax_error_is_reported_on_its_line() {
+        let problem = single(validate(&[
+            (
+                "api/users/api.yaml",
+                "authentication:\n  enabled: false\n middlewares: []\n",
+            ),
+            ("api/users/index.js", "export {};\n"),
+        ]));
+
+        assert_eq!(problem.path, Path::new("api/users/api.yaml"));
+        assert_eq!(problem.line, Some(3));
+    }
+
+    #[test]
+    fn missing_middleware_script_is_reported() {
+        let manifest =
+            "authentication:\n  enabled: false\nmiddlewares:\n  - script: middlewares/missing.js\n";
+        let problem = single(validate(&[
+            ("api/users/api.yaml", manifest),
+            ("api/users/index.js", "export {};\n"),
+        ]));
+
+        assert_eq!(problem.path, Path::new("api/users/api.yaml"));
+        assert_eq!(problem.line, Some(4));
+        assert!(problem.message.contains("middlewares/missing.js"));
+    }
+
+    #[test]
+    fn missing_index_is_reported() {
+        let problem = single(validate(&[
+            ("api/users/api.yaml", MANIFEST),
+            ("api/users/helpers.js", "export {};\n"),
+        ]));
+
+        assert_eq!(problem.path, Path::new("api/users/api.yaml"));
+        assert!(problem.message.contains("no index.js"));
+    }
+
+    #[test]
+    fn index_for_a_method_is_enough() {
+        let problems = validate(&[
+            ("api/users/api.yaml", MANIFEST),
+            ("api/users/index.get.js", "export {};\n"),
+        ]);
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn script_compile_error_is_reported_on_its_line() {
+        let manifest =
+            "authentication:\n  enabled: false\nmiddlewares:\n  - script: middlewares/check.js\n";
+        let problem = single(validate(&[
+            ("api/users/api.yaml", manifest),
+            ("api/users/index.js", "export {};\n"),
+            (
+                "middlewares/check.js",
+                "() => {\n  let allowed = true;\n  return allowed +;\n}\n",
+            ),
+        ]));
+
+        // The line is the one in the file, not in the code wrapped around it.
+        assert_eq!(problem.path, Path::new("middlewares/check.js"));
+        assert_eq!(problem.line, Some(3));
+    }
+
+    #[test]
+    fn module_compile_error_is_reported_on_its_line() {
+        let problem = single(validate(&[
+            ("api/users/api.yaml", MANIFEST),
+            ("api/users/index.js", "export {};\nexport const value = ;\n"),
+        ]));
+
+        assert_eq!(problem.path, Path::new("api/users/index.js"));
+        assert_eq!(problem.line, Some(2));
+    }
+
+    #[test]
+    fn permission_paths_are_reported_on_their_line() {
+        let manifest = format!(
+            "{}permissions:\n  http_event:\n    request_read: false\n    response_send: true\n  fs:\n    read:\n      - ../secrets/**\n  db:\n    read:\n      - /users$/table\n",
+            MANIFEST
+        );
+        let problems = validate(&[
+            ("api/users/api.yaml", &manifest),
+            ("api/users/index.js", "export {};\n"),
+        ]);
+
+        let lines: Vec<Option<usize>> = problems.iter().map(|problem| problem.line).collect();
+        assert_eq!(lines, vec![Some(10), Some(13)], "{:?}", problems);
+    }
+
+    #[test]
+    fn fs_paths_must_stay_in_the_workspace() {
+        for glob in ["..", "../secrets", "data/../../secrets", "data/**/../.."] {
+            assert!(Validator::check_fs_path(glob).is_some(), "{}", glob);
+        }
+
+        for glob in ["data/**", "data/*.json", "data/..json"] {
+            assert_eq!(Validator::check_fs_path(glob), None, "{}", glob);
+        }
+    }
+
+    #[test]
+    fn malformed_db_paths_are_rejected() {
+        for glob in [
+            "users",
+            "",
+            "/",
+            "/users//name",
+            "/users/",
+            "/a/b/c/d",
+            "/users/***",
+        ] {
+            assert!(Validator::check_db_path(glob).is_some(), "{}", glob);
+        }
+
+        for glob in ["/users", "/users/*", "/users/accounts/email", "/**"] {
+            assert_eq!(Validator::check_db_path(glob), None, "{}", glob);
+        }
+    }
+
+    #[test]
+    fn db_paths_with_namespace_separator_are_rejected() {
+        for glob in ["/users$", "/$/accounts", "/users/accounts$email"] {
+            let message = Validator::check_db_path(glob).unwrap();
+            assert!(message.contains('$'), "{}: {}", glob, message);
+        }
+    }
+
+    #[test]
+    fn long_database_names_are_rejected() {
+        let longest = "a".repeat(DbPath::MAX_DB_NAME_LEN);
+        let too_long = "a".repeat(DbPath::MAX_DB_NAME_LEN + 1);
+
+        assert_eq!(
+            Validator::check_db_path(&format!("/{}/accounts", longest)),
+            None
+        );
+        assert!(Validator::check_db_path(&format!("/{}/accounts", too_long)).is_some());
+    }
+}
@@ -1,7 +1,17 @@
 // Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
+mod console;
 mod db;
 mod p2p;
+mod sse;
+mod stream;
+mod trace;
+mod websocket;
 
+pub use console::*;
 pub use db::*;
 pub use p2p::*;
+pub use sse::*;
+pub use stream::*;
+pub use trace::*;
+pub use websocket::*;
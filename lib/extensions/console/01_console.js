@@ -0,0 +1,44 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+"use strict";
+
+((window) => {
+  const { core } = window.__bootstrap;
+
+  function stringify(value) {
+    if (typeof value === "string") {
+      return value;
+    }
+
+    if (value instanceof Error) {
+      return value.stack ?? String(value);
+    }
+
+    try {
+      const json = JSON.stringify(value);
+      return json === undefined ? String(value) : json;
+    } catch {
+      return String(value);
+    }
+  }
+
+  function write(level, args) {
+    core.opSync("opConsoleWrite", {
+      level,
+      message: args.map(stringify).join(" "),
+    });
+  }
+
+  // Replaces the methods of the console that print, so that output goes to the workspace log.
+  function capture(console) {
+    console.log = (...args) => write("info", args);
+    console.info = (...args) => write("info", args);
+    console.debug = (...args) => write("debug", args);
+    console.warn = (...args) => write("warn", args);
+    console.error = (...args) => write("error", args);
+  }
+
+  window.__bootstrap.console_capture = {
+    capture,
+  };
+})(globalThis);
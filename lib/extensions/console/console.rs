@@ -0,0 +1,167 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::ConsoleLogs;
+use log::{log, Level};
+use serde::Deserialize;
+use serde_json::json;
+use tera::{
+    errors::AnyError,
+    extensions::{op_sync, Extension, OpState},
+    include_js_files,
+};
+
+/// The console output of the scripts of a request, written to the log of its workspace.
+pub struct ConsoleSink {
+    logs: Arc<ConsoleLogs>,
+    workspace_id: String,
+    request_id: Option<String>,
+    script: RefCell<String>,
+    written: Cell<u64>,
+    dropped: Cell<bool>,
+}
+
+/// A message as written by scripts.
+#[derive(Debug, Deserialize)]
+struct ConsoleMessage {
+    level: ConsoleLevel,
+    message: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ConsoleLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// The log target of console output mirrored to the server log.
+pub const CONSOLE_LOG_TARGET: &str = "console";
+
+/// Sends the output of `console.log` and friends to `sink`.
+pub fn console(sink: Rc<ConsoleSink>) -> Extension {
+    let extension = Extension::builder()
+        .js(include_js_files!(
+            prefix "(runtime_server:extensions) ",
+            "lib/extensions/console/01_console.js",
+        ))
+        .ops(vec![("opConsoleWrite", op_sync(op_console_write))])
+        .state(move |state| {
+            if !state.has::<Rc<ConsoleSink>>() {
+                state.put(Rc::clone(&sink));
+            }
+
+            Ok(())
+        })
+        .build();
+
+    extension
+}
+
+impl ConsoleSink {
+    pub fn new(logs: Arc<ConsoleLogs>, workspace_id: &str, request_id: Option<String>) -> Self {
+        Self {
+            logs,
+            workspace_id: workspace_id.to_string(),
+            request_id,
+            script: RefCell::new(String::new()),
+            written: Cell::new(0),
+            dropped: Cell::new(false),
+        }
+    }
+
+    /// Sets the script that subsequent output is attributed to.
+    pub fn set_script(&self, script: &str) {
+        *self.script.borrow_mut() = script.to_string();
+    }
+
+    /// Writes a message, truncated to the maximum message size.
+    ///
+    /// Once the request has written its maximum number of bytes, a last line says so and everything after is dropped.
+    fn write(&self, level: ConsoleLevel, mut message: String) {
+        let config = &self.logs.config;
+
+        if self.dropped.get() {
+            return;
+        }
+
+        if message.len() > config.max_message_size {
+            let mut end = config.max_message_size;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            message.truncate(end);
+            message.push_str("...");
+        }
+
+        let written = self.written.get() + message.len() as u64;
+        self.written.set(written);
+
+        if written > config.max_request_size {
+            self.dropped.set(true);
+            message =
+                "console output limit reached, further output of the request is dropped".into();
+            self.emit(ConsoleLevel::Warn, &message);
+        } else {
+            self.emit(level, &message);
+        }
+    }
+
+    fn emit(&self, level: ConsoleLevel, message: &str) {
+        let script = self.script.borrow();
+
+        if self.logs.config.mirror {
+            log!(
+                target: CONSOLE_LOG_TARGET,
+                level.as_log_level(),
+                r#"workspace {:?} script {:?}: {}"#,
+                self.workspace_id,
+                script,
+                message
+            );
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+
+        let line = json!({
+            "time": time,
+            "level": level.as_log_level().as_str(),
+            "script": *script,
+            "request_id": self.request_id,
+            "message": message,
+        });
+
+        self.logs.write(&self.workspace_id, &line.to_string());
+    }
+}
+
+impl ConsoleLevel {
+    fn as_log_level(&self) -> Level {
+        match self {
+            ConsoleLevel::Debug => Level::Debug,
+            ConsoleLevel::Info => Level::Info,
+            ConsoleLevel::Warn => Level::Warn,
+            ConsoleLevel::Error => Level::Error,
+        }
+    }
+}
+
+/// Writes a console message of a script.
+fn op_console_write(state: &mut OpState, message: ConsoleMessage, _: ()) -> Result<(), AnyError> {
+    let sink = state.borrow::<Rc<ConsoleSink>>();
+    sink.write(message.level, message.message);
+
+    Ok(())
+}
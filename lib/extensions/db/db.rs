@@ -2,7 +2,7 @@
 
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use crate::permissions::Db;
+use crate::{permissions::Db, ScriptSpan, SpanKind};
 use tera::{
     errors::AnyError,
     extensions::{op_async, op_sync, Extension, OpState, Resource, ResourceId},
@@ -80,10 +80,23 @@ fn op_db_connect(_state: &mut OpState, _db_name: String, _: ()) -> Result<Resour
 }
 
 async fn op_db_query(
-    _state: Rc<RefCell<OpState>>,
+    state: Rc<RefCell<OpState>>,
     _rid: ResourceId,
-    _query: String,
+    query: String,
 ) -> Result<String, AnyError> {
+    let trace = state
+        .borrow()
+        .try_borrow::<Rc<ScriptSpan>>()
+        .map(|script_span| script_span.get())
+        .unwrap_or_default();
+
+    let mut span = trace.start_as("db.query", SpanKind::Client);
+
+    // SEC: Statements can hold literal values like passwords.
+    if trace.records_db_statements() {
+        span.set_attribute("db.statement", &query);
+    }
+
     // TODO(appcypher):
     // Parse SQL query to make sure we have permission to do any of it
     Ok(String::new())
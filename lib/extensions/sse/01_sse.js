@@ -0,0 +1,50 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+"use strict";
+
+((window) => {
+  const { core } = window.__bootstrap;
+
+  class EventStream {
+    #rid;
+
+    constructor(rid) {
+      this.#rid = rid;
+      this.onclose = null;
+
+      // Stays pending while the stream is open, which keeps the runtime alive.
+      this.closed = core.opAsync("opSseClosed", rid).then((reason) => {
+        if (typeof this.onclose === "function") {
+          this.onclose(reason);
+        }
+
+        return reason;
+      });
+    }
+
+    async send(event) {
+      const { event: name, data, id, retry } =
+        typeof event === "string" ? { data: event } : event;
+
+      return core.opAsync("opSseSend", this.#rid, {
+        event: name,
+        data: data === undefined ? "" : String(data),
+        id: id === undefined ? undefined : String(id),
+        retry,
+      });
+    }
+
+    close() {
+      core.opSync("opSseClose", this.#rid);
+    }
+  }
+
+  async function sseStart(headers = {}) {
+    const rid = await core.opAsync("opSseStart", Object.entries(headers));
+    return new EventStream(rid);
+  }
+
+  window.__bootstrap.sse = {
+    sseStart,
+  };
+})(globalThis);
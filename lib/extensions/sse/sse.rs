@@ -0,0 +1,293 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{borrow::Cow, cell::RefCell, rc::Rc, time::Duration};
+
+use crate::{config::SseConfig, extensions::ResponseSender};
+use serde::{Deserialize, Serialize};
+use tera::{
+    errors::AnyError,
+    extensions::{op_async, op_sync, Extension, OpState, Resource, ResourceId},
+    include_js_files,
+};
+use tokio::{
+    sync::{watch, Mutex},
+    time::{self, Instant},
+};
+use utilities::{
+    errors,
+    hyper::{
+        body::{self, Bytes},
+        header, Body, Response,
+    },
+    result::Context,
+};
+
+/// The response that event streams are sent through and their settings.
+struct SseState {
+    response_sender: Option<Rc<ResponseSender>>,
+    config: SseConfig,
+}
+
+/// An open event stream.
+struct EventStream {
+    body_tx: Mutex<Option<body::Sender>>,
+    closed_tx: watch::Sender<Option<CloseReason>>,
+    closed_rx: watch::Receiver<Option<CloseReason>>,
+}
+
+/// Why an event stream closed. Surfaced to scripts as a string.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CloseReason {
+    /// The client went away.
+    Disconnected,
+    /// The stream reached its maximum lifetime.
+    Expired,
+    /// The script closed the stream.
+    Closed,
+}
+
+/// An event as sent by scripts.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Event {
+    event: Option<String>,
+    data: String,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+/// Lets scripts push server-sent events to the client.
+///
+/// `response_sender` is `None` if the api is not permitted to send responses.
+pub fn sse(response_sender: Option<Rc<ResponseSender>>, config: SseConfig) -> Extension {
+    let sse_state = Rc::new(SseState {
+        response_sender,
+        config,
+    });
+
+    let extension = Extension::builder()
+        .js(include_js_files!(
+            prefix "(runtime_server:extensions) ",
+            "lib/extensions/sse/01_sse.js",
+        ))
+        .ops(vec![
+            ("opSseStart", op_async(op_sse_start)),
+            ("opSseSend", op_async(op_sse_send)),
+            ("opSseClosed", op_async(op_sse_closed)),
+            ("opSseClose", op_sync(op_sse_close)),
+        ])
+        .state(move |state| {
+            if !state.has::<Rc<SseState>>() {
+                state.put(Rc::clone(&sse_state));
+            }
+
+            Ok(())
+        })
+        .build();
+
+    extension
+}
+
+impl EventStream {
+    fn new(body_tx: body::Sender) -> Self {
+        let (closed_tx, closed_rx) = watch::channel(None);
+
+        Self {
+            body_tx: Mutex::new(Some(body_tx)),
+            closed_tx,
+            closed_rx,
+        }
+    }
+
+    /// Writes to the stream and closes it if the client is gone.
+    async fn write(&self, bytes: Bytes) -> Result<(), AnyError> {
+        let mut body_tx = self.body_tx.lock().await;
+
+        // A stream that got closed while this write waited for the lock is ended here.
+        if self.closed_rx.borrow().is_some() {
+            body_tx.take();
+            return errors::new_error_t("event stream is closed");
+        }
+
+        let result = match body_tx.as_mut() {
+            Some(sender) => sender.send_data(bytes).await,
+            None => return errors::new_error_t("event stream is closed"),
+        };
+
+        if result.is_err() {
+            body_tx.take();
+            self.close(CloseReason::Disconnected);
+            return errors::new_error_t("client disconnected from event stream");
+        }
+
+        Ok(())
+    }
+
+    /// Closes the stream. Only the first reason is kept.
+    fn close(&self, reason: CloseReason) {
+        if self.closed_rx.borrow().is_some() {
+            return;
+        }
+
+        let _ = self.closed_tx.send(Some(reason));
+
+        // Dropping the sender ends the response body. A pending write ends it instead if it holds the lock.
+        if let Ok(mut body_tx) = self.body_tx.try_lock() {
+            body_tx.take();
+        }
+    }
+
+    /// Waits for the stream to close.
+    async fn closed(&self) -> CloseReason {
+        let mut closed_rx = self.closed_rx.clone();
+
+        loop {
+            if let Some(reason) = *closed_rx.borrow() {
+                return reason;
+            }
+
+            if closed_rx.changed().await.is_err() {
+                return CloseReason::Closed;
+            }
+        }
+    }
+}
+
+impl Event {
+    /// Encodes the event in the `text/event-stream` format.
+    fn encode(&self) -> Result<Bytes, AnyError> {
+        let mut message = String::new();
+
+        for (field, value) in [("id", &self.id), ("event", &self.event)].iter() {
+            if let Some(value) = value {
+                // SEC: A line break would let scripts inject fields or whole events.
+                if value.contains(|c: char| c == '\n' || c == '\r') {
+                    return errors::new_error_t(format!(
+                        "event {} cannot contain line breaks: {:?}",
+                        field, value
+                    ));
+                }
+
+                message.push_str(&format!("{}: {}\n", field, value));
+            }
+        }
+
+        if let Some(retry) = self.retry {
+            message.push_str(&format!("retry: {}\n", retry));
+        }
+
+        // Every line of the data gets its own field.
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            message.push_str(&format!("data: {}\n", line));
+        }
+
+        message.push('\n');
+
+        Ok(message.into())
+    }
+}
+
+impl Resource for EventStream {
+    fn name(&self) -> Cow<str> {
+        "eventStream".into()
+    }
+}
+
+/// Sends keep-alive comments while the stream is open and closes it when it reaches its maximum lifetime.
+///
+/// A failed keep-alive is how a client that went away is detected.
+async fn keep_alive(stream: Rc<EventStream>, config: SseConfig) {
+    let deadline = Instant::now() + Duration::from_secs(config.max_lifetime);
+    let heartbeat_interval = Duration::from_secs(config.heartbeat_interval.max(1));
+
+    loop {
+        tokio::select! {
+            _ = time::sleep(heartbeat_interval) => {
+                if stream.write(Bytes::from_static(b": keep-alive\n\n")).await.is_err() {
+                    return;
+                }
+            }
+            _ = time::sleep_until(deadline) => {
+                stream.close(CloseReason::Expired);
+                return;
+            }
+            _ = stream.closed() => return,
+        }
+    }
+}
+
+/// Sends the event stream response and returns the resource events are sent to.
+async fn op_sse_start(
+    state: Rc<RefCell<OpState>>,
+    headers: Vec<(String, String)>,
+    _: (),
+) -> Result<ResourceId, AnyError> {
+    let sse_state = Rc::clone(state.borrow().borrow::<Rc<SseState>>());
+
+    let response_sender = match &sse_state.response_sender {
+        Some(response_sender) => Rc::clone(response_sender),
+        None => return errors::permission_error_t("permission to send a response not granted"),
+    };
+
+    let (body_tx, body) = Body::channel();
+
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache");
+
+    for (name, value) in headers.iter() {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+
+    let response = builder
+        .body(body)
+        .context("building event stream response")?;
+
+    response_sender.send(response).await?;
+
+    let (rid, stream) = {
+        let mut op_state = state.borrow_mut();
+        let rid = op_state.resource_table.add(EventStream::new(body_tx));
+        (rid, op_state.resource_table.get::<EventStream>(rid)?)
+    };
+
+    // Heartbeats and the lifetime limit run alongside the script.
+    tokio::task::spawn_local(keep_alive(stream, sse_state.config.clone()));
+
+    Ok(rid)
+}
+
+/// Sends an event.
+async fn op_sse_send(
+    state: Rc<RefCell<OpState>>,
+    rid: ResourceId,
+    event: Event,
+) -> Result<(), AnyError> {
+    let stream = state.borrow().resource_table.get::<EventStream>(rid)?;
+    let bytes = event.encode()?;
+
+    stream.write(bytes).await
+}
+
+/// Resolves with the close reason once the stream closes.
+///
+/// A pending call keeps the runtime alive, so scripts can keep pushing events for the stream's duration.
+async fn op_sse_closed(
+    state: Rc<RefCell<OpState>>,
+    rid: ResourceId,
+    _: (),
+) -> Result<CloseReason, AnyError> {
+    let stream = state.borrow().resource_table.get::<EventStream>(rid)?;
+
+    Ok(stream.closed().await)
+}
+
+/// Closes the stream.
+fn op_sse_close(state: &mut OpState, rid: ResourceId, _: ()) -> Result<(), AnyError> {
+    let stream = state.resource_table.take::<EventStream>(rid)?;
+    stream.close(CloseReason::Closed);
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+"use strict";
+
+((window) => {
+  const { core } = window.__bootstrap;
+
+  async function requestBodyRead() {
+    return core.opAsync("opRequestBodyRead");
+  }
+
+  async function* requestBodyChunks() {
+    while (true) {
+      const chunk = await requestBodyRead();
+      if (chunk === null) {
+        return;
+      }
+
+      yield chunk;
+    }
+  }
+
+  class ResponseStream {
+    #rid;
+
+    constructor(rid) {
+      this.#rid = rid;
+    }
+
+    async write(chunk) {
+      const bytes = typeof chunk === "string" ? core.encode(chunk) : chunk;
+      return core.opAsync("opResponseWrite", this.#rid, bytes);
+    }
+
+    end() {
+      core.opSync("opResponseEnd", this.#rid);
+    }
+  }
+
+  async function responseStart(status = 200, headers = {}) {
+    const rid = await core.opAsync(
+      "opResponseStart",
+      status,
+      Object.entries(headers),
+    );
+
+    return new ResponseStream(rid);
+  }
+
+  window.__bootstrap.httpStream = {
+    requestBodyRead,
+    requestBodyChunks,
+    responseStart,
+  };
+})(globalThis);
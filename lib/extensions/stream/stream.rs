@@ -0,0 +1,241 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use crate::{LimitedBody, PayloadTooLarge};
+use deno_core::ZeroCopyBuf;
+use tera::{
+    errors::AnyError,
+    extensions::{op_async, op_sync, Extension, OpState, Resource, ResourceId},
+    include_js_files,
+};
+use tokio::sync::{mpsc::Sender, Mutex};
+use utilities::{
+    errors,
+    hyper::{
+        body::{self, Bytes},
+        Body, Response,
+    },
+    result::Context,
+};
+
+/// Sends the one response of a request on behalf of the extensions that build responses natively.
+pub struct ResponseSender {
+    response_tx: Rc<Sender<Response<Body>>>,
+    sent: Cell<bool>,
+}
+
+/// The request body and response that scripts can stream through.
+struct StreamState {
+    request_body: Mutex<Option<LimitedBody>>,
+    can_read: bool,
+    response_sender: Option<Rc<ResponseSender>>,
+    response_tx: Rc<Sender<Response<Body>>>,
+}
+
+/// The sending half of a streamed response body.
+struct ResponseBodySender(Mutex<Option<body::Sender>>);
+
+/// Lets scripts consume the request body and produce the response body chunk by chunk.
+///
+/// `request_body` is `None` if the body has already been buffered for the http event, or if the api is not
+/// permitted to read it, in which case `can_read` is false.
+/// `response_sender` is `None` if the api is not permitted to send responses. `response_tx` is used to answer
+/// requests whose streamed body turns out to be too large.
+pub fn stream(
+    request_body: Option<LimitedBody>,
+    can_read: bool,
+    response_sender: Option<Rc<ResponseSender>>,
+    response_tx: Rc<Sender<Response<Body>>>,
+) -> Extension {
+    let stream_state = Rc::new(StreamState {
+        request_body: Mutex::new(request_body),
+        can_read,
+        response_sender,
+        response_tx,
+    });
+
+    let extension = Extension::builder()
+        .js(include_js_files!(
+            prefix "(runtime_server:extensions) ",
+            "lib/extensions/stream/01_stream.js",
+        ))
+        .ops(vec![
+            ("opRequestBodyRead", op_async(op_request_body_read)),
+            ("opResponseStart", op_async(op_response_start)),
+            ("opResponseWrite", op_async(op_response_write)),
+            ("opResponseEnd", op_sync(op_response_end)),
+        ])
+        .state(move |state| {
+            if !state.has::<Rc<StreamState>>() {
+                state.put(Rc::clone(&stream_state));
+            }
+
+            Ok(())
+        })
+        .build();
+
+    extension
+}
+
+impl ResponseSender {
+    /// Creates a new response sender.
+    pub fn new(response_tx: Rc<Sender<Response<Body>>>) -> Self {
+        Self {
+            response_tx,
+            sent: Cell::new(false),
+        }
+    }
+
+    /// Sends the response. Fails if a response has already been sent.
+    ///
+    /// The status and headers reach the client as soon as the driver receives the response, the body follows as it is produced.
+    pub async fn send(&self, response: Response<Body>) -> Result<(), AnyError> {
+        if self.sent.replace(true) {
+            return errors::new_error_t("response has already been started");
+        }
+
+        self.response_tx
+            .send(response)
+            .await
+            .map_err(|err| errors::new_error(format!("sending response: {}", err)))
+    }
+}
+
+impl StreamState {
+    /// Answers with a `413 Payload Too Large` response, unless a response has already been sent.
+    ///
+    /// The server only receives one response from the channel, so the send only succeeds while it is still empty and open.
+    fn reject(&self, too_large: &PayloadTooLarge) {
+        if self
+            .response_tx
+            .try_send(too_large.as_hyper_response())
+            .is_ok()
+        {
+            // Scripts that start a response afterwards get an error instead.
+            if let Some(response_sender) = &self.response_sender {
+                response_sender.sent.set(true);
+            }
+        }
+    }
+}
+
+impl Resource for ResponseBodySender {
+    fn name(&self) -> Cow<str> {
+        "responseBodySender".into()
+    }
+}
+
+/// Reads the next chunk of the request body. Resolves to `null` at the end of the body.
+async fn op_request_body_read(
+    state: Rc<RefCell<OpState>>,
+    _: (),
+    _: (),
+) -> Result<Option<ZeroCopyBuf>, AnyError> {
+    let stream_state = Rc::clone(state.borrow().borrow::<Rc<StreamState>>());
+
+    // SEC: Streamed bodies are subject to the same permission as buffered ones.
+    if !stream_state.can_read {
+        return errors::permission_error_t("permission to read the request body not granted");
+    }
+
+    let mut request_body = stream_state.request_body.lock().await;
+
+    let body = match request_body.as_mut() {
+        Some(body) => body,
+        None => {
+            return errors::new_error_t(
+                "request body is not streamed, enable `body.streaming` in the api manifest",
+            )
+        }
+    };
+
+    let chunk = match body.next_chunk().await {
+        Ok(chunk) => chunk,
+        Err(err) => {
+            if let Some(too_large) = err.downcast_ref::<PayloadTooLarge>() {
+                stream_state.reject(too_large);
+            }
+
+            return Err(err);
+        }
+    };
+
+    Ok(chunk.map(|chunk| chunk.to_vec().into()))
+}
+
+/// Sends the status and headers of the response and returns the resource the body is written to.
+async fn op_response_start(
+    state: Rc<RefCell<OpState>>,
+    status: u16,
+    headers: Vec<(String, String)>,
+) -> Result<ResourceId, AnyError> {
+    let stream_state = Rc::clone(state.borrow().borrow::<Rc<StreamState>>());
+
+    let response_sender = match &stream_state.response_sender {
+        Some(response_sender) => Rc::clone(response_sender),
+        None => return errors::permission_error_t("permission to send a response not granted"),
+    };
+
+    let (body_tx, body) = Body::channel();
+
+    let mut builder = Response::builder().status(status);
+    for (name, value) in headers.iter() {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+
+    let response = builder.body(body).context("building streamed response")?;
+
+    response_sender.send(response).await?;
+
+    let rid = state
+        .borrow_mut()
+        .resource_table
+        .add(ResponseBodySender(Mutex::new(Some(body_tx))));
+
+    Ok(rid)
+}
+
+/// Writes a chunk of the response body.
+///
+/// Resolves once the chunk has been handed to the connection, so a slow client slows the script down.
+async fn op_response_write(
+    state: Rc<RefCell<OpState>>,
+    rid: ResourceId,
+    chunk: ZeroCopyBuf,
+) -> Result<(), AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<ResponseBodySender>(rid)?;
+
+    let mut body_tx = resource.0.lock().await;
+
+    let body_tx = match body_tx.as_mut() {
+        Some(body_tx) => body_tx,
+        None => return errors::new_error_t("response body has already ended"),
+    };
+
+    body_tx
+        .send_data(Bytes::from(chunk.to_vec()))
+        .await
+        .context("writing response body, client may have disconnected")?;
+
+    Ok(())
+}
+
+/// Ends the response body.
+fn op_response_end(state: &mut OpState, rid: ResourceId, _: ()) -> Result<(), AnyError> {
+    let resource = state.resource_table.take::<ResponseBodySender>(rid)?;
+
+    // Dropping the sender ends the body. A pending write still holds the lock and ends it when done.
+    if let Ok(mut body_tx) = resource.0.try_lock() {
+        body_tx.take();
+    }
+
+    Ok(())
+}
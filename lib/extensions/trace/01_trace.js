@@ -0,0 +1,17 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+"use strict";
+
+((window) => {
+  const { core } = window.__bootstrap;
+
+  // Headers to add to outgoing requests so that they join the trace of the current request.
+  function traceHeaders() {
+    const traceparent = core.opSync("opTraceparent");
+    return traceparent === null ? {} : { traceparent };
+  }
+
+  window.__bootstrap.trace = {
+    traceHeaders,
+  };
+})(globalThis);
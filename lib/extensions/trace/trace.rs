@@ -0,0 +1,33 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::ScriptSpan;
+use std::rc::Rc;
+use tera::{
+    errors::AnyError,
+    extensions::{op_sync, Extension, OpState},
+    include_js_files,
+};
+
+/// Lets scripts get the trace context of the span they run in, to pass it on to the services they call.
+///
+/// `script_span` is put in the op state, so that ops start their spans as children of the running script.
+pub fn trace(script_span: Rc<ScriptSpan>) -> Extension {
+    let extension = Extension::builder()
+        .js(include_js_files!(
+            prefix "(runtime_server:extensions) ",
+            "lib/extensions/trace/01_trace.js",
+        ))
+        .ops(vec![("opTraceparent", op_sync(op_traceparent))])
+        .state(move |state| {
+            state.put(Rc::clone(&script_span));
+            Ok(())
+        })
+        .build();
+
+    extension
+}
+
+/// Gets the `traceparent` header value of the current span.
+fn op_traceparent(state: &mut OpState, _: (), _: ()) -> Result<Option<String>, AnyError> {
+    Ok(state.borrow::<Rc<ScriptSpan>>().get().traceparent())
+}
@@ -0,0 +1,5 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+mod websocket;
+
+pub use websocket::*;
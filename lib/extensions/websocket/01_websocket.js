@@ -0,0 +1,61 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+"use strict";
+
+((window) => {
+  const { core } = window.__bootstrap;
+
+  class WebSocketConnection {
+    constructor() {
+      this.onmessage = null;
+      this.onclose = null;
+
+      // Receives until the connection closes, which keeps the runtime alive.
+      this.closed = this.#receive();
+    }
+
+    async #receive() {
+      while (true) {
+        const event = await core.opAsync("opWsRecv");
+
+        if (event.kind === "close") {
+          if (typeof this.onclose === "function") {
+            this.onclose(event);
+          }
+
+          return event;
+        }
+
+        if (typeof this.onmessage === "function") {
+          await this.onmessage(event);
+        }
+      }
+    }
+
+    async send(data) {
+      if (typeof data === "string") {
+        return core.opAsync("opWsSendText", data);
+      }
+
+      return core.opAsync("opWsSendBinary", data);
+    }
+
+    async close(code = 1000, reason = "") {
+      return core.opAsync("opWsClose", code, reason);
+    }
+  }
+
+  let connection = null;
+
+  function websocketAccept() {
+    if (connection === null) {
+      connection = new WebSocketConnection();
+    }
+
+    return connection;
+  }
+
+  window.__bootstrap.websocket = {
+    websocketAccept,
+  };
+})(globalThis);
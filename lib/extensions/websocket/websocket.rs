@@ -0,0 +1,227 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use deno_core::ZeroCopyBuf;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use log::debug;
+use serde::Serialize;
+use tera::{
+    errors::AnyError,
+    extensions::{op_async, Extension, OpState},
+    include_js_files,
+};
+use tokio::{sync::Mutex, time};
+use tokio_tungstenite::{
+    tungstenite::{
+        error::Error as ProtocolError,
+        protocol::{frame::coding::CloseCode, CloseFrame, Message},
+    },
+    WebSocketStream,
+};
+use utilities::{errors, hyper::upgrade::Upgraded, result::Context};
+
+type Socket = WebSocketStream<Upgraded>;
+
+/// The websocket connection of a request.
+///
+/// It is created with the runtime and attached once the upgrade has been accepted.
+pub struct WebSocket {
+    sink: Mutex<Option<SplitSink<Socket, Message>>>,
+    stream: Mutex<Option<SplitStream<Socket>>>,
+    max_message_size: usize,
+    idle_timeout: Duration,
+}
+
+/// What scripts receive from the connection.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum WebSocketEvent {
+    Text { data: String },
+    Binary { data: ZeroCopyBuf },
+    Close { code: u16, reason: String },
+}
+
+/// Lets the websocket handler of an api receive and send messages.
+pub fn websocket(socket: Rc<WebSocket>) -> Extension {
+    let extension = Extension::builder()
+        .js(include_js_files!(
+            prefix "(runtime_server:extensions) ",
+            "lib/extensions/websocket/01_websocket.js",
+        ))
+        .ops(vec![
+            ("opWsRecv", op_async(op_ws_recv)),
+            ("opWsSendText", op_async(op_ws_send_text)),
+            ("opWsSendBinary", op_async(op_ws_send_binary)),
+            ("opWsClose", op_async(op_ws_close)),
+        ])
+        .state(move |state| {
+            if !state.has::<Rc<WebSocket>>() {
+                state.put(Rc::clone(&socket));
+            }
+
+            Ok(())
+        })
+        .build();
+
+    extension
+}
+
+impl WebSocket {
+    /// Creates a connection that is not attached to a socket yet.
+    pub fn new(max_message_size: usize, idle_timeout: Duration) -> Self {
+        Self {
+            sink: Mutex::new(None),
+            stream: Mutex::new(None),
+            max_message_size,
+            idle_timeout,
+        }
+    }
+
+    /// Attaches the upgraded socket.
+    pub async fn attach(&self, socket: Socket) {
+        let (sink, stream) = socket.split();
+
+        *self.sink.lock().await = Some(sink);
+        *self.stream.lock().await = Some(stream);
+    }
+
+    /// Waits for the next message from the client.
+    ///
+    /// Pings and pongs are handled by the protocol layer and never reach scripts.
+    async fn recv(&self) -> Result<WebSocketEvent, AnyError> {
+        let mut stream = self.stream.lock().await;
+
+        loop {
+            let next = match stream.as_mut() {
+                Some(stream) => time::timeout(self.idle_timeout, stream.next()).await,
+                None => return Ok(WebSocketEvent::close(1006, "")),
+            };
+
+            let event = match next {
+                Ok(Some(Ok(Message::Text(data)))) => return Ok(WebSocketEvent::Text { data }),
+                Ok(Some(Ok(Message::Binary(data)))) => {
+                    return Ok(WebSocketEvent::Binary { data: data.into() })
+                }
+                Ok(Some(Ok(Message::Close(frame)))) => match frame {
+                    Some(frame) => WebSocketEvent::close(frame.code.into(), &frame.reason),
+                    None => WebSocketEvent::close(1005, ""),
+                },
+                Ok(Some(Ok(_))) => continue,
+                Ok(Some(Err(ProtocolError::Capacity(err)))) => {
+                    debug!("Websocket message rejected = {}", err);
+                    self.close(1009, "message too big").await?;
+                    WebSocketEvent::close(1009, "message too big")
+                }
+                Ok(Some(Err(err))) => {
+                    debug!("Websocket connection failed = {}", err);
+                    WebSocketEvent::close(1006, "")
+                }
+                Ok(None) => WebSocketEvent::close(1006, ""),
+                // SEC: An idle connection would otherwise hold on to its runtime forever.
+                Err(_) => {
+                    self.close(1001, "idle timeout").await?;
+                    WebSocketEvent::close(1001, "idle timeout")
+                }
+            };
+
+            // Nothing can be received once the connection is closed.
+            stream.take();
+
+            return Ok(event);
+        }
+    }
+
+    /// Sends a message to the client.
+    async fn send(&self, message: Message) -> Result<(), AnyError> {
+        // Outgoing messages obey the same limit as incoming ones.
+        if message.len() > self.max_message_size {
+            return errors::new_error_t(format!(
+                "websocket message is larger than {} bytes",
+                self.max_message_size
+            ));
+        }
+
+        let mut sink = self.sink.lock().await;
+
+        match sink.as_mut() {
+            Some(sink) => sink
+                .send(message)
+                .await
+                .context("sending websocket message"),
+            None => errors::new_error_t("websocket connection is closed"),
+        }
+    }
+
+    /// Closes the connection if it is still open.
+    pub async fn close(&self, code: u16, reason: &str) -> Result<(), AnyError> {
+        let mut sink = match self.sink.lock().await.take() {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+
+        let frame = CloseFrame {
+            code: CloseCode::from(code),
+            reason: reason.to_owned().into(),
+        };
+
+        // The client may already be gone, in which case there is nobody to tell.
+        let _ = sink.send(Message::Close(Some(frame))).await;
+        let _ = sink.close().await;
+
+        Ok(())
+    }
+}
+
+impl WebSocketEvent {
+    fn close(code: u16, reason: &str) -> Self {
+        WebSocketEvent::Close {
+            code,
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+/// Resolves with the next message or with a close event once the connection closes.
+async fn op_ws_recv(state: Rc<RefCell<OpState>>, _: (), _: ()) -> Result<WebSocketEvent, AnyError> {
+    let socket = Rc::clone(state.borrow().borrow::<Rc<WebSocket>>());
+
+    socket.recv().await
+}
+
+/// Sends a text message.
+async fn op_ws_send_text(state: Rc<RefCell<OpState>>, data: String, _: ()) -> Result<(), AnyError> {
+    let socket = Rc::clone(state.borrow().borrow::<Rc<WebSocket>>());
+
+    socket.send(Message::Text(data)).await
+}
+
+/// Sends a binary message.
+async fn op_ws_send_binary(
+    state: Rc<RefCell<OpState>>,
+    data: ZeroCopyBuf,
+    _: (),
+) -> Result<(), AnyError> {
+    let socket = Rc::clone(state.borrow().borrow::<Rc<WebSocket>>());
+
+    socket.send(Message::Binary(data.to_vec())).await
+}
+
+/// Closes the connection with a close code and reason.
+async fn op_ws_close(
+    state: Rc<RefCell<OpState>>,
+    code: u16,
+    reason: String,
+) -> Result<(), AnyError> {
+    // SEC: Scripts can only use the normal closure code or the ones reserved for applications.
+    if code != 1000 && !(3000..=4999).contains(&code) {
+        return errors::new_error_t(format!("invalid websocket close code {}", code));
+    }
+
+    let socket = Rc::clone(state.borrow().borrow::<Rc<WebSocket>>());
+
+    socket.close(code, &reason).await
+}
@@ -0,0 +1,7 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+mod test_harness;
+mod test_workspace;
+
+pub use test_harness::*;
+pub use test_workspace::*;
@@ -0,0 +1,88 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::TestWorkspace;
+use crate::{
+    config::{RuntimeConfig, StoreKind},
+    RuntimeServer, ServerContext,
+};
+use std::sync::Arc;
+use utilities::{
+    config::GigamonoConfig,
+    http,
+    hyper::{self, body, Body, Method, Request, Response},
+    result::{Context, Result},
+    setup::CommonSetup,
+};
+
+/// Sends requests to the apis of a test workspace the way the server dispatches them, without a listener.
+///
+/// Handler errors come back as responses, decorated like on the server.
+pub struct TestHarness {
+    context: Arc<ServerContext>,
+    workspace: TestWorkspace,
+}
+
+impl TestHarness {
+    /// Creates a harness for a workspace with the default runtime config.
+    pub async fn new(workspace: TestWorkspace) -> Result<Self> {
+        Self::with_config(workspace, RuntimeConfig::default()).await
+    }
+
+    /// Creates a harness for a workspace with a runtime config of its own.
+    ///
+    /// The common setup is built from the default config rather than the config file of the environment, with the
+    /// volume root and store set so that requests reach the workspace.
+    pub async fn with_config(workspace: TestWorkspace, mut config: RuntimeConfig) -> Result<Self> {
+        let mut setup = CommonSetup {
+            config: GigamonoConfig::default(),
+        };
+        setup.config.volume.root = workspace.root().to_string_lossy().into_owned();
+        setup.config.volume.multi_workspace = true;
+
+        config.store = StoreKind::Local;
+
+        let context = Arc::new(ServerContext::new(Arc::new(setup), config)?);
+
+        Ok(Self { context, workspace })
+    }
+
+    pub fn context(&self) -> &Arc<ServerContext> {
+        &self.context
+    }
+
+    pub fn workspace(&self) -> &TestWorkspace {
+        &self.workspace
+    }
+
+    /// Starts a request to the workspace.
+    pub fn request(&self, method: Method, path: &str) -> hyper::http::request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header(http::WORKSPACE_ID_HEADER, TestWorkspace::ID)
+    }
+
+    /// Sends a `GET` request without a body.
+    pub async fn get(&self, path: &str) -> Result<Response<Body>> {
+        let request = self
+            .request(Method::GET, path)
+            .body(Body::empty())
+            .context("creating request")?;
+
+        self.send(request).await
+    }
+
+    /// Dispatches a request the way the server does and waits for its response.
+    pub async fn send(&self, request: Request<Body>) -> Result<Response<Body>> {
+        Ok(RuntimeServer::dispatch(request, Arc::clone(&self.context)).await)
+    }
+
+    /// Reads the whole body of a response as a string.
+    pub async fn body_string(response: Response<Body>) -> Result<String> {
+        let bytes = body::to_bytes(response.into_body())
+            .await
+            .context("reading response body")?;
+
+        String::from_utf8(bytes.to_vec()).context("decoding response body")
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use utilities::result::{Context, Result};
+
+/// A workspace in a volume root of its own under the temp folder, removed when dropped.
+pub struct TestWorkspace {
+    root: PathBuf,
+}
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl TestWorkspace {
+    /// The id of the workspace.
+    pub const ID: &'static str = "test";
+
+    /// Creates an empty workspace.
+    pub fn new() -> Result<Self> {
+        // Tests run in parallel, so every workspace gets a root of its own.
+        let root = env::temp_dir().join(format!(
+            "engine_runtime_harness_{}_{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(Self::ID))
+            .context(format!(r#"creating workspace folder under {:?}"#, root))?;
+
+        Ok(Self { root })
+    }
+
+    /// Creates a workspace with a copy of the files of a fixture folder.
+    pub fn from_fixtures(fixtures: impl AsRef<Path>) -> Result<Self> {
+        let workspace = Self::new()?;
+        Self::copy_dir(fixtures.as_ref(), &workspace.path())?;
+
+        Ok(workspace)
+    }
+
+    /// Writes a file of the workspace, creating its parent folders if they do not exist.
+    pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+        let path = self.path().join(path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!(r#"creating folder {:?}"#, parent))?;
+        }
+
+        fs::write(&path, contents).context(format!(r#"writing file {:?}"#, path))
+    }
+
+    /// The volume root that holds the workspace.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The folder of the workspace.
+    pub fn path(&self) -> PathBuf {
+        self.root.join(Self::ID)
+    }
+
+    fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+        fs::create_dir_all(to).context(format!(r#"creating folder {:?}"#, to))?;
+
+        for entry in fs::read_dir(from).context(format!(r#"reading fixtures {:?}"#, from))? {
+            let entry = entry.context(format!(r#"reading fixtures {:?}"#, from))?;
+            let path = entry.path();
+            let target = to.join(entry.file_name());
+
+            if path.is_dir() {
+                Self::copy_dir(&path, &target)?;
+            } else {
+                fs::copy(&path, &target).context(format!(r#"copying fixture {:?}"#, path))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for TestWorkspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -1,8 +1,15 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
+// Workspace files are only reached through `openat` and friends, and workspace versions are switched with symlinks.
+#[cfg(not(unix))]
+compile_error!("engine_runtime only supports unix platforms");
+
+pub mod config;
+pub mod deploy;
 pub mod root;
 pub mod runtimes;
 pub mod extensions;
+pub mod harness;
 pub mod permissions;        
 mod server;
 
@@ -83,12 +83,9 @@ impl PermissionType for Db {
                 let path_string = dir.downcast_ref::<DbPath>().unwrap().as_ref();
 
                 // Ensure db name part of the scheme is not larger than 48 characters.
-                let db_name = {
-                    let trimmed = path_string.trim_start_matches("/");
-                    trimmed.split_once("/").unwrap_or((trimmed, "")).0
-                };
+                let db_name = DbPath::db_name(path_string);
 
-                if db_name.chars().count() > 48 {
+                if db_name.chars().count() > DbPath::MAX_DB_NAME_LEN {
                     return errors::new_error_t(format!("database name is too long: {}", db_name));
                 }
 
@@ -153,6 +150,17 @@ impl PermissionType for Db {
     }
 }
 
+impl DbPath {
+    /// The longest database name allowed, in characters.
+    pub const MAX_DB_NAME_LEN: usize = 48;
+
+    /// Gets the database name part of a path.
+    pub fn db_name(path: &str) -> &str {
+        let trimmed = path.trim_start_matches('/');
+        trimmed.split_once('/').unwrap_or((trimmed, "")).0
+    }
+}
+
 impl Resource for DbPath {
     fn get_debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("DbPath")
@@ -0,0 +1,8 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+"use strict";
+
+((window) => {
+  // The console is set up by the runtime bootstrap, so it can only be captured afterwards.
+  window.__bootstrap.console_capture.capture(window.console);
+})(globalThis);
@@ -1,18 +1,60 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
+mod archive_store;
+mod local_store;
+mod memory_store;
+mod store;
+mod versions;
+mod workspace_dir;
+
+pub use archive_store::*;
+pub use local_store::*;
+pub use memory_store::*;
+pub use store::*;
+pub use versions::*;
+pub use workspace_dir::*;
+
+use crate::config::SymlinkPolicy;
 use std::{
-    fs,
+    error::Error,
+    fmt, io,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
 };
 use utilities::{
-    errors,
+    hyper::{Body, Response, StatusCode},
     result::{Context, Result},
 };
 
 /// Manages files in the workspace root.
+///
+/// Files are kept in a workspace store, which is a folder on the local volume unless configured otherwise.
 #[derive(Clone)]
 pub struct RootManager {
-    pub canon_workspace_path: PathBuf,
+    store: Arc<dyn WorkspaceStore>,
+}
+
+/// The error of a workspace id that does not name a workspace folder.
+#[derive(Debug)]
+pub struct InvalidWorkspaceId {
+    pub workspace_id: String,
+    pub reason: &'static str,
+}
+
+/// An entry of a workspace folder.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
+/// The metadata of a workspace file or folder.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
 }
 
 /// Common paths that are relative to the workspace root.
@@ -25,74 +67,176 @@ pub enum RootLevel {
 }
 
 impl RootManager {
-    /// Creates a new root manager.
-    pub fn new(root: &str, workspace_id: &str) -> Result<Self> {
-        let workspace_path: PathBuf = [root, workspace_id].iter().collect();
+    /// The maximum length of a workspace id, matching the workspace prefix of a [`DbPath`](crate::permissions::DbPath).
+    pub const MAX_WORKSPACE_ID_LEN: usize = 15;
 
-        let canon_workspace_path = fs::canonicalize(&workspace_path).context(format!(
-            r#"getting canonical workspace path from {:?}"#,
-            workspace_path
-        ))?;
+    /// Creates a new root manager for a workspace folder on the local volume, pinned to its current version if it is versioned.
+    ///
+    /// An empty workspace id refers to the root itself, for when workspaces are not multiplexed. Fails with
+    /// `InvalidWorkspaceId` if the id cannot name a folder directly under the root.
+    pub fn new(root: &str, workspace_id: &str, symlink_policy: SymlinkPolicy) -> Result<Self> {
+        Self::validate_workspace_id_or_single(workspace_id)?;
+
+        let store = LocalStore::open(root, workspace_id, symlink_policy)?;
+
+        Ok(Self::with_store(Arc::new(store)))
+    }
 
-        Ok(Self {
-            canon_workspace_path,
-        })
+    /// Creates a new root manager for the draft of a workspace folder on the local volume, where files are edited
+    /// before they become a version.
+    ///
+    /// Without versions, the draft is what requests see.
+    pub fn draft(root: &str, workspace_id: &str, symlink_policy: SymlinkPolicy) -> Result<Self> {
+        Self::validate_workspace_id_or_single(workspace_id)?;
+
+        let store = LocalStore::open_draft(root, workspace_id, symlink_policy)?;
+
+        Ok(Self::with_store(Arc::new(store)))
+    }
+
+    /// Creates a new root manager for a workspace in one of the stores.
+    ///
+    /// An empty workspace id refers to the root itself, for when workspaces are not multiplexed. Fails with
+    /// `InvalidWorkspaceId` if the id is not valid.
+    pub fn open(root: &str, workspace_id: &str, stores: &WorkspaceStores) -> Result<Self> {
+        Self::validate_workspace_id_or_single(workspace_id)?;
+
+        Ok(Self::with_store(stores.open(root, workspace_id)?))
+    }
+
+    /// Creates a new root manager for a workspace store.
+    pub fn with_store(store: Arc<dyn WorkspaceStore>) -> Self {
+        Self { store }
+    }
+
+    /// The folder that holds the workspace on the local volume, if its store has one.
+    pub fn local_path(&self) -> Option<&Path> {
+        self.store.local_path()
+    }
+
+    /// The workspace version the root manager is pinned to, if the workspace is versioned.
+    pub fn version(&self) -> Option<&str> {
+        self.store.version()
+    }
+
+    /// Checks that a workspace id is made of at most 15 ASCII letters, digits, `-` or `_`.
+    pub fn validate_workspace_id(workspace_id: &str) -> Result<()> {
+        let reason = if workspace_id.is_empty() {
+            "it is empty"
+        } else if workspace_id.len() > Self::MAX_WORKSPACE_ID_LEN {
+            "it is longer than 15 characters"
+        } else if !workspace_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            "it contains characters other than ASCII letters, digits, `-` and `_`"
+        } else {
+            return Ok(());
+        };
+
+        Err(InvalidWorkspaceId {
+            workspace_id: workspace_id.to_string(),
+            reason,
+        }
+        .into())
+    }
+
+    /// Checks a workspace id, letting through the empty id of the single workspace at the root.
+    ///
+    /// Only callers that do not multiplex workspaces may pass the empty id. Ids that come from clients of multiplexed
+    /// workspaces must go through `validate_workspace_id`, since the empty id refers to the whole volume root.
+    fn validate_workspace_id_or_single(workspace_id: &str) -> Result<()> {
+        // SEC: Workspace ids come from clients, so anything like `..` or an absolute path is rejected up front.
+        if workspace_id.is_empty() {
+            return Ok(());
+        }
+
+        Self::validate_workspace_id(workspace_id)
     }
 
     /// Reads file from a path realative to `level`.
     ///
     /// Does not want specified path to be preceded by a path separator.
     pub fn read_file_from(&self, path: &Path, level: RootLevel) -> Result<String> {
-        // Join paths.
-        let path: PathBuf = [
-            &self.canon_workspace_path,
-            &level.get_path(),
-            &PathBuf::from(path),
-        ]
-        .iter()
-        .collect();
-
-        self.read_file(&path)
+        self.read_file_from_workspace(&level.get_path().join(path))
     }
 
     /// Reads file from a path relative to the workspace root.
     ///
     /// Does not want specified path to be preceded by a path separator.
     pub fn read_file_from_workspace(&self, path: &Path) -> Result<String> {
-        // Join paths.
-        let path: PathBuf = [&self.canon_workspace_path, &PathBuf::from(path)]
-            .iter()
-            .collect();
+        let content = self.read_bytes_from_workspace(path)?;
+
+        String::from_utf8(content).context(format!(r#"attempt to read file {:?}"#, path))
+    }
+
+    /// Reads the raw bytes of a file from a path relative to the workspace root.
+    pub fn read_bytes_from_workspace(&self, path: &Path) -> Result<Vec<u8>> {
+        self.store
+            .read(path)
+            .context(format!(r#"attempt to read file {:?}"#, path))
+    }
+
+    /// Writes file to a path relative to `level`, creating missing parent folders.
+    ///
+    /// Does not want specified path to be preceded by a path separator.
+    pub fn write_file_to(&self, path: &Path, level: RootLevel, contents: &[u8]) -> Result<()> {
+        self.write_file_to_workspace(&level.get_path().join(path), contents)
+    }
+
+    /// Writes file to a path relative to the workspace root, creating missing parent folders.
+    ///
+    /// On the local volume, the file is replaced atomically, so readers see either the old or the new contents.
+    pub fn write_file_to_workspace(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.store
+            .write(path, contents)
+            .context(format!(r#"attempt to write file {:?}"#, path))
+    }
 
-        self.read_file(&path)
+    /// Creates a folder and its missing parents at a path relative to the workspace root.
+    pub fn create_dir(&self, path: &Path) -> Result<()> {
+        self.store
+            .create_dir(path)
+            .context(format!(r#"attempt to create folder {:?}"#, path))
     }
 
-    /// Reads file from a path.
+    /// Lists the entries of a folder relative to the workspace root, sorted by name.
+    pub fn list_dir(&self, path: &Path) -> Result<Vec<DirEntry>> {
+        let mut entries = self
+            .store
+            .list_dir(path)
+            .context(format!(r#"attempt to list folder {:?}"#, path))?;
+
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(entries)
+    }
+
+    /// Gets the metadata of a file or folder relative to the workspace root.
+    pub fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.store
+            .metadata(path)
+            .context(format!(r#"attempt to get metadata of {:?}"#, path))
+    }
+
+    /// Checks whether a file or folder exists at a path relative to the workspace root.
     ///
-    /// Expects an absolute path.
-    fn read_file(&self, full_path: &Path) -> Result<String> {
-        // Validate path.
-        let canon_path = self.validate_path(full_path)?;
-
-        // Read file.
-        fs::read_to_string(&canon_path).context(format!(r#"attempt to read file {:?}"#, canon_path))
-    }
-
-    /// Checks that specified path is still within the workspace root.
-    fn validate_path(&self, path: &Path) -> Result<PathBuf> {
-        // SEC: Canonicalize path.
-        let canon_path =
-            fs::canonicalize(path).context(format!(r#"getting canonical path from {:?}"#, path))?;
-
-        // SEC: Making sure workspace paths is still base.
-        if !canon_path.starts_with(&self.canon_workspace_path) {
-            return errors::new_error_t(format!(
-                r#"path {:?} must be a under of workspace path {:?}"#,
-                path, self.canon_workspace_path,
-            ));
+    /// Only a missing path counts as absent. Other errors, like a denied symlink, are returned.
+    pub fn exists(&self, path: &Path) -> Result<bool> {
+        match self.store.metadata(path) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err).context(format!(r#"attempt to get metadata of {:?}"#, path)),
         }
+    }
 
-        Ok(canon_path)
+    /// Removes a file or a folder with its contents at a path relative to the workspace root.
+    ///
+    /// A symlink is removed itself, never what it points to. The workspace root itself cannot be removed.
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        self.store
+            .remove(path)
+            .context(format!(r#"attempt to remove {:?}"#, path))
     }
 }
 
@@ -108,3 +252,24 @@ impl RootLevel {
         }
     }
 }
+
+impl InvalidWorkspaceId {
+    /// Creates a `400 Bad Request` response.
+    pub fn as_hyper_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.to_string()));
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        response
+    }
+}
+
+impl fmt::Display for InvalidWorkspaceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid workspace id {:?}, {}",
+            self.workspace_id, self.reason
+        )
+    }
+}
+
+impl Error for InvalidWorkspaceId {}
@@ -0,0 +1,211 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::{DirEntry, MemoryStore, Metadata, WorkspaceStore};
+use flate2::read::GzDecoder;
+use std::{
+    fs,
+    io::{self, Cursor, Read},
+    path::Path,
+    time::SystemTime,
+};
+use tar::EntryType;
+use zip::ZipArchive;
+
+/// A read-only workspace unpacked into memory from a tar, gzipped tar or zip archive.
+///
+/// Every file takes the modified time of the archive. Archives with symlinks or other special files are rejected.
+pub struct ArchiveStore {
+    files: MemoryStore,
+}
+
+impl ArchiveStore {
+    /// The maximum size of an archive file in bytes.
+    pub const MAX_ARCHIVE_SIZE: u64 = 512 * 1024 * 1024;
+
+    /// The maximum size of all unpacked files of an archive in bytes.
+    pub const MAX_TOTAL_SIZE: u64 = 256 * 1024 * 1024;
+
+    /// The maximum size of an unpacked file in bytes.
+    pub const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+    /// The maximum number of files and folders in an archive.
+    pub const MAX_ENTRIES: usize = 100_000;
+
+    /// Unpacks the archive at a path, telling its format by its first bytes.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        if metadata.len() > Self::MAX_ARCHIVE_SIZE {
+            return Err(too_large_error(format!(
+                "archive {:?} is larger than {} bytes",
+                path,
+                Self::MAX_ARCHIVE_SIZE
+            )));
+        }
+
+        let bytes = fs::read(path)?;
+
+        Self::try_from(bytes, metadata.modified()?)
+    }
+
+    /// Unpacks an archive whose files were last modified at a time.
+    ///
+    /// SEC: Archives are small when compressed and can be huge unpacked, so sizes are counted as entries are read
+    /// rather than taken from their headers.
+    pub fn try_from(bytes: Vec<u8>, modified: SystemTime) -> io::Result<Self> {
+        let files = MemoryStore::default();
+
+        if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+            Self::unpack_zip(&files, bytes, modified)?;
+        } else if bytes.starts_with(&[0x1f, 0x8b]) {
+            Self::unpack_tar(&files, GzDecoder::new(Cursor::new(bytes)), modified)?;
+        } else {
+            Self::unpack_tar(&files, Cursor::new(bytes), modified)?;
+        }
+
+        Ok(Self { files })
+    }
+
+    fn unpack_tar(files: &MemoryStore, reader: impl Read, modified: SystemTime) -> io::Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        let mut limits = UnpackLimits::default();
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+
+            limits.count_entry()?;
+
+            match entry.header().entry_type() {
+                EntryType::Directory => files.create_dir(&path)?,
+                EntryType::Regular | EntryType::Continuous => {
+                    let contents = limits.read_entry(entry, &path)?;
+                    files.insert(&path, contents, modified)?;
+                }
+                // Metadata entries that are not files of their own.
+                EntryType::XHeader | EntryType::XGlobalHeader | EntryType::GNULongName => (),
+                _ => return Err(unsupported_entry_error(&path)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unpack_zip(files: &MemoryStore, bytes: Vec<u8>, modified: SystemTime) -> io::Result<()> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+        let mut limits = UnpackLimits::default();
+
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index)?;
+            let path = Path::new(entry.name()).to_owned();
+
+            limits.count_entry()?;
+
+            // Zip entries made on unix keep their file type in the upper bits of the mode.
+            let file_type = entry.unix_mode().map(|mode| mode & 0o170000);
+
+            if entry.is_dir() {
+                files.create_dir(&path)?;
+            } else if file_type.is_none() || file_type == Some(0o100000) {
+                let contents = limits.read_entry(entry, &path)?;
+                files.insert(&path, contents, modified)?;
+            } else {
+                return Err(unsupported_entry_error(&path));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What has been unpacked of an archive so far.
+#[derive(Default)]
+struct UnpackLimits {
+    entries: usize,
+    total_size: u64,
+}
+
+impl UnpackLimits {
+    fn count_entry(&mut self) -> io::Result<()> {
+        self.entries += 1;
+
+        if self.entries > ArchiveStore::MAX_ENTRIES {
+            return Err(too_large_error(format!(
+                "archive has more than {} entries",
+                ArchiveStore::MAX_ENTRIES
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the contents of an entry, failing as soon as the entry or the archive gets too large.
+    fn read_entry(&mut self, entry: impl Read, path: &Path) -> io::Result<Vec<u8>> {
+        let mut contents = vec![];
+        entry
+            .take(ArchiveStore::MAX_ENTRY_SIZE + 1)
+            .read_to_end(&mut contents)?;
+
+        if contents.len() as u64 > ArchiveStore::MAX_ENTRY_SIZE {
+            return Err(too_large_error(format!(
+                "archive entry {:?} is larger than {} bytes",
+                path,
+                ArchiveStore::MAX_ENTRY_SIZE
+            )));
+        }
+
+        self.total_size += contents.len() as u64;
+
+        if self.total_size > ArchiveStore::MAX_TOTAL_SIZE {
+            return Err(too_large_error(format!(
+                "archive is larger than {} bytes unpacked",
+                ArchiveStore::MAX_TOTAL_SIZE
+            )));
+        }
+
+        Ok(contents)
+    }
+}
+
+impl WorkspaceStore for ArchiveStore {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.read(path)
+    }
+
+    fn write(&self, _: &Path, _: &[u8]) -> io::Result<()> {
+        Err(read_only_error())
+    }
+
+    fn create_dir(&self, _: &Path) -> io::Result<()> {
+        Err(read_only_error())
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.files.list_dir(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.files.metadata(path)
+    }
+
+    fn remove(&self, _: &Path) -> io::Result<()> {
+        Err(read_only_error())
+    }
+}
+
+fn read_only_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "workspace archives are read-only",
+    )
+}
+
+fn unsupported_entry_error(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("archive entry {:?} is neither a file nor a folder", path),
+    )
+}
+
+fn too_large_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,171 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::{
+    DirEntry, InvalidWorkspaceId, Metadata, VersionPin, WorkspaceDir, WorkspaceStore,
+    WorkspaceVersions,
+};
+use crate::config::SymlinkPolicy;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use utilities::result::{Context, Result};
+
+/// A workspace stored in a folder on the local volume.
+///
+/// A versioned workspace is opened at its current version, which stays pinned for the lifetime of the store and cannot be modified.
+pub struct LocalStore {
+    canon_path: PathBuf,
+    dir: WorkspaceDir,
+    version: Option<String>,
+    _pin: Option<VersionPin>,
+}
+
+impl LocalStore {
+    /// Opens a workspace under the root, at its current version if it is versioned.
+    ///
+    /// Does not validate the workspace id. An empty workspace id refers to the root itself.
+    pub fn open(root: &str, workspace_id: &str, symlink_policy: SymlinkPolicy) -> Result<Self> {
+        let canon_workspace_path = Self::canonicalize(root, workspace_id)?;
+
+        // Later switches do not affect an opened store, since it holds a descriptor of the version folder.
+        // The pin keeps pruning from removing the version's files while the store is open.
+        match WorkspaceVersions::pin_current(&canon_workspace_path)? {
+            Some((canon_version_path, pin)) => {
+                let version = canon_version_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+
+                let mut store = Self::open_at(canon_version_path, version, symlink_policy)?;
+                store._pin = Some(pin);
+
+                Ok(store)
+            }
+            None => Self::open_at(canon_workspace_path, None, symlink_policy),
+        }
+    }
+
+    /// Opens the folder of a workspace under the root itself, where files are edited before they become a version.
+    ///
+    /// Does not validate the workspace id. An empty workspace id refers to the root itself.
+    pub fn open_draft(
+        root: &str,
+        workspace_id: &str,
+        symlink_policy: SymlinkPolicy,
+    ) -> Result<Self> {
+        Self::open_at(
+            Self::canonicalize(root, workspace_id)?,
+            None,
+            symlink_policy,
+        )
+    }
+
+    fn open_at(
+        canon_path: PathBuf,
+        version: Option<String>,
+        symlink_policy: SymlinkPolicy,
+    ) -> Result<Self> {
+        let dir = WorkspaceDir::open(&canon_path, symlink_policy)
+            .context(format!(r#"opening workspace folder {:?}"#, canon_path))?;
+
+        Ok(Self {
+            canon_path,
+            dir,
+            version,
+            _pin: None,
+        })
+    }
+
+    /// Gets the canonical path of a workspace folder.
+    fn canonicalize(root: &str, workspace_id: &str) -> Result<PathBuf> {
+        let workspace_path: PathBuf = [root, workspace_id].iter().collect();
+
+        let canon_path = fs::canonicalize(&workspace_path).context(format!(
+            r#"getting canonical workspace path from {:?}"#,
+            workspace_path
+        ))?;
+
+        // SEC: Making sure the workspace is a direct child of the root, even if it is a symlink.
+        if !workspace_id.is_empty() {
+            let canon_root = fs::canonicalize(root)
+                .context(format!(r#"getting canonical root path from {:?}"#, root))?;
+
+            if canon_path.parent() != Some(canon_root.as_path()) {
+                return Err(InvalidWorkspaceId {
+                    workspace_id: workspace_id.to_string(),
+                    reason: "it does not resolve to a folder directly under the root",
+                }
+                .into());
+            }
+        }
+
+        Ok(canon_path)
+    }
+
+    /// Fails if the store is a version, which must not change once created.
+    fn check_writable(&self) -> io::Result<()> {
+        match &self.version {
+            Some(version) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("version {:?} of the workspace is immutable", version),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl WorkspaceStore for LocalStore {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut contents = vec![];
+
+        // SEC: Resolved relative to the workspace descriptor, so no symlink can be swapped in before the read.
+        io::Read::read_to_end(&mut self.dir.open_file(path)?, &mut contents)?;
+
+        Ok(contents)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.check_writable()?;
+        self.dir.write_atomic(path, contents)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.check_writable()?;
+        self.dir.create_dir_all(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        Ok(self
+            .dir
+            .list_dir(path)?
+            .into_iter()
+            .map(|(name, is_dir)| DirEntry {
+                name: name.to_string_lossy().into_owned(),
+                is_dir,
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = self.dir.metadata(path)?;
+
+        Ok(Metadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.check_writable()?;
+        self.dir.remove(path)
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.canon_path)
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::{normalize, DirEntry, Metadata, WorkspaceStore};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+
+/// A workspace whose files only live in memory.
+///
+/// Meant for tests. Paths are resolved lexically and the workspace root always exists.
+#[derive(Default)]
+pub struct MemoryStore {
+    nodes: RwLock<BTreeMap<PathBuf, Node>>,
+}
+
+enum Node {
+    File {
+        contents: Vec<u8>,
+        modified: SystemTime,
+    },
+    Dir,
+}
+
+impl MemoryStore {
+    /// Adds a file with a modified time, creating its missing parent folders.
+    pub(super) fn insert(
+        &self,
+        path: &Path,
+        contents: Vec<u8>,
+        modified: SystemTime,
+    ) -> io::Result<()> {
+        let path = normalize(path)?;
+        let mut nodes = self.nodes.write().unwrap();
+
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => return Err(is_dir_error(&path)),
+        };
+
+        Self::insert_dirs(&mut nodes, parent)?;
+
+        if let Some(Node::Dir) = nodes.get(&path) {
+            return Err(is_dir_error(&path));
+        }
+
+        nodes.insert(path, Node::File { contents, modified });
+
+        Ok(())
+    }
+
+    /// Adds a folder and its missing parents.
+    fn insert_dirs(nodes: &mut BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            match nodes.get(dir) {
+                Some(Node::File { .. }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{:?} is a file", dir),
+                    ))
+                }
+                Some(Node::Dir) => (),
+                None => {
+                    nodes.insert(dir.to_owned(), Node::Dir);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl WorkspaceStore for MemoryStore {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = normalize(path)?;
+
+        match self.nodes.read().unwrap().get(&path) {
+            Some(Node::File { contents, .. }) => Ok(contents.clone()),
+            Some(Node::Dir) => Err(is_dir_error(&path)),
+            None if path.as_os_str().is_empty() => Err(is_dir_error(&path)),
+            None => Err(not_found_error(&path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.insert(path, contents.to_vec(), SystemTime::now())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path)?;
+
+        Self::insert_dirs(&mut self.nodes.write().unwrap(), &path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let path = normalize(path)?;
+
+        if !self.metadata(&path)?.is_dir {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{:?} is not a folder", path),
+            ));
+        }
+
+        Ok(self
+            .nodes
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(entry_path, _)| entry_path.parent() == Some(path.as_path()))
+            .map(|(entry_path, node)| DirEntry {
+                name: entry_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                is_dir: matches!(node, Node::Dir),
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = normalize(path)?;
+
+        match self.nodes.read().unwrap().get(&path) {
+            Some(Node::File { contents, modified }) => Ok(Metadata {
+                is_dir: false,
+                len: contents.len() as u64,
+                modified: Some(*modified),
+            }),
+            Some(Node::Dir) => Ok(Metadata {
+                is_dir: true,
+                len: 0,
+                modified: None,
+            }),
+            None if path.as_os_str().is_empty() => Ok(Metadata {
+                is_dir: true,
+                len: 0,
+                modified: None,
+            }),
+            None => Err(not_found_error(&path)),
+        }
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path)?;
+
+        if path.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the workspace root cannot be removed",
+            ));
+        }
+
+        let mut nodes = self.nodes.write().unwrap();
+
+        if nodes.remove(&path).is_none() {
+            return Err(not_found_error(&path));
+        }
+
+        nodes.retain(|entry_path, _| !entry_path.starts_with(&path));
+
+        Ok(())
+    }
+}
+
+fn is_dir_error(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?} is a folder", path))
+}
+
+fn not_found_error(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{:?} does not exist", path),
+    )
+}
@@ -0,0 +1,257 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::{ArchiveStore, DirEntry, LocalStore, MemoryStore, Metadata, RootManager};
+use crate::config::{StoreKind, SymlinkPolicy};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use utilities::{
+    errors,
+    result::{Context, Result},
+};
+
+/// Storage of the files of a single workspace.
+///
+/// Paths are relative to the workspace root and must not be preceded by a path separator.
+pub trait WorkspaceStore: Send + Sync {
+    /// Reads the whole content of a file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Replaces the content of a file, creating it and its missing parent folders if needed.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Creates a folder and its missing parents.
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Lists the entries of a folder in no particular order.
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    /// Gets the metadata of a file or folder.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Removes a file or a folder with its contents.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// The folder that holds the workspace on the local volume, if the store has one.
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// The workspace version the store is pinned to, if the workspace is versioned.
+    fn version(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Opens the workspace stores of the configured kind.
+///
+/// Memory stores live as long as this and archives are only unpacked again when they change.
+pub struct WorkspaceStores {
+    kind: StoreKind,
+    symlink_policy: SymlinkPolicy,
+    memory: Mutex<HashMap<String, Arc<MemoryStore>>>,
+    archives: Mutex<HashMap<PathBuf, (SystemTime, Arc<ArchiveStore>)>>,
+}
+
+impl WorkspaceStores {
+    /// The file extensions of workspace archives, in the order they are looked up.
+    pub const ARCHIVE_EXTENSIONS: [&'static str; 4] = ["tar", "tar.gz", "tgz", "zip"];
+
+    /// Creates stores of a kind.
+    pub fn new(kind: StoreKind, symlink_policy: SymlinkPolicy) -> Self {
+        Self {
+            kind,
+            symlink_policy,
+            memory: Mutex::new(HashMap::new()),
+            archives: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Opens the store of a workspace under the root.
+    ///
+    /// Does not validate the workspace id. An empty workspace id refers to the root itself.
+    pub fn open(&self, root: &str, workspace_id: &str) -> Result<Arc<dyn WorkspaceStore>> {
+        Ok(match self.kind {
+            StoreKind::Local => {
+                Arc::new(LocalStore::open(root, workspace_id, self.symlink_policy)?)
+            }
+            StoreKind::Memory => self.memory(workspace_id)?,
+            StoreKind::Archive => self.archive(root, workspace_id)?,
+        })
+    }
+
+    /// Gets the in-memory store of a workspace. Fails if the workspace has not been created.
+    pub fn memory(&self, workspace_id: &str) -> Result<Arc<MemoryStore>> {
+        // SEC: Workspace ids come from clients, so opening a store must never create one.
+        match self.memory.lock().unwrap().get(workspace_id) {
+            Some(store) => Ok(Arc::clone(store)),
+            None => errors::new_error_t(format!(
+                r#"workspace {:?} does not exist in memory"#,
+                workspace_id
+            )),
+        }
+    }
+
+    /// Creates an empty in-memory store for a workspace, or gets its store if it already has one.
+    pub fn create_memory(&self, workspace_id: &str) -> Arc<MemoryStore> {
+        let mut memory = self.memory.lock().unwrap();
+
+        Arc::clone(memory.entry(workspace_id.to_string()).or_default())
+    }
+
+    /// Lists the ids of the workspaces under the root, in order.
+    ///
+    /// Entries of the root that are not valid workspace ids are left out.
+    pub fn list(&self, root: &str) -> Result<Vec<String>> {
+        let mut workspace_ids: Vec<String> = match self.kind {
+            StoreKind::Memory => self.memory.lock().unwrap().keys().cloned().collect(),
+            StoreKind::Local | StoreKind::Archive => {
+                let entries =
+                    fs::read_dir(root).context(format!(r#"listing workspaces in {:?}"#, root))?;
+
+                let mut workspace_ids = vec![];
+                for entry in entries {
+                    let entry = entry.context(format!(r#"listing workspaces in {:?}"#, root))?;
+                    let name = entry.file_name().to_string_lossy().to_string();
+
+                    let workspace_id = if self.kind == StoreKind::Local {
+                        entry.path().is_dir().then(|| name)
+                    } else {
+                        Self::ARCHIVE_EXTENSIONS.iter().find_map(|extension| {
+                            name.strip_suffix(&format!(".{}", extension))
+                                .map(String::from)
+                        })
+                    };
+
+                    if let Some(workspace_id) = workspace_id {
+                        if RootManager::validate_workspace_id(&workspace_id).is_ok() {
+                            workspace_ids.push(workspace_id);
+                        }
+                    }
+                }
+
+                workspace_ids
+            }
+        };
+
+        workspace_ids.sort();
+        workspace_ids.dedup();
+
+        Ok(workspace_ids)
+    }
+
+    /// Drops what is cached of a workspace, so its files are read again on the next request.
+    ///
+    /// Returns false if nothing was cached. Memory stores are never dropped, as they hold the only copy of their files.
+    pub fn reload(&self, root: &str, workspace_id: &str) -> bool {
+        if self.kind != StoreKind::Archive {
+            return false;
+        }
+
+        let paths: Vec<PathBuf> = if workspace_id.is_empty() {
+            vec![PathBuf::from(root)]
+        } else {
+            Self::ARCHIVE_EXTENSIONS
+                .iter()
+                .map(|extension| Path::new(root).join(format!("{}.{}", workspace_id, extension)))
+                .collect()
+        };
+
+        let mut archives = self.archives.lock().unwrap();
+        let mut reloaded = false;
+
+        for path in paths {
+            reloaded |= archives.remove(&path).is_some();
+        }
+
+        reloaded
+    }
+
+    /// Gets the store of a workspace archive, unpacking it again if it has changed since it was last opened.
+    ///
+    /// The archive of a workspace is `<root>/<workspace id>.<extension>`. Without a workspace id, the root is the archive itself.
+    fn archive(&self, root: &str, workspace_id: &str) -> Result<Arc<ArchiveStore>> {
+        let path = Self::archive_path(root, workspace_id)?;
+
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .context(format!(r#"getting modified time of archive {:?}"#, path))?;
+
+        if let Some((cached_modified, store)) = self.archives.lock().unwrap().get(&path) {
+            if *cached_modified == modified {
+                return Ok(Arc::clone(store));
+            }
+        }
+
+        let store = Arc::new(
+            ArchiveStore::open(&path)
+                .context(format!(r#"opening workspace archive {:?}"#, path))?,
+        );
+
+        self.archives
+            .lock()
+            .unwrap()
+            .insert(path, (modified, Arc::clone(&store)));
+
+        Ok(store)
+    }
+
+    /// Finds the archive of a workspace.
+    fn archive_path(root: &str, workspace_id: &str) -> Result<PathBuf> {
+        if workspace_id.is_empty() {
+            return Ok(PathBuf::from(root));
+        }
+
+        let path = Self::ARCHIVE_EXTENSIONS
+            .iter()
+            .map(|extension| Path::new(root).join(format!("{}.{}", workspace_id, extension)))
+            .find(|path| path.is_file());
+
+        match path {
+            Some(path) => Ok(path),
+            None => errors::new_error_t(format!(
+                r#"no archive of workspace {:?} in {:?}"#,
+                workspace_id, root
+            )),
+        }
+    }
+}
+
+/// Turns a path relative to the workspace root into the components it names, dropping `.` and resolving `..` lexically.
+///
+/// Fails if the path is absolute or climbs above the workspace root.
+pub(super) fn normalize(path: &Path) -> io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return Err(invalid_path(path, "it climbs above the workspace root"));
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(invalid_path(
+                    path,
+                    "it is not relative to the workspace root",
+                ))
+            }
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Creates an error for a path that cannot be used within a workspace.
+pub(super) fn invalid_path(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid workspace path {:?}, {}", path, reason),
+    )
+}
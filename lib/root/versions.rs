@@ -0,0 +1,493 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::{normalize, InvalidWorkspaceId, RootManager};
+use log::error;
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs, io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+use utilities::{
+    hyper::{Body, Response, StatusCode},
+    result::{Context, Result},
+};
+
+/// The versions of a workspace folder on the local volume.
+///
+/// A versioned workspace keeps immutable copies of its files in `versions/<version>` and a `current` symlink to
+/// the one requests see. Switching versions replaces the symlink atomically, so a request never sees a mix of two versions.
+pub struct WorkspaceVersions {
+    workspace_path: PathBuf,
+}
+
+/// Keeps a version from being removed by `WorkspaceVersions::prune` while it is held.
+///
+/// A pruned version that is still pinned is removed once its last pin is dropped.
+#[derive(Debug)]
+pub struct VersionPin {
+    canon_version_path: PathBuf,
+}
+
+#[derive(Debug, Default)]
+struct PinCount {
+    count: usize,
+    pruned: bool,
+}
+
+/// The pinned versions of the process, by canonical path.
+static PINS: Lazy<Mutex<BTreeMap<PathBuf, PinCount>>> = Lazy::new(Default::default);
+
+/// Tells apart the temporary files of concurrent operations of the process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A version of a workspace.
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub name: String,
+    pub created: Option<SystemTime>,
+    pub current: bool,
+}
+
+/// The error of a version operation that conflicts with the versions a workspace has.
+#[derive(Debug)]
+pub struct VersionError {
+    pub reason: String,
+}
+
+impl WorkspaceVersions {
+    /// The symlink to the current version.
+    pub const CURRENT: &'static str = "current";
+
+    /// The folder that holds the versions.
+    pub const VERSIONS: &'static str = "versions";
+
+    /// The folder console logs are written to, which is not part of any version.
+    pub const LOGS: &'static str = "logs";
+
+    /// The maximum length of a version name.
+    pub const MAX_VERSION_LEN: usize = 64;
+
+    /// Gets the versions of a workspace under the root.
+    ///
+    /// An empty workspace id refers to the root itself. The workspace folder is only created along with its first version.
+    pub fn open(root: &str, workspace_id: &str) -> Result<Self> {
+        if !workspace_id.is_empty() {
+            RootManager::validate_workspace_id(workspace_id)?;
+        }
+
+        Ok(Self {
+            workspace_path: Path::new(root).join(workspace_id),
+        })
+    }
+
+    /// Resolves the `current` symlink of a canonical workspace path to the canonical path of the current version.
+    ///
+    /// Returns `None` if the workspace is not versioned.
+    pub fn resolve_current(canon_workspace_path: &Path) -> Result<Option<PathBuf>> {
+        let current = canon_workspace_path.join(Self::CURRENT);
+
+        if fs::symlink_metadata(&current).is_err() {
+            return Ok(None);
+        }
+
+        let canon_version_path = fs::canonicalize(&current)
+            .context(format!(r#"resolving current version {:?}"#, current))?;
+
+        // SEC: The symlink must point at one of the workspace's own versions.
+        if canon_version_path.parent() != Some(canon_workspace_path.join(Self::VERSIONS).as_path())
+        {
+            return Err(InvalidWorkspaceId {
+                workspace_id: canon_workspace_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                reason: "its current version is not in its versions folder",
+            }
+            .into());
+        }
+
+        Ok(Some(canon_version_path))
+    }
+
+    /// Resolves the current version like `resolve_current` and pins it.
+    ///
+    /// Pruning never removes the current version, so the version is resolved again once pinned, in case a concurrent
+    /// switch and prune happened in between.
+    pub fn pin_current(canon_workspace_path: &Path) -> Result<Option<(PathBuf, VersionPin)>> {
+        loop {
+            let canon_version_path = match Self::resolve_current(canon_workspace_path)? {
+                Some(canon_version_path) => canon_version_path,
+                None => return Ok(None),
+            };
+
+            let pin = Self::pin(&canon_version_path);
+
+            if Self::resolve_current(canon_workspace_path)?.as_ref() == Some(&canon_version_path) {
+                return Ok(Some((canon_version_path, pin)));
+            }
+        }
+    }
+
+    fn pin(canon_version_path: &Path) -> VersionPin {
+        let mut pins = PINS.lock().unwrap();
+        pins.entry(canon_version_path.to_path_buf())
+            .or_default()
+            .count += 1;
+
+        VersionPin {
+            canon_version_path: canon_version_path.to_path_buf(),
+        }
+    }
+
+    /// Checks that a version name is made of ASCII letters, digits, `-`, `_` or `.` and does not start with `.`.
+    pub fn validate_version(name: &str) -> Result<()> {
+        let valid = !name.is_empty()
+            && name.len() <= Self::MAX_VERSION_LEN
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+        if !valid {
+            return Err(VersionError::new(format!(
+                r#"invalid version {:?}, it must be at most {} ASCII letters, digits, `-`, `_` or `.` and not start with `.`"#,
+                name,
+                Self::MAX_VERSION_LEN
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new version from its files and switches to it.
+    ///
+    /// The files are written to a hidden folder first, which is only renamed into place once complete.
+    /// Fails if the version already exists.
+    pub fn create(&self, name: &str, files: Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+        Self::validate_version(name)?;
+
+        let version_path = self.version_path(name);
+        if fs::symlink_metadata(&version_path).is_ok() {
+            return Err(VersionError::new(format!(r#"version {:?} already exists"#, name)).into());
+        }
+
+        fs::create_dir_all(self.versions_path()).context(format!(
+            r#"creating versions folder of workspace {:?}"#,
+            self.workspace_path
+        ))?;
+
+        let staging_path =
+            self.versions_path()
+                .join(format!(".{}.{}.tmp", name, Self::temp_suffix()));
+
+        let result = Self::write_files(&staging_path, files)
+            .and_then(|_| fs::rename(&staging_path, &version_path))
+            .context(format!(r#"writing files of version {:?}"#, name));
+
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&staging_path);
+        }
+
+        result?;
+
+        self.switch(name)
+    }
+
+    /// Creates a new version from the files of the workspace draft and switches to it.
+    ///
+    /// The `current` symlink, the versions folder and the logs folder at the top of the draft are left out.
+    pub fn snapshot(&self, name: &str, draft: &RootManager) -> Result<()> {
+        let mut files = vec![];
+        Self::collect_draft_files(draft, Path::new(""), &mut files)?;
+
+        self.create(name, files)
+    }
+
+    /// Switches back to a previous version, or to the one before the current version if none is given.
+    ///
+    /// Returns the version switched to.
+    pub fn rollback(&self, name: Option<&str>) -> Result<String> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let versions = self.list()?;
+
+                let previous = versions
+                    .iter()
+                    .position(|version| version.current)
+                    .and_then(|index| index.checked_sub(1))
+                    .map(|index| versions[index].name.clone());
+
+                match previous {
+                    Some(previous) => previous,
+                    None => {
+                        return Err(VersionError::new(
+                            "there is no version before the current one to roll back to",
+                        )
+                        .into())
+                    }
+                }
+            }
+        };
+
+        self.switch(&name)?;
+
+        Ok(name)
+    }
+
+    /// Makes a version the current one.
+    pub fn switch(&self, name: &str) -> Result<()> {
+        Self::validate_version(name)?;
+
+        if !self.version_path(name).is_dir() {
+            return Err(VersionError::new(format!(r#"version {:?} does not exist"#, name)).into());
+        }
+
+        let target: PathBuf = [Self::VERSIONS, name].iter().collect();
+        let temp_path =
+            self.workspace_path
+                .join(format!(".{}.{}.tmp", Self::CURRENT, Self::temp_suffix()));
+
+        let _ = fs::remove_file(&temp_path);
+
+        // The rename replaces the old symlink in one step.
+        symlink(&target, &temp_path)
+            .and_then(|_| fs::rename(&temp_path, self.workspace_path.join(Self::CURRENT)))
+            .context(format!(r#"switching to version {:?}"#, name))
+    }
+
+    /// Gets the name of the current version, if the workspace is versioned.
+    pub fn current(&self) -> Result<Option<String>> {
+        let current = self.workspace_path.join(Self::CURRENT);
+
+        match fs::read_link(&current) {
+            Ok(target) => Ok(target
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).context(format!(r#"reading current version {:?}"#, current)),
+        }
+    }
+
+    /// Lists the versions of the workspace from the oldest to the newest.
+    pub fn list(&self) -> Result<Vec<Version>> {
+        let current = self.current()?;
+
+        let entries = match fs::read_dir(self.versions_path()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => {
+                return Err(err).context(format!(
+                    r#"listing versions of workspace {:?}"#,
+                    self.workspace_path
+                ))
+            }
+        };
+
+        let mut versions = vec![];
+        for entry in entries {
+            let entry = entry.context("reading versions folder entry")?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            // Hidden entries are versions still being written.
+            if name.starts_with('.') || !entry.path().is_dir() {
+                continue;
+            }
+
+            versions.push(Version {
+                created: entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok(),
+                current: current.as_deref() == Some(name.as_str()),
+                name,
+            });
+        }
+
+        versions.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.name.cmp(&b.name)));
+
+        Ok(versions)
+    }
+
+    /// Removes the oldest versions that are not current, so at most `keep` previous versions remain.
+    ///
+    /// Versions pinned by open stores are removed once the last of them is dropped instead.
+    pub fn prune(&self, keep: usize) -> Result<()> {
+        let previous: Vec<Version> = self
+            .list()?
+            .into_iter()
+            .filter(|version| !version.current)
+            .collect();
+
+        let excess = previous.len().saturating_sub(keep);
+
+        let mut canon_version_paths = vec![];
+        for version in &previous[..excess] {
+            let version_path = self.version_path(&version.name);
+            let canon_version_path = fs::canonicalize(&version_path).context(format!(
+                r#"getting canonical path of version {:?}"#,
+                version.name
+            ))?;
+
+            canon_version_paths.push((version.name.as_str(), canon_version_path));
+        }
+
+        // Versions that are unpinned while the lock is held can only be pinned again if they become current.
+        let unpinned: Vec<_> = {
+            let mut pins = PINS.lock().unwrap();
+            let current = self.current()?;
+
+            canon_version_paths
+                .into_iter()
+                .filter(|(name, _)| current.as_deref() != Some(*name))
+                .filter(
+                    |(_, canon_version_path)| match pins.get_mut(canon_version_path) {
+                        Some(pin_count) => {
+                            pin_count.pruned = true;
+                            false
+                        }
+                        None => true,
+                    },
+                )
+                .collect()
+        };
+
+        for (name, canon_version_path) in unpinned {
+            fs::remove_dir_all(&canon_version_path)
+                .context(format!(r#"removing version {:?}"#, name))?;
+        }
+
+        Ok(())
+    }
+
+    fn collect_draft_files(
+        draft: &RootManager,
+        folder: &Path,
+        files: &mut Vec<(PathBuf, Vec<u8>)>,
+    ) -> Result<()> {
+        for entry in draft.list_dir(folder)? {
+            // The temporary symlinks made while switching versions are hidden too.
+            let reserved = folder.as_os_str().is_empty()
+                && (entry.name == Self::CURRENT
+                    || entry.name == Self::VERSIONS
+                    || entry.name == Self::LOGS
+                    || entry.name.starts_with(&format!(".{}.", Self::CURRENT)));
+
+            if reserved {
+                continue;
+            }
+
+            let path = folder.join(&entry.name);
+
+            if entry.is_dir {
+                Self::collect_draft_files(draft, &path, files)?;
+            } else {
+                let contents = draft.read_bytes_from_workspace(&path)?;
+                files.push((path, contents));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets a suffix for temporary file names that no other operation of the process uses.
+    fn temp_suffix() -> String {
+        format!(
+            "{}.{}",
+            process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    fn versions_path(&self) -> PathBuf {
+        self.workspace_path.join(Self::VERSIONS)
+    }
+
+    fn version_path(&self, name: &str) -> PathBuf {
+        self.versions_path().join(name)
+    }
+
+    fn write_files(folder: &Path, files: Vec<(PathBuf, Vec<u8>)>) -> io::Result<()> {
+        fs::create_dir_all(folder)?;
+
+        for (path, contents) in files {
+            // SEC: Paths come from bundles, so they must stay within the version folder.
+            let path = folder.join(normalize(&path)?);
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::write(path, contents)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for VersionPin {
+    fn drop(&mut self) {
+        let pruned = {
+            let mut pins = PINS.lock().unwrap();
+
+            let pin_count = match pins.get_mut(&self.canon_version_path) {
+                Some(pin_count) => pin_count,
+                None => return,
+            };
+
+            pin_count.count -= 1;
+            if pin_count.count > 0 {
+                return;
+            }
+
+            let pruned = pin_count.pruned;
+            pins.remove(&self.canon_version_path);
+            pruned
+        };
+
+        // The folder is removed without holding the lock, so opening other stores does not wait on it.
+        if pruned {
+            if let Err(err) = fs::remove_dir_all(&self.canon_version_path) {
+                error!(
+                    r#"Failed to remove pruned version {:?}: {}"#,
+                    self.canon_version_path, err
+                );
+            }
+        }
+    }
+}
+
+impl VersionError {
+    /// Creates a new version error.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+
+    /// Creates a `409 Conflict` response.
+    pub fn as_hyper_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.to_string()));
+        *response.status_mut() = StatusCode::CONFLICT;
+        response
+    }
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for VersionError {}
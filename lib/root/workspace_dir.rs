@@ -0,0 +1,435 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::config::SymlinkPolicy;
+use libc::c_int;
+use std::{
+    ffi::{CStr, CString, OsStr, OsString},
+    fs::{File, Metadata},
+    io::{self, Write},
+    mem,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        io::{AsRawFd, FromRawFd, RawFd},
+    },
+    path::{Component, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A workspace folder whose files are only ever reached through descriptors relative to it.
+///
+/// Every path component is opened with `O_NOFOLLOW` from the descriptor of its parent folder, so a component swapped
+/// for a symlink mid-resolution is caught instead of followed. Symlinks are resolved by hand according to the policy,
+/// and `..` can never climb above the workspace.
+pub struct WorkspaceDir {
+    dir: File,
+    canon_path: PathBuf,
+    policy: SymlinkPolicy,
+}
+
+/// The maximum number of symlinks followed while resolving a path, like the kernel's own limit.
+const MAX_SYMLINKS: usize = 40;
+
+impl WorkspaceDir {
+    /// Opens the workspace folder at a canonical path.
+    pub fn open(canon_path: &Path, policy: SymlinkPolicy) -> io::Result<Self> {
+        let dir = Self::open_at(
+            libc::AT_FDCWD,
+            canon_path.as_os_str(),
+            libc::O_RDONLY | libc::O_DIRECTORY,
+        )?;
+
+        Ok(Self {
+            dir,
+            canon_path: canon_path.to_owned(),
+            policy,
+        })
+    }
+
+    /// Opens a file for reading.
+    pub fn open_file(&self, path: &Path) -> io::Result<File> {
+        self.walk(path, libc::O_RDONLY, false)
+    }
+
+    /// Gets the metadata of a file or folder.
+    pub fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        // Non-blocking so that opening a FIFO does not wait for a writer.
+        self.walk(path, libc::O_RDONLY | libc::O_NONBLOCK, false)?
+            .metadata()
+    }
+
+    /// Lists the names of the entries of a folder and whether they are folders themselves.
+    pub fn list_dir(&self, path: &Path) -> io::Result<Vec<(OsString, bool)>> {
+        let dir = self.walk(path, libc::O_RDONLY | libc::O_DIRECTORY, false)?;
+
+        Self::read_dir(&dir)
+    }
+
+    /// Creates a folder and its missing parents.
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.walk(path, libc::O_RDONLY | libc::O_DIRECTORY, true)?;
+
+        Ok(())
+    }
+
+    /// Writes a file by writing a temporary file next to it and renaming it over the file.
+    ///
+    /// Missing parent folders are created. A symlink at the path is replaced, never written through.
+    pub fn write_atomic(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let (parent, name) = self.resolve_parent(path, true)?;
+        let parent_fd = parent.as_raw_fd();
+
+        let mut temp_name = OsString::from(".");
+        temp_name.push(&name);
+        temp_name.push(format!(
+            ".{}.{}.tmp",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let result = Self::open_at(
+            parent_fd,
+            &temp_name,
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW,
+        )
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| Self::rename_at(parent_fd, &temp_name, parent_fd, &name));
+
+        if result.is_err() {
+            let _ = Self::unlink_at(parent_fd, &temp_name, 0);
+        }
+
+        result
+    }
+
+    /// Opens a file for appending, creating it and its missing parent folders.
+    ///
+    /// A symlink at the path is never written through.
+    pub fn open_append(&self, path: &Path) -> io::Result<File> {
+        let (parent, name) = self.resolve_parent(path, true)?;
+
+        Self::open_at(
+            parent.as_raw_fd(),
+            &name,
+            libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT | libc::O_NOFOLLOW,
+        )
+    }
+
+    /// Renames a file, a symlink or a folder. A symlink is renamed itself, never its target.
+    pub fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from_parent, from_name) = self.resolve_parent(from, false)?;
+        let (to_parent, to_name) = self.resolve_parent(to, false)?;
+
+        Self::rename_at(
+            from_parent.as_raw_fd(),
+            &from_name,
+            to_parent.as_raw_fd(),
+            &to_name,
+        )
+    }
+
+    /// Removes a file, a symlink or a folder with its contents.
+    pub fn remove(&self, path: &Path) -> io::Result<()> {
+        let (parent, name) = self.resolve_parent(path, false)?;
+
+        if Self::file_type_at(parent.as_raw_fd(), &name)? == libc::S_IFDIR {
+            Self::remove_dir_all_at(&parent, &name)
+        } else {
+            Self::unlink_at(parent.as_raw_fd(), &name, 0)
+        }
+    }
+
+    /// Resolves the folder that holds the last component of a path, which is returned unresolved.
+    fn resolve_parent(&self, path: &Path, create: bool) -> io::Result<(File, OsString)> {
+        let mut components = Self::components(path)?;
+
+        let name = match components.pop() {
+            Some(name) if name != ".." => name,
+            _ => return Err(Self::invalid_path(path)),
+        };
+
+        let parent_path: PathBuf = components.iter().collect();
+        let parent = self.walk(&parent_path, libc::O_RDONLY | libc::O_DIRECTORY, create)?;
+
+        Ok((parent, name))
+    }
+
+    /// Opens a path component by component, starting from the workspace folder.
+    ///
+    /// The last component is opened with `flags`, the others as folders. Missing folders are created if `create` is set.
+    fn walk(&self, path: &Path, flags: c_int, create: bool) -> io::Result<File> {
+        // The folders from the workspace down to the current one.
+        let mut dirs = vec![self.dir.try_clone()?];
+
+        // The components left to open, last one first.
+        let mut pending: Vec<OsString> = Self::components(path)?.into_iter().rev().collect();
+
+        let mut symlinks = 0;
+
+        while let Some(name) = pending.pop() {
+            if name == ".." {
+                // SEC: Climbing above the workspace is never allowed.
+                if dirs.len() == 1 {
+                    return Err(Self::escape_error(path));
+                }
+
+                dirs.pop();
+                continue;
+            }
+
+            let is_last = pending.is_empty();
+            let component_flags = if is_last {
+                flags
+            } else {
+                libc::O_RDONLY | libc::O_DIRECTORY
+            };
+
+            let parent_fd = dirs.last().unwrap().as_raw_fd();
+
+            // SEC: Never let the kernel follow a symlink.
+            match Self::open_at(parent_fd, &name, component_flags | libc::O_NOFOLLOW) {
+                Ok(file) if is_last => return Ok(file),
+                Ok(dir) => dirs.push(dir),
+                Err(err) if create && err.kind() == io::ErrorKind::NotFound => {
+                    Self::mkdir_at(parent_fd, &name)?;
+                    pending.push(name);
+                }
+                Err(err) => {
+                    // Opening with `O_NOFOLLOW` fails on symlinks. Anything else is a genuine error.
+                    if !matches!(Self::file_type_at(parent_fd, &name), Ok(libc::S_IFLNK)) {
+                        return Err(err);
+                    }
+
+                    if self.policy == SymlinkPolicy::Deny {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            format!("symlink {:?} in path {:?} is not allowed", name, path),
+                        ));
+                    }
+
+                    symlinks += 1;
+                    if symlinks > MAX_SYMLINKS {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("too many symlinks in path {:?}", path),
+                        ));
+                    }
+
+                    let target = Self::read_link_at(parent_fd, &name)?;
+
+                    // Absolute targets restart the resolution from the workspace.
+                    let target = if target.is_absolute() {
+                        // SEC: The target must name the workspace by its canonical path.
+                        let relative = target
+                            .strip_prefix(&self.canon_path)
+                            .map_err(|_| Self::escape_error(path))?;
+
+                        dirs.truncate(1);
+                        relative.to_owned()
+                    } else {
+                        target
+                    };
+
+                    pending.extend(Self::components(&target)?.into_iter().rev());
+                }
+            }
+        }
+
+        // The path is empty or ends with `..`, so it names a folder.
+        Ok(dirs.pop().unwrap())
+    }
+
+    /// Splits a relative path into names and `..`, dropping `.`.
+    fn components(path: &Path) -> io::Result<Vec<OsString>> {
+        path.components()
+            .filter(|component| *component != Component::CurDir)
+            .map(|component| match component {
+                Component::Normal(name) => Ok(name.to_owned()),
+                Component::ParentDir => Ok(OsString::from("..")),
+                _ => Err(Self::invalid_path(path)),
+            })
+            .collect()
+    }
+
+    /// Removes a folder and its contents without following any symlink in it.
+    fn remove_dir_all_at(parent: &File, name: &OsStr) -> io::Result<()> {
+        let dir = Self::open_at(
+            parent.as_raw_fd(),
+            name,
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW,
+        )?;
+
+        for (entry, is_dir) in Self::read_dir(&dir)? {
+            if is_dir {
+                Self::remove_dir_all_at(&dir, &entry)?;
+            } else {
+                Self::unlink_at(dir.as_raw_fd(), &entry, 0)?;
+            }
+        }
+
+        Self::unlink_at(parent.as_raw_fd(), name, libc::AT_REMOVEDIR)
+    }
+
+    /// Reads the entries of an open folder, skipping `.` and `..`.
+    fn read_dir(dir: &File) -> io::Result<Vec<(OsString, bool)>> {
+        // The stream takes ownership of the descriptor it is given.
+        let fd = unsafe { libc::dup(dir.as_raw_fd()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+
+        let mut entries = vec![];
+
+        unsafe {
+            // The duplicate shares its offset with the original descriptor.
+            libc::rewinddir(stream);
+
+            loop {
+                let entry = libc::readdir(stream);
+                if entry.is_null() {
+                    break;
+                }
+
+                let name = CStr::from_ptr((*entry).d_name.as_ptr());
+                let name = OsStr::from_bytes(name.to_bytes());
+
+                if name == "." || name == ".." {
+                    continue;
+                }
+
+                let is_dir = match (*entry).d_type {
+                    libc::DT_DIR => true,
+                    libc::DT_UNKNOWN => matches!(Self::file_type_at(fd, name), Ok(libc::S_IFDIR)),
+                    _ => false,
+                };
+
+                entries.push((name.to_owned(), is_dir));
+            }
+
+            libc::closedir(stream);
+        }
+
+        Ok(entries)
+    }
+
+    fn open_at(dir_fd: RawFd, name: &OsStr, flags: c_int) -> io::Result<File> {
+        let name = Self::c_string(name)?;
+
+        let fd = unsafe {
+            libc::openat(
+                dir_fd,
+                name.as_ptr(),
+                flags | libc::O_CLOEXEC,
+                0o666 as libc::c_uint,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    fn mkdir_at(dir_fd: RawFd, name: &OsStr) -> io::Result<()> {
+        let name = Self::c_string(name)?;
+
+        if unsafe { libc::mkdirat(dir_fd, name.as_ptr(), 0o777) } < 0 {
+            let err = io::Error::last_os_error();
+
+            // Someone else may have just created it.
+            if err.kind() != io::ErrorKind::AlreadyExists {
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rename_at(from_dir_fd: RawFd, from: &OsStr, to_dir_fd: RawFd, to: &OsStr) -> io::Result<()> {
+        let from = Self::c_string(from)?;
+        let to = Self::c_string(to)?;
+
+        if unsafe { libc::renameat(from_dir_fd, from.as_ptr(), to_dir_fd, to.as_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn unlink_at(dir_fd: RawFd, name: &OsStr, flags: c_int) -> io::Result<()> {
+        let name = Self::c_string(name)?;
+
+        if unsafe { libc::unlinkat(dir_fd, name.as_ptr(), flags) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn read_link_at(dir_fd: RawFd, name: &OsStr) -> io::Result<PathBuf> {
+        let name = Self::c_string(name)?;
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+
+        let len = unsafe {
+            libc::readlinkat(
+                dir_fd,
+                name.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+            )
+        };
+
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        buf.truncate(len as usize);
+
+        Ok(PathBuf::from(OsString::from_vec(buf)))
+    }
+
+    /// Gets the file type bits of an entry without following it if it is a symlink.
+    fn file_type_at(dir_fd: RawFd, name: &OsStr) -> io::Result<libc::mode_t> {
+        let name = Self::c_string(name)?;
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+        if unsafe { libc::fstatat(dir_fd, name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(stat.st_mode & libc::S_IFMT)
+    }
+
+    fn c_string(name: &OsStr) -> io::Result<CString> {
+        CString::new(name.as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))
+    }
+
+    fn invalid_path(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("path {:?} must be relative to the workspace", path),
+        )
+    }
+
+    fn escape_error(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("path {:?} leads outside of the workspace", path),
+        )
+    }
+}
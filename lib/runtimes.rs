@@ -2,6 +2,10 @@
 
 mod api;
 mod permissions;
+mod resolver;
+mod route_table;
 
 pub use api::*;
 pub use permissions::*;
+pub use resolver::*;
+pub use route_table::*;
@@ -5,23 +5,30 @@ use std::{
     path::{self, Path, PathBuf},
     rc::Rc,
     sync::Arc,
+    time::Instant,
 };
 
-use crate::{root::RootManager, runtimes::ApiPermissions};
+use crate::{
+    config::ApiSettings,
+    extensions::ConsoleSink,
+    root::RootManager,
+    runtimes::{ApiPermissions, ResolvedApi},
+    InFlightRequest, Metrics, RequestId, RequestPhase, ScriptPhase, ScriptSpan, ServerContext,
+    SpanParent,
+};
 use log::debug;
-use regex::Regex;
 use tera::{
     events::{Events, HttpResponder},
+    extensions::Extension,
+    include_js_files,
     permissions::Permissions,
     Runtime, RuntimeOptions,
 };
 use tokio::sync::mpsc::Sender;
 use utilities::{
     config::ApiManifest,
-    errors, http,
     hyper::{Body, Method, Request, Response},
     result::Result,
-    setup::CommonSetup,
 };
 
 /// A runtime for executing previously-defined scripts and modules relating to an api.
@@ -31,42 +38,60 @@ pub struct ApiRuntime {
     relative_folder_path: String,
     root_mgr: RootManager,
     manifest: ApiManifest,
+    settings: ApiSettings,
     runtime: Runtime,
     method: Method,
+    workspace_id: String,
+    metrics: Arc<Metrics>,
+    console: Rc<ConsoleSink>,
+    in_flight: Option<Arc<InFlightRequest>>,
+    trace: SpanParent,
+    script_span: Rc<ScriptSpan>,
 }
 
 impl ApiRuntime {
     /// Creates a new API runtime.
+    ///
+    /// `extensions` are added to the runtime alongside the builtin ones. The runtime reads every file from the
+    /// workspace version the resolved api was pinned to, even if the workspace switches versions meanwhile.
     pub async fn new(
         request: Request<Body>,
+        resolved: ResolvedApi,
         response_tx: Rc<Sender<Response<Body>>>,
-        setup: Arc<CommonSetup>,
+        mut extensions: Vec<Extension>,
+        context: Arc<ServerContext>,
     ) -> Result<Self> {
         // Get config.
-        let config = &setup.config;
-
-        // Get url path.
-        let url_path = request.uri().path().to_string();
+        let config = &context.setup.config;
 
         // Get request method. Used to determine the index script to run.
         let method = request.method().to_owned();
 
-        debug!("Request path = {}", url_path);
+        // Set by the server before the request is handed to the events.
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|request_id| request_id.0.clone());
 
-        // Check if we can map multiple workspaces to a volume or db.
-        let workspace_id = if config.volume.multi_workspace || config.db.multi_workspace {
-            http::get_header_value(&request, http::WORKSPACE_ID_HEADER)?
-        } else {
-            String::new()
-        };
+        let in_flight = request.extensions().get::<Arc<InFlightRequest>>().cloned();
 
-        // Create root manager.
-        let root_mgr = RootManager::new(&config.volume.root, &workspace_id)?;
+        // The spans of the runtime are children of the request span.
+        let trace = request
+            .extensions()
+            .get::<SpanParent>()
+            .cloned()
+            .unwrap_or_default();
 
-        // Resolve path params.
-        let relative_folder_path = Self::resolve_url_path(&url_path)?;
+        let ResolvedApi {
+            workspace_id,
+            relative_folder_path,
+            root_mgr,
+            manifest,
+            settings,
+            ..
+        } = resolved;
 
-        debug!("Resolved url path = {}", &relative_folder_path);
+        debug!("Pinned workspace version = {:?}", root_mgr.version());
 
         // Create events.
         let events = Rc::new(RefCell::new(Events {
@@ -76,62 +101,114 @@ impl ApiRuntime {
             )),
         }));
 
-        // Parse the api manifest.
-        let content = root_mgr.read_file_from_workspace(
-            &[&relative_folder_path, "api.yaml"]
-                .iter()
-                .collect::<PathBuf>(),
-        )?;
-
-        // Parse manifest.
-        let manifest = ApiManifest::try_from(&content)?;
-
         // Get permissions.
-        let permissions =
-            ApiPermissions::load_permissions(&manifest, &root_mgr.canon_workspace_path)?;
+        let permissions = {
+            let _span = trace.start("permissions.load");
+            ApiPermissions::load_permissions(&manifest, root_mgr.local_path())?
+        };
+
+        // Capture console output of scripts.
+        let console = Rc::new(ConsoleSink::new(
+            Arc::clone(&context.console_logs),
+            &workspace_id,
+            request_id,
+        ));
+
+        extensions.push(crate::extensions::console(Rc::clone(&console)));
+        let script_span = Rc::new(ScriptSpan::default());
+        extensions.push(crate::extensions::trace(Rc::clone(&script_span)));
+
+        // Get custom postcripts. They run once the runtime is bootstrapped.
+        let custom_postscripts = include_js_files!(
+            prefix "(runtime_server:postscripts) ",
+            "lib/postscripts/40_console.js",
+        );
 
-        // TODO(appcypher): ...
-        // Get custom postcripts.
-        let custom_postscripts = vec![];
+        if let Some(in_flight) = &in_flight {
+            in_flight.set_phase(RequestPhase::RuntimeCreation);
+        }
 
         // Create runtime.
-        let runtime = Runtime::with_events(
+        let span = trace.start("runtime.create");
+        let start = Instant::now();
+        let mut runtime = Runtime::with_events(
             permissions,
             events,
             config.js_runtime.enable_snapshot,
             custom_postscripts,
             RuntimeOptions {
+                extensions,
                 ..Default::default()
             },
         )
         .await?;
 
+        drop(span);
+
+        // Killing the request interrupts running scripts.
+        if let Some(in_flight) = &in_flight {
+            in_flight.set_isolate_handle(runtime.v8_isolate().thread_safe_handle());
+        }
+
+        let metrics = Arc::clone(&context.metrics);
+        metrics
+            .runtime_construction
+            .observe(start.elapsed().as_secs_f64());
+
         Ok(Self {
             relative_folder_path,
             root_mgr,
             manifest,
+            settings,
             runtime,
             method,
+            workspace_id,
+            metrics,
+            console,
+            in_flight,
+            trace,
+            script_span,
         })
     }
 
     /// Executes the auth script (if enabled), the middleware scripts and the associated index module of the api.
     pub async fn execute(&mut self) -> Result<bool> {
+        if !self.authorize().await? {
+            return Ok(false);
+        }
+
+        // Run index.
+        self.run_index().await?;
+
+        Ok(true)
+    }
+
+    /// Executes the auth script (if enabled) and the middleware scripts of the api.
+    ///
+    /// Returns false if any of them rejects the request.
+    pub async fn authorize(&mut self) -> Result<bool> {
         // Run auth if enabled.
         if self.manifest.authentication.enabled {
-            if !self.run_auth().await? {
+            self.enter_phase(RequestPhase::Auth);
+            let start = Instant::now();
+            let authorized = self.run_auth().await?;
+            self.observe_phase(ScriptPhase::Auth, start);
+
+            if !authorized {
                 return Ok(false);
             };
         }
 
         // Run middlewares
-        if !self.run_middlewares().await? {
+        self.enter_phase(RequestPhase::Middlewares);
+        let start = Instant::now();
+        let passed = self.run_middlewares().await?;
+        self.observe_phase(ScriptPhase::Middlewares, start);
+
+        if !passed {
             return Ok(false);
         };
 
-        // Run index.
-        self.run_index().await?;
-
         Ok(true)
     }
 
@@ -152,6 +229,11 @@ impl ApiRuntime {
         let permissions = Permissions::default();
 
         // Execute script.
+        let mut span = self.trace.start("script.auth");
+        span.set_attribute("script", filepath);
+        self.script_span.set(span.as_parent());
+
+        self.console.set_script(filepath);
         let value_global = self
             .runtime
             .execute_middleware_script(filepath, code, permissions)
@@ -181,6 +263,11 @@ impl ApiRuntime {
             let permissions = Permissions::default();
 
             // Execute script.
+            let mut span = self.trace.start("script.middleware");
+            span.set_attribute("script", filepath);
+            self.script_span.set(span.as_parent());
+
+            self.console.set_script(filepath);
             let value_global = self
                 .runtime
                 .execute_middleware_script(filepath, code, permissions)
@@ -215,17 +302,54 @@ impl ApiRuntime {
 
         debug!("Index relative filepath = {:?}", filepath);
 
+        self.enter_phase(RequestPhase::Index);
+        let start = Instant::now();
+        let result = self.run_module(&filepath).await;
+        self.observe_phase(ScriptPhase::Index, start);
+
+        result
+    }
+
+    /// Executes the module that handles websocket connections to the api.
+    ///
+    /// That is the handler declared in the api manifest or `"index.ws.js"`.
+    pub async fn run_websocket_index(&mut self) -> Result<()> {
+        let filename = match &self.settings.websocket.handler {
+            Some(handler) => handler.as_str(),
+            None => "index.ws.js",
+        };
+
+        let filepath: PathBuf = [&self.relative_folder_path, filename].iter().collect();
+
+        debug!("Websocket index relative filepath = {:?}", filepath);
+
+        self.enter_phase(RequestPhase::Index);
+        let start = Instant::now();
+        let result = self.run_module(&filepath).await;
+        self.observe_phase(ScriptPhase::Index, start);
+
+        result
+    }
+
+    /// Executes a module at a path relative to the workspace root.
+    async fn run_module(&mut self, filepath: &Path) -> Result<()> {
         // Grab code from file.
-        let code = &self.root_mgr.read_file_from_workspace(&filepath)?;
+        let code = &self.root_mgr.read_file_from_workspace(filepath)?;
 
-        // Make index path absolute.
-        let abs_path: PathBuf = [&PathBuf::from(path::MAIN_SEPARATOR.to_string()), &filepath]
+        // Make module path absolute.
+        let abs_path: PathBuf = [&PathBuf::from(path::MAIN_SEPARATOR.to_string()), filepath]
             .iter()
             .collect();
 
-        debug!("Index absolute filepath = {:?}", abs_path);
+        debug!("Module absolute filepath = {:?}", abs_path);
 
         // Execute module.
+        let script = filepath.display().to_string();
+        let mut span = self.trace.start("script.index");
+        span.set_attribute("script", &script);
+        self.script_span.set(span.as_parent());
+
+        self.console.set_script(&script);
         self.runtime
             .execute_module(abs_path.display().to_string(), code)
             .await?;
@@ -242,71 +366,31 @@ impl ApiRuntime {
             .iter()
             .collect();
 
-        let full_path: PathBuf = [&self.root_mgr.canon_workspace_path, &relative_path]
-            .iter()
-            .collect();
-
-        // Check that full path exists.
-        if Path::new(&full_path).exists() {
+        // Check that path exists.
+        if self.root_mgr.metadata(&relative_path).is_ok() {
             relative_path
         } else {
             [&self.relative_folder_path, "index.js"].iter().collect()
         }
     }
 
-    /// Adds code string within an iife syntax to prevent accidental leak of data to global space.
-    fn format_code(code: &str) -> String {
-        // SEC: Note that there still ways to leak things into the global scope. https://gist.github.com/appcypher/2c210cd04774f1812a4b3e5c84496858
-        // Not sure if this is a critical security issue yet.
-        format!("\"use strict\"; (\n{} \n)();", code)
+    /// Shows the phase the request is in on the admin listener.
+    fn enter_phase(&self, phase: RequestPhase) {
+        if let Some(in_flight) = &self.in_flight {
+            in_flight.set_phase(phase);
+        }
     }
 
-    /// Converts url path to platform path and resolves path params in path.
-    ///
-    /// If a path ends with a param path `=`, the parent is returned instead.
-    fn resolve_url_path(url_path: &str) -> Result<String> {
-        let platform_path = &Self::to_platform_path(url_path)?;
-
-        // SEC: Get regex pattern of current platform's separator.
-        let re_sep = utilities::path::get_platform_sep_pattern();
-
-        // Pattern that matches path param like `\=foo\` in `C:\\Users\=foo\name`.
-        let pattern = format!(r"{}=[^{}]*{}?", re_sep, re_sep, re_sep);
-        let re = Regex::new(&pattern).unwrap();
-
-        // Replace all path param pattern with "\=\" (in unix for example)
-        let replace = format!(r"{}={}", path::MAIN_SEPARATOR, path::MAIN_SEPARATOR);
-        let resolved_param_path = re.replace_all(platform_path, replace);
-
-        debug!("Resolved param path = {}", &resolved_param_path);
-
-        // Remove trailing `=` in path. This is because a resolved path that ends with `=` should be handled by its parent directory.
-        // NOTE: SEC: Since we are only trimming, it is not possible to `../{workspace_root}`.
-        let resolved_param_path = resolved_param_path
-            .trim_end_matches(path::MAIN_SEPARATOR)
-            .trim_end_matches('=');
-
-        // Finally remove any path separator at both ends of the path.
-        Ok(resolved_param_path
-            .trim_end_matches(path::MAIN_SEPARATOR)
-            .trim_start_matches(path::MAIN_SEPARATOR)
-            .to_string())
+    /// Records how long a script phase took since `start`.
+    fn observe_phase(&self, phase: ScriptPhase, start: Instant) {
+        self.metrics
+            .observe_script_phase(&self.workspace_id, phase, start.elapsed());
     }
 
-    /// Converts url path to platform path (using the platform's main separator)
-    ///
-    /// Windows path separator is not allowed in url.
-    fn to_platform_path(url_path: &str) -> Result<String> {
-        // SEC: Check if there is windows path separator in the url.
-        if url_path.contains(r"\") {
-            return errors::new_error_t(r"the `\` character is not supported in a url");
-        }
-
-        if !cfg!(unix) {
-            // Replace url separators with platform-specific separators.
-            return Ok(url_path.replace("/", &path::MAIN_SEPARATOR.to_string()));
-        }
-
-        return Ok(url_path.to_string());
+    /// Adds code string within an iife syntax to prevent accidental leak of data to global space.
+    pub(crate) fn format_code(code: &str) -> String {
+        // SEC: Note that there still ways to leak things into the global scope. https://gist.github.com/appcypher/2c210cd04774f1812a4b3e5c84496858
+        // Not sure if this is a critical security issue yet.
+        format!("\"use strict\"; (\n{} \n)();", code)
     }
 }
@@ -7,7 +7,7 @@ use tera::permissions::{
     fs::{Fs, FsPath, FsRoot},
     PermissionType, Permissions, Resource,
 };
-use utilities::{config::ApiManifest, result::Result};
+use utilities::{config::ApiManifest, errors, result::Result};
 
 type PermissionTuple = (Box<dyn PermissionType>, Vec<Box<dyn Resource>>);
 
@@ -16,13 +16,25 @@ pub struct ApiPermissions;
 impl ApiPermissions {
     pub fn load_permissions(
         api_manifest: &ApiManifest,
-        workspace_path: &Path,
+        workspace_path: Option<&Path>,
     ) -> Result<Permissions> {
         let fs_permissions = Self::fs_permissions(api_manifest);
         let http_event_permissions = Self::http_event_permissions(api_manifest);
 
-        Ok(Permissions::builder()
-            .add_state(FsRoot::try_from(workspace_path)?)
+        // Fs ops reach files directly, so they only work with workspaces on the local volume.
+        let builder = match workspace_path {
+            Some(workspace_path) => {
+                Permissions::builder().add_state(FsRoot::try_from(workspace_path)?)
+            }
+            None if !fs_permissions.is_empty() => {
+                return errors::new_error_t(
+                    "fs permissions need a workspace store on the local volume",
+                )
+            }
+            None => Permissions::builder(),
+        };
+
+        Ok(builder
             .add_owned_permissions(http_event_permissions)?
             .add_owned_permissions_with_allow_lists(fs_permissions)?
             .build())
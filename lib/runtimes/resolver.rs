@@ -0,0 +1,200 @@
+// Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{
+    error::Error,
+    fmt,
+    path::{self, Path, PathBuf},
+};
+
+use crate::{
+    config::{ApiSettings, WorkspaceConfig},
+    root::RootManager,
+    ApiLabels, ServerContext, SpanParent,
+};
+use log::debug;
+use regex::Regex;
+use utilities::{
+    config::ApiManifest,
+    errors::{self, AnyError},
+    http,
+    hyper::{Body, Request},
+    result::Result,
+};
+
+/// The workspace folder and manifest that a request url resolves to.
+///
+/// Resolving an api does not start a JavaScript runtime, so the server can act on the manifest natively before any script runs.
+pub struct ResolvedApi {
+    pub workspace_id: String,
+    pub relative_folder_path: String,
+    pub root_mgr: RootManager,
+    pub workspace_config: WorkspaceConfig,
+    pub manifest: ApiManifest,
+    pub settings: ApiSettings,
+}
+
+/// The error of an api manifest that cannot be parsed.
+#[derive(Debug)]
+pub struct InvalidManifest {
+    /// The manifest path relative to the workspace root.
+    pub path: PathBuf,
+    /// The line of the manifest the error is on, starting at 1, if known.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ResolvedApi {
+    /// Resolves the api folder and manifest of a request.
+    ///
+    /// Fails with `MisdirectedRequest` if host routing is enabled and the request's workspace cannot be determined.
+    pub fn resolve(request: &Request<Body>, context: &ServerContext) -> Result<Self> {
+        let trace = request
+            .extensions()
+            .get::<SpanParent>()
+            .cloned()
+            .unwrap_or_default();
+
+        let mut span = trace.start("routing");
+
+        // Get config.
+        let config = &context.setup.config;
+
+        // Get url path.
+        let url_path = request.uri().path();
+
+        debug!("Request path = {}", url_path);
+
+        // Check if we can map multiple workspaces to a volume or db.
+        let workspace_id = if config.volume.multi_workspace || config.db.multi_workspace {
+            let workspace_id = match &context.host_router {
+                Some(host_router) => host_router.workspace_id(request)?,
+                None => http::get_header_value(request, http::WORKSPACE_ID_HEADER)?,
+            };
+
+            // SEC: The empty id of a single workspace would give a tenant the whole volume root.
+            RootManager::validate_workspace_id(&workspace_id)?;
+
+            workspace_id
+        } else {
+            String::new()
+        };
+
+        // Create root manager.
+        let root_mgr = RootManager::open(&config.volume.root, &workspace_id, &context.stores)?;
+
+        // Load workspace config.
+        let workspace_config = WorkspaceConfig::load(&root_mgr)?;
+
+        // Resolve path params.
+        let relative_folder_path = Self::resolve_url_path(url_path)?;
+
+        debug!("Resolved url path = {}", &relative_folder_path);
+
+        span.set_attribute("workspace.id", &workspace_id);
+        span.set_attribute("api.folder", &relative_folder_path);
+
+        let (manifest, settings) = {
+            let _span = span.as_parent().start("manifest.load");
+
+            // Read the api manifest.
+            let manifest_path: PathBuf = [&relative_folder_path, "api.yaml"].iter().collect();
+            let content = root_mgr.read_file_from_workspace(&manifest_path)?;
+
+            // Parse manifest.
+            let manifest = ApiManifest::try_from(&content)
+                .map_err(|err| InvalidManifest::new(&manifest_path, err))?;
+
+            // Parse runtime settings.
+            let settings = ApiSettings::try_from(&content)
+                .map_err(|err| InvalidManifest::new(&manifest_path, err))?;
+
+            (manifest, settings)
+        };
+
+        // Only label metrics with paths that have an api, so arbitrary urls cannot blow up their number.
+        if let Some(labels) = request.extensions().get::<ApiLabels>() {
+            labels.set(&workspace_id, &relative_folder_path);
+        }
+
+        Ok(Self {
+            workspace_id,
+            relative_folder_path,
+            root_mgr,
+            workspace_config,
+            manifest,
+            settings,
+        })
+    }
+
+    /// Converts url path to platform path and resolves path params in path.
+    ///
+    /// If a path ends with a param path `=`, the parent is returned instead.
+    fn resolve_url_path(url_path: &str) -> Result<String> {
+        let platform_path = &Self::to_platform_path(url_path)?;
+
+        // SEC: Get regex pattern of current platform's separator.
+        let re_sep = utilities::path::get_platform_sep_pattern();
+
+        // Pattern that matches path param like `\=foo\` in `C:\\Users\=foo\name`.
+        let pattern = format!(r"{}=[^{}]*{}?", re_sep, re_sep, re_sep);
+        let re = Regex::new(&pattern).unwrap();
+
+        // Replace all path param pattern with "\=\" (in unix for example)
+        let replace = format!(r"{}={}", path::MAIN_SEPARATOR, path::MAIN_SEPARATOR);
+        let resolved_param_path = re.replace_all(platform_path, replace);
+
+        debug!("Resolved param path = {}", &resolved_param_path);
+
+        // Remove trailing `=` in path. This is because a resolved path that ends with `=` should be handled by its parent directory.
+        // NOTE: SEC: Since we are only trimming, it is not possible to `../{workspace_root}`.
+        let resolved_param_path = resolved_param_path
+            .trim_end_matches(path::MAIN_SEPARATOR)
+            .trim_end_matches('=');
+
+        // Finally remove any path separator at both ends of the path.
+        Ok(resolved_param_path
+            .trim_end_matches(path::MAIN_SEPARATOR)
+            .trim_start_matches(path::MAIN_SEPARATOR)
+            .to_string())
+    }
+
+    /// Converts url path to platform path (using the platform's main separator)
+    ///
+    /// Windows path separator is not allowed in url.
+    fn to_platform_path(url_path: &str) -> Result<String> {
+        // SEC: Check if there is windows path separator in the url.
+        if url_path.contains(r"\") {
+            return errors::new_error_t(r"the `\` character is not supported in a url");
+        }
+
+        if !cfg!(unix) {
+            // Replace url separators with platform-specific separators.
+            return Ok(url_path.replace("/", &path::MAIN_SEPARATOR.to_string()));
+        }
+
+        return Ok(url_path.to_string());
+    }
+}
+
+impl InvalidManifest {
+    pub fn new(path: &Path, err: AnyError) -> Self {
+        let line = err
+            .downcast_ref::<serde_yaml::Error>()
+            .and_then(|err| err.location())
+            .map(|location| location.line());
+
+        Self {
+            path: path.to_owned(),
+            line,
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+impl fmt::Display for InvalidManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid api manifest {:?}, {}", self.path, self.message)
+    }
+}
+
+impl Error for InvalidManifest {}
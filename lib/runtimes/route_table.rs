@@ -0,0 +1,112 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{
+    config::ApiSettings,
+    root::{DirEntry, RootManager},
+};
+use std::path::{Component, Path, PathBuf};
+use utilities::{config::ApiManifest, result::Result};
+
+/// An api of a workspace and how requests reach it.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// The url path of the api. Path param segments are shown as `=*`.
+    pub path: String,
+    /// The api folder relative to the workspace root.
+    pub folder: PathBuf,
+    /// The methods that have an index module of their own, like `GET` for `index.get.js`.
+    pub methods: Vec<String>,
+    /// Whether there is an `index.js` for the other methods.
+    pub has_index: bool,
+    pub websocket: bool,
+    pub authentication: bool,
+    pub middlewares: Vec<String>,
+}
+
+/// The apis of a workspace, as the router resolves them.
+pub struct RouteTable;
+
+impl RouteTable {
+    /// The folder under the workspace root that holds the apis.
+    pub const API_FOLDER: &'static str = "api";
+
+    pub(crate) const METHODS: [&'static str; 9] = [
+        "get", "post", "put", "delete", "head", "options", "connect", "patch", "trace",
+    ];
+
+    /// Finds every api folder of a workspace, in path order.
+    pub fn load(root_mgr: &RootManager) -> Result<Vec<Route>> {
+        let mut routes = vec![];
+
+        if root_mgr.metadata(Path::new(Self::API_FOLDER)).is_ok() {
+            Self::collect_routes(root_mgr, Path::new(Self::API_FOLDER), &mut routes)?;
+        }
+
+        Ok(routes)
+    }
+
+    fn collect_routes(
+        root_mgr: &RootManager,
+        folder: &Path,
+        routes: &mut Vec<Route>,
+    ) -> Result<()> {
+        let entries = root_mgr.list_dir(folder)?;
+
+        if entries
+            .iter()
+            .any(|entry| !entry.is_dir && entry.name == "api.yaml")
+        {
+            routes.push(Self::route(root_mgr, folder, &entries)?);
+        }
+
+        for entry in entries.iter().filter(|entry| entry.is_dir) {
+            Self::collect_routes(root_mgr, &folder.join(&entry.name), routes)?;
+        }
+
+        Ok(())
+    }
+
+    fn route(root_mgr: &RootManager, folder: &Path, entries: &[DirEntry]) -> Result<Route> {
+        let manifest_path = folder.join("api.yaml");
+        let content = root_mgr.read_file_from_workspace(&manifest_path)?;
+
+        let manifest = ApiManifest::try_from(&content)?;
+        let settings = ApiSettings::try_from(&content)?;
+
+        let has_file = |name: &str| {
+            entries
+                .iter()
+                .any(|entry| !entry.is_dir && entry.name == name)
+        };
+
+        let methods = Self::METHODS
+            .iter()
+            .filter(|method| has_file(&format!("index.{}.js", method)))
+            .map(|method| method.to_ascii_uppercase())
+            .collect();
+
+        // Param folders are named `=` since any `=<value>` segment of a url resolves to them.
+        let segments: Vec<String> = folder
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) if name == "=" => Some("=*".to_string()),
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Route {
+            path: format!("/{}", segments.join("/")),
+            folder: folder.to_owned(),
+            methods,
+            has_index: has_file("index.js"),
+            websocket: settings.permissions.http_event.websocket,
+            authentication: manifest.authentication.enabled,
+            middlewares: manifest
+                .middlewares
+                .iter()
+                .map(|middleware| middleware.script.clone())
+                .collect(),
+        })
+    }
+}
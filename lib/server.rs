@@ -1,10 +1,41 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
+mod access_log;
+mod admin;
+mod body;
+mod console_logs;
+mod context;
+mod cors;
+mod dev_errors;
 mod driver;
 pub(crate) mod handlers;
+mod health;
+mod hosts;
+mod in_flight;
+mod logger;
+mod metrics;
+mod rate_limit;
 mod routes;
 mod server;
+mod tls;
+mod trace;
+mod trace_exporter;
 
+pub use access_log::*;
+pub use admin::*;
+pub use body::*;
+pub use console_logs::*;
+pub use context::*;
+pub use cors::*;
+pub use dev_errors::*;
 pub use driver::*;
+pub use health::*;
+pub use hosts::*;
+pub use in_flight::*;
+pub use logger::*;
+pub use metrics::*;
+pub use rate_limit::*;
 pub use routes::*;
 pub use server::*;
+pub use tls::*;
+pub use trace::*;
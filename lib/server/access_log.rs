@@ -0,0 +1,134 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::ApiLabels;
+use env_logger::fmt::Formatter;
+use log::{info, Record};
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use utilities::hyper::{body::HttpBody, header::HeaderValue, Body, Method, Response};
+
+/// The log target of access log records.
+pub const ACCESS_LOG_TARGET: &str = "access";
+
+/// The id of a request, taken from its `X-Request-Id` header or generated.
+///
+/// Added to the extensions of every request by the server and returned in the `X-Request-Id` response header.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// The access log record of a request.
+pub struct AccessLog<'a> {
+    pub request_id: &'a RequestId,
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub labels: &'a ApiLabels,
+    pub duration: Duration,
+}
+
+thread_local! {
+    /// The id of the request the current thread handles.
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl RequestId {
+    pub const HEADER: &'static str = "x-request-id";
+
+    /// The maximum length of an incoming request id.
+    const MAX_LEN: usize = 128;
+
+    /// Uses the `X-Request-Id` header value if it is a sensible id, otherwise generates a new one.
+    pub fn from_header(value: Option<&HeaderValue>) -> Self {
+        let incoming = value
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| Self::is_valid(id));
+
+        match incoming {
+            Some(id) => Self(id.to_string()),
+            None => Self::generate(),
+        }
+    }
+
+    /// Generates an id that is unique across the requests of the process and unlikely to clash with other processes.
+    pub fn generate() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or(0);
+
+        let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        Self(format!("{:016x}-{:08x}-{:x}", nanos, process::id(), count))
+    }
+
+    /// Makes this the id of the request the current thread handles, so it shows up in every log record of the thread.
+    pub fn enter(&self) {
+        CURRENT_REQUEST_ID.with(|current| *current.borrow_mut() = Some(self.0.clone()));
+    }
+
+    /// Gets the id of the request the current thread handles.
+    pub fn current() -> Option<String> {
+        CURRENT_REQUEST_ID.with(|current| current.borrow().clone())
+    }
+
+    // SEC: Only visible ascii is accepted so that clients cannot forge log lines or response headers.
+    fn is_valid(id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= Self::MAX_LEN
+            && id.bytes().all(|byte| byte.is_ascii_graphic())
+    }
+}
+
+impl<'a> AccessLog<'a> {
+    /// Writes the access log record of a response.
+    ///
+    /// The duration is the time until the response head was ready. Bytes are only known for bodies that are not streamed.
+    pub fn log(&self, response: &Response<Body>) {
+        let (workspace_id, folder) = self.labels.get();
+
+        let record = json!({
+            "request_id": self.request_id.0,
+            "workspace": workspace_id,
+            "method": self.method.as_str(),
+            "path": self.path,
+            "folder": folder,
+            "status": response.status().as_u16(),
+            "duration_ms": self.duration.as_secs_f64() * 1000.0,
+            "bytes": response.body().size_hint().exact(),
+        });
+
+        info!(target: ACCESS_LOG_TARGET, "{}", record);
+    }
+
+    /// Formats log records as JSON lines for `env_logger`, tagged with the id of the request the thread handles.
+    ///
+    /// Access log records are already JSON objects and only get the time added.
+    pub fn format(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+        let time = buf.timestamp_millis().to_string();
+
+        if record.target() == ACCESS_LOG_TARGET {
+            if let Ok(Value::Object(mut fields)) = serde_json::from_str(&record.args().to_string())
+            {
+                fields.insert("time".to_string(), Value::String(time));
+                return writeln!(buf, "{}", Value::Object(fields));
+            }
+        }
+
+        let line = json!({
+            "time": time,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "request_id": RequestId::current(),
+            "message": record.args().to_string(),
+        });
+
+        writeln!(buf, "{}", line)
+    }
+}
@@ -0,0 +1,465 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{
+    config::{AdminConfig, StoreKind},
+    deploy::{Deployer, InvalidBundle},
+    root::{InvalidWorkspaceId, RootManager, VersionError, WorkspaceVersions},
+    LimitedBody, PayloadTooLarge, RuntimeLogger, ServerContext,
+};
+use log::{debug, error, info, LevelFilter};
+use serde_json::json;
+use std::{
+    convert::Infallible,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::TcpListener, task};
+use utilities::{
+    errors,
+    hyper::{
+        header, server::conn::Http, service::service_fn, Body, Method, Request, Response,
+        StatusCode,
+    },
+    ip,
+    result::{Context, Result},
+};
+
+/// Serves operations on the runtime itself on a listener of its own.
+///
+/// Every request must carry the admin token as `Authorization: Bearer <token>`.
+///
+/// - `GET /metrics` renders the runtime metrics in the Prometheus text format.
+/// - `GET /workspaces` lists the workspaces and their current versions.
+/// - `GET /requests` lists the requests being handled with their phase and age.
+/// - `DELETE /requests/<id>` kills a request.
+/// - `GET /config` dumps the runtime config.
+/// - `GET /log-level` and `PUT /log-level?level=<level>` get and set the log level. `reset` goes back to `RUST_LOG`.
+/// - `POST /workspaces/<workspace id>/reload` drops what is cached of a workspace.
+/// - `PUT /workspaces/<workspace id>/bundle` deploys a bundle to a workspace.
+/// - `GET /workspaces/<workspace id>/versions` lists the versions of a workspace.
+/// - `POST /workspaces/<workspace id>/versions[?version=<version>]` makes a version of the workspace draft and switches to it.
+/// - `POST /workspaces/<workspace id>/rollback[?version=<version>]` switches back to a version, by default the one before the current.
+///
+/// Without multiple workspaces, workspace routes start with `/workspace` instead.
+pub struct AdminServer {
+    context: Arc<ServerContext>,
+    config: AdminConfig,
+    token: String,
+}
+
+impl AdminServer {
+    /// Creates a new admin server, loading the admin token.
+    pub fn new(context: Arc<ServerContext>, config: AdminConfig) -> Result<Self> {
+        let token = config.load_token()?;
+
+        if token.is_empty() {
+            return errors::new_error_t(format!(
+                r#"admin token file {:?} is empty"#,
+                config.token_path
+            ));
+        }
+
+        Ok(Self {
+            context,
+            config,
+            token,
+        })
+    }
+
+    /// Accepts admin connections until the listener cannot be bound.
+    pub async fn listen(self: Arc<Self>) -> Result<()> {
+        let addr = ip::parse_socket_address(&self.config.socket_address)?;
+
+        info!(r#"Admin socket address = "{}""#, addr);
+
+        let tcp_listener = TcpListener::bind(addr)
+            .await
+            .context(format!(r#"binding admin listener to "{}""#, addr))?;
+
+        loop {
+            let (tcp_stream, remote_addr) = match tcp_listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    error!("accepting admin connection = {:?}", err);
+                    continue;
+                }
+            };
+
+            let admin = Arc::clone(&self);
+
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let admin = Arc::clone(&admin);
+                    async move { Ok::<_, Infallible>(admin.handle(request).await) }
+                });
+
+                if let Err(err) = Http::new()
+                    .http1_only(true)
+                    .serve_connection(tcp_stream, service)
+                    .await
+                {
+                    debug!("admin connection with {} = {:?}", remote_addr, err);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if !self.is_authorized(&request) {
+            return Self::error_response(
+                StatusCode::UNAUTHORIZED,
+                "missing or invalid admin token",
+            );
+        }
+
+        match self.route(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                if let Some(invalid) = err.downcast_ref::<InvalidBundle>() {
+                    invalid.as_hyper_response()
+                } else if let Some(too_large) = err.downcast_ref::<PayloadTooLarge>() {
+                    too_large.as_hyper_response()
+                } else if let Some(invalid) = err.downcast_ref::<InvalidWorkspaceId>() {
+                    invalid.as_hyper_response()
+                } else if let Some(conflict) = err.downcast_ref::<VersionError>() {
+                    conflict.as_hyper_response()
+                } else {
+                    error!("{:?}", err);
+                    Self::error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+                }
+            }
+        }
+    }
+
+    async fn route(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let path = request.uri().path().trim_matches('/').to_string();
+        let segments: Vec<&str> = path.split('/').collect();
+
+        match (request.method(), segments.as_slice()) {
+            (&Method::GET, ["metrics"]) => return self.metrics(),
+            (&Method::GET, ["workspaces"]) => return self.list_workspaces(),
+            (&Method::GET, ["requests"]) => return Ok(self.list_requests()),
+            (&Method::DELETE, ["requests", id]) => return Ok(self.kill_request(id)),
+            (&Method::GET, ["config"]) => return self.dump_config(),
+            (&Method::GET, ["log-level"]) => return Ok(Self::log_level()),
+            (&Method::PUT, ["log-level"]) => return Ok(Self::set_log_level(&request)),
+            _ => (),
+        }
+
+        let (workspace_id, operation) = match self.workspace_route(&segments) {
+            Some(route) => route,
+            None => return Ok(Self::error_response(StatusCode::NOT_FOUND, "unknown route")),
+        };
+
+        if let (&Method::POST, ["reload"]) = (request.method(), operation) {
+            return self.reload(&workspace_id);
+        }
+
+        // Versions only exist for workspaces on the local volume.
+        if self.context.config.store != StoreKind::Local {
+            return Ok(Self::error_response(
+                StatusCode::CONFLICT,
+                "workspaces are not on the local volume",
+            ));
+        }
+
+        match (request.method(), operation) {
+            (&Method::PUT, ["bundle"]) => self.deploy(request, workspace_id).await,
+            (&Method::GET, ["versions"]) => self.list_versions(&workspace_id),
+            (&Method::POST, ["versions"]) => self.snapshot(&request, workspace_id).await,
+            (&Method::POST, ["rollback"]) => self.rollback(&request, &workspace_id),
+            _ => Ok(Self::error_response(StatusCode::NOT_FOUND, "unknown route")),
+        }
+    }
+
+    /// Splits the segments of a workspace route into the workspace id and the operation segments.
+    fn workspace_route<'a>(&self, segments: &'a [&'a str]) -> Option<(String, &'a [&'a str])> {
+        let config = &self.context.setup.config;
+
+        if config.volume.multi_workspace || config.db.multi_workspace {
+            match segments {
+                // SEC: The empty id refers to the whole volume root.
+                ["workspaces", workspace_id, operation @ ..] if !workspace_id.is_empty() => {
+                    Some((workspace_id.to_string(), operation))
+                }
+                _ => None,
+            }
+        } else {
+            match segments {
+                ["workspace", operation @ ..] => Some((String::new(), operation)),
+                _ => None,
+            }
+        }
+    }
+
+    /// Renders the runtime metrics.
+    fn metrics(&self) -> Result<Response<Body>> {
+        let (content_type, body) = self.context.metrics.render()?;
+
+        let mut response = Response::new(Body::from(body));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_str(&content_type)
+                .context("creating metrics content type header")?,
+        );
+
+        Ok(response)
+    }
+
+    /// Lists the workspaces on the volume, or the single workspace, with their current versions.
+    fn list_workspaces(&self) -> Result<Response<Body>> {
+        let config = &self.context.setup.config;
+        let root = &config.volume.root;
+
+        let workspace_ids = if config.volume.multi_workspace || config.db.multi_workspace {
+            self.context.stores.list(root)?
+        } else {
+            vec![String::new()]
+        };
+
+        let workspaces: Vec<_> = workspace_ids
+            .iter()
+            .map(|workspace_id| {
+                // Only workspaces on the local volume have versions.
+                let version = match self.context.config.store {
+                    StoreKind::Local => WorkspaceVersions::open(root, workspace_id)
+                        .and_then(|versions| versions.current())
+                        .ok()
+                        .flatten(),
+                    _ => None,
+                };
+
+                json!({ "id": workspace_id, "version": version })
+            })
+            .collect();
+
+        Ok(Self::json_response(
+            StatusCode::OK,
+            json!({ "workspaces": workspaces }),
+        ))
+    }
+
+    /// Drops what is cached of a workspace.
+    fn reload(&self, workspace_id: &str) -> Result<Response<Body>> {
+        if !workspace_id.is_empty() {
+            RootManager::validate_workspace_id(workspace_id)?;
+        }
+
+        let root = &self.context.setup.config.volume.root;
+        let reloaded = self.context.stores.reload(root, workspace_id);
+
+        info!(r#"Reloaded workspace {:?}"#, workspace_id);
+
+        Ok(Self::json_response(
+            StatusCode::OK,
+            json!({ "reloaded": reloaded }),
+        ))
+    }
+
+    fn list_requests(&self) -> Response<Body> {
+        let requests: Vec<_> = self
+            .context
+            .in_flight
+            .list()
+            .iter()
+            .map(|request| request.to_json())
+            .collect();
+
+        Self::json_response(StatusCode::OK, json!({ "requests": requests }))
+    }
+
+    fn kill_request(&self, id: &str) -> Response<Body> {
+        let killed = id
+            .parse()
+            .map_or(false, |id| self.context.in_flight.kill(id));
+
+        if !killed {
+            return Self::error_response(StatusCode::NOT_FOUND, "no such request");
+        }
+
+        info!("Killed request {}", id);
+
+        Self::json_response(StatusCode::OK, json!({ "killed": true }))
+    }
+
+    /// Dumps the runtime config. Secrets like the tracing headers are left out.
+    fn dump_config(&self) -> Result<Response<Body>> {
+        let config =
+            serde_json::to_value(&self.context.config).context("serializing runtime config")?;
+
+        Ok(Self::json_response(StatusCode::OK, config))
+    }
+
+    fn log_level() -> Response<Body> {
+        let level = RuntimeLogger::level().map(|level| level.to_string().to_ascii_lowercase());
+        Self::json_response(StatusCode::OK, json!({ "level": level }))
+    }
+
+    /// Sets the log level from the `level` query parameter.
+    fn set_log_level(request: &Request<Body>) -> Response<Body> {
+        let level = match Self::query_param(request, "level").as_deref() {
+            Some("reset") => None,
+            Some(level) => match LevelFilter::from_str(level) {
+                Ok(level) => Some(level),
+                Err(_) => {
+                    return Self::error_response(StatusCode::BAD_REQUEST, "invalid log level")
+                }
+            },
+            None => return Self::error_response(StatusCode::BAD_REQUEST, "missing log level"),
+        };
+
+        RuntimeLogger::set_level(level);
+
+        info!("Log level set to {:?}", level);
+
+        Self::log_level()
+    }
+
+    /// Deploys the bundle in the request body.
+    async fn deploy(&self, request: Request<Body>, workspace_id: String) -> Result<Response<Body>> {
+        let config = self.context.config.deploy.clone();
+        LimitedBody::check_content_length(&request, config.max_bundle_size)?;
+
+        let bytes = LimitedBody::new(request.into_body(), config.max_bundle_size)
+            .read_to_end()
+            .await?;
+
+        let root = self.context.setup.config.volume.root.clone();
+
+        // Validation compiles scripts in a V8 isolate, which must not run on the main runtime's threads.
+        let version = task::spawn_blocking(move || {
+            Deployer::deploy(bytes.to_vec(), &root, &workspace_id, &config)
+        })
+        .await
+        .context("joining deploy task")??;
+
+        Ok(Self::json_response(
+            StatusCode::OK,
+            json!({ "version": version }),
+        ))
+    }
+
+    /// Lists the versions of a workspace from the oldest to the newest.
+    fn list_versions(&self, workspace_id: &str) -> Result<Response<Body>> {
+        let root = &self.context.setup.config.volume.root;
+        let versions = WorkspaceVersions::open(root, workspace_id)?.list()?;
+
+        let versions: Vec<_> = versions
+            .iter()
+            .map(|version| {
+                json!({
+                    "name": version.name,
+                    "created": version.created.map(Self::unix_seconds),
+                    "current": version.current,
+                })
+            })
+            .collect();
+
+        Ok(Self::json_response(
+            StatusCode::OK,
+            json!({ "versions": versions }),
+        ))
+    }
+
+    /// Makes a version of the workspace draft, named by the `version` query parameter or after the current time.
+    async fn snapshot(
+        &self,
+        request: &Request<Body>,
+        workspace_id: String,
+    ) -> Result<Response<Body>> {
+        let version = match Self::query_param(request, "version") {
+            Some(version) => version,
+            None => format!("snapshot-{}", Self::unix_seconds(SystemTime::now())),
+        };
+
+        let root = self.context.setup.config.volume.root.clone();
+        let symlink_policy = self.context.config.symlinks;
+        let keep_versions = self.context.config.deploy.keep_versions;
+
+        // Copying the draft reads every file, so it is kept off the main runtime's threads.
+        let version = task::spawn_blocking(move || -> Result<String> {
+            let draft = RootManager::draft(&root, &workspace_id, symlink_policy)?;
+            let versions = WorkspaceVersions::open(&root, &workspace_id)?;
+
+            versions.snapshot(&version, &draft)?;
+            versions.prune(keep_versions)?;
+
+            Ok(version)
+        })
+        .await
+        .context("joining snapshot task")??;
+
+        Ok(Self::json_response(
+            StatusCode::OK,
+            json!({ "version": version }),
+        ))
+    }
+
+    /// Switches a workspace back to the version in the `version` query parameter or the one before the current.
+    fn rollback(&self, request: &Request<Body>, workspace_id: &str) -> Result<Response<Body>> {
+        let root = &self.context.setup.config.volume.root;
+        let version = Self::query_param(request, "version");
+
+        let version = WorkspaceVersions::open(root, workspace_id)?.rollback(version.as_deref())?;
+
+        info!(
+            r#"Rolled workspace {:?} back to version {:?}"#,
+            workspace_id, version
+        );
+
+        Ok(Self::json_response(
+            StatusCode::OK,
+            json!({ "version": version }),
+        ))
+    }
+
+    /// Gets the value of a query parameter. Values are not percent-decoded.
+    fn query_param(request: &Request<Body>, name: &str) -> Option<String> {
+        request.uri().query().and_then(|query| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    fn unix_seconds(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// Compares the bearer token of a request with the admin token in constant time.
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        // SEC: Comparing every byte so the time taken does not tell how much of the token is right.
+        token.len() == self.token.len()
+            && token
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+        let mut response = Response::new(Body::from(body.to_string()));
+        *response.status_mut() = status;
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+        response
+    }
+
+    fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+        Self::json_response(status, json!({ "error": message }))
+    }
+}
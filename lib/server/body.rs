@@ -0,0 +1,96 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use std::{error::Error, fmt};
+
+use utilities::{
+    hyper::{
+        body::{Bytes, HttpBody},
+        header, Body, Request, Response, StatusCode,
+    },
+    result::{Context, Result},
+};
+
+/// A request body that fails once more than `limit` bytes have been read from it.
+pub struct LimitedBody {
+    body: Body,
+    limit: u64,
+    read: u64,
+}
+
+/// The error returned when a request body is larger than allowed.
+#[derive(Debug)]
+pub struct PayloadTooLarge {
+    pub limit: u64,
+}
+
+impl LimitedBody {
+    /// Creates a new size-limited body.
+    pub fn new(body: Body, limit: u64) -> Self {
+        Self {
+            body,
+            limit,
+            read: 0,
+        }
+    }
+
+    /// Checks the `Content-Length` header of a request so that oversized bodies can be rejected without reading them.
+    pub fn check_content_length(
+        request: &Request<Body>,
+        limit: u64,
+    ) -> std::result::Result<(), PayloadTooLarge> {
+        let content_length = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+
+        match content_length {
+            Some(length) if length > limit => Err(PayloadTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the next chunk of the body. Returns `None` at the end of the body.
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        let chunk = match self.body.data().await {
+            Some(chunk) => chunk.context("reading request body")?,
+            None => return Ok(None),
+        };
+
+        // SEC: Content-Length can be absent or wrong, so the limit is enforced on what is actually read.
+        self.read += chunk.len() as u64;
+        if self.read > self.limit {
+            return Err(PayloadTooLarge { limit: self.limit }.into());
+        }
+
+        Ok(Some(chunk))
+    }
+
+    /// Reads the rest of the body into memory.
+    pub async fn read_to_end(mut self) -> Result<Bytes> {
+        let mut buffer = Vec::new();
+
+        while let Some(chunk) = self.next_chunk().await? {
+            buffer.extend_from_slice(&chunk);
+        }
+
+        Ok(buffer.into())
+    }
+}
+
+impl PayloadTooLarge {
+    /// Creates a `413 Payload Too Large` response.
+    pub fn as_hyper_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.to_string()));
+        *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+        response
+    }
+}
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body is larger than {} bytes", self.limit)
+    }
+}
+
+impl Error for PayloadTooLarge {}
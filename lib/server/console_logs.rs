@@ -0,0 +1,359 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{
+    config::{ConsoleConfig, StoreKind, SymlinkPolicy},
+    root::{WorkspaceDir, WorkspaceVersions},
+};
+use log::error;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use utilities::result::{Context, Result};
+
+/// The rotating console log files of workspaces, shared by all worker threads.
+pub struct ConsoleLogs {
+    pub config: ConsoleConfig,
+    base_dir: Option<PathBuf>,
+    in_workspace: bool,
+    symlink_policy: SymlinkPolicy,
+    max_open_files: usize,
+    files: Mutex<OpenFiles>,
+}
+
+/// Log files by workspace id, along with the order they were last used in.
+///
+/// SEC: Every workspace that logs would otherwise keep a file and a folder open for as long as the server runs, so
+/// the least recently used files are closed once there are too many of them.
+#[derive(Default)]
+struct OpenFiles {
+    by_id: HashMap<String, (Arc<Mutex<RotatingFile>>, u64)>,
+    by_use: BTreeMap<u64, String>,
+    uses: u64,
+}
+
+/// A log file that is rotated once it reaches its maximum size.
+///
+/// SEC: Log files in workspaces live in folders the workspace controls, so they are only ever opened, renamed and
+/// removed through a `WorkspaceDir` that never follows a symlink out of the folder or writes through one.
+struct RotatingFile {
+    /// The folder the log file is confined to.
+    base_dir: PathBuf,
+    /// Whether the base folder is created if it is missing, which is only done for the server's own log dir.
+    create_base_dir: bool,
+    symlink_policy: SymlinkPolicy,
+    dir: Option<WorkspaceDir>,
+    /// The path of the log file relative to the base folder.
+    path: PathBuf,
+    file: Option<File>,
+    len: u64,
+}
+
+impl ConsoleLogs {
+    /// The name of the current log file of a workspace.
+    pub const FILE_NAME: &'static str = "console.log";
+
+    /// The maximum number of log files kept open at a time.
+    pub const MAX_OPEN_FILES: usize = 256;
+
+    /// Creates the console logs of workspaces under `root`.
+    ///
+    /// Without a configured log dir, logs are only written for workspaces on the local volume.
+    pub fn new(
+        config: ConsoleConfig,
+        root: &str,
+        store: StoreKind,
+        symlink_policy: SymlinkPolicy,
+    ) -> Self {
+        let (base_dir, in_workspace) = match &config.log_dir {
+            Some(log_dir) => (Some(log_dir.clone()), false),
+            None if store == StoreKind::Local => (Some(PathBuf::from(root)), true),
+            None => (None, false),
+        };
+
+        Self {
+            config,
+            base_dir,
+            in_workspace,
+            symlink_policy,
+            max_open_files: Self::MAX_OPEN_FILES,
+            files: Mutex::new(OpenFiles::default()),
+        }
+    }
+
+    /// Appends a line to the log file of a workspace. Does nothing if there is nowhere to write it.
+    ///
+    /// The workspace id must have been validated.
+    pub fn write(&self, workspace_id: &str, line: &str) {
+        let file = match self.file(workspace_id) {
+            Some(file) => file,
+            None => return,
+        };
+
+        let mut file = file.lock().unwrap();
+        if let Err(err) = file.write_line(line, &self.config) {
+            error!(
+                r#"writing console log of workspace {:?} = {:?}"#,
+                workspace_id, err
+            );
+        }
+    }
+
+    fn file(&self, workspace_id: &str) -> Option<Arc<Mutex<RotatingFile>>> {
+        let base_dir = self.base_dir.as_ref()?;
+        let mut files = self.files.lock().unwrap();
+
+        let file = files.touch(workspace_id, self.max_open_files, || {
+            if self.in_workspace {
+                let path = [WorkspaceVersions::LOGS, Self::FILE_NAME].iter().collect();
+                RotatingFile::new(
+                    base_dir.join(workspace_id),
+                    false,
+                    self.symlink_policy,
+                    path,
+                )
+            } else {
+                let path = [workspace_id, Self::FILE_NAME].iter().collect();
+                RotatingFile::new(base_dir.clone(), true, SymlinkPolicy::Deny, path)
+            }
+        });
+
+        Some(file)
+    }
+}
+
+impl OpenFiles {
+    /// Gets the log file of a workspace, creating it if needed, and marks it as the most recently used one.
+    fn touch(
+        &mut self,
+        workspace_id: &str,
+        max_open_files: usize,
+        create: impl FnOnce() -> RotatingFile,
+    ) -> Arc<Mutex<RotatingFile>> {
+        self.uses += 1;
+        let last_use = self.uses;
+
+        if let Some((file, file_use)) = self.by_id.get_mut(workspace_id) {
+            self.by_use.remove(file_use);
+            *file_use = last_use;
+        } else {
+            self.evict(max_open_files.max(1) - 1);
+            self.by_id.insert(
+                workspace_id.to_string(),
+                (Arc::new(Mutex::new(create())), last_use),
+            );
+        }
+
+        self.by_use.insert(last_use, workspace_id.to_string());
+        Arc::clone(&self.by_id[workspace_id].0)
+    }
+
+    /// Closes the least recently used files until at most `max_open_files` are left.
+    ///
+    /// Files that are being written to are skipped, so that a workspace never has two handles rotating the same file.
+    /// They are closed on a later call once the write is done.
+    fn evict(&mut self, max_open_files: usize) {
+        let excess = self.by_id.len().saturating_sub(max_open_files);
+
+        let idle: Vec<u64> = self
+            .by_use
+            .iter()
+            .filter(|(_, id)| Arc::strong_count(&self.by_id[*id].0) == 1)
+            .map(|(file_use, _)| *file_use)
+            .take(excess)
+            .collect();
+
+        for file_use in idle {
+            if let Some(id) = self.by_use.remove(&file_use) {
+                self.by_id.remove(&id);
+            }
+        }
+    }
+}
+
+impl RotatingFile {
+    fn new(
+        base_dir: PathBuf,
+        create_base_dir: bool,
+        symlink_policy: SymlinkPolicy,
+        path: PathBuf,
+    ) -> Self {
+        Self {
+            base_dir,
+            create_base_dir,
+            symlink_policy,
+            dir: None,
+            path,
+            file: None,
+            len: 0,
+        }
+    }
+
+    fn write_line(&mut self, line: &str, config: &ConsoleConfig) -> Result<()> {
+        let size = line.len() as u64 + 1;
+
+        if self.file.is_none() {
+            self.open()?;
+        }
+
+        if self.len > 0 && self.len + size > config.max_file_size {
+            self.rotate(config.max_files)?;
+            self.open()?;
+        }
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line).context(format!(r#"writing to {:?}"#, self.path))?;
+            self.len += size;
+        }
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<()> {
+        if self.dir.is_none() {
+            self.dir = Some(self.open_dir()?);
+        }
+
+        let file = self
+            .dir
+            .as_ref()
+            .unwrap()
+            .open_append(&self.path)
+            .context(format!(r#"opening log file {:?}"#, self.path))?;
+
+        self.len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        self.file = Some(file);
+
+        Ok(())
+    }
+
+    /// Shifts `console.log.<n>` to `console.log.<n + 1>` and the current file to `console.log.1`, dropping the oldest.
+    fn rotate(&mut self, max_files: usize) -> Result<()> {
+        self.file = None;
+
+        // Only files that have been opened get rotated.
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+
+        if max_files == 0 {
+            return dir
+                .remove(&self.path)
+                .context(format!(r#"removing {:?}"#, self.path));
+        }
+
+        let _ = dir.remove(&self.rotated_path(max_files));
+
+        for n in (1..max_files).rev() {
+            let from = self.rotated_path(n);
+            match dir.rename(&from, &self.rotated_path(n + 1)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    return Err(err).context(format!(r#"rotating {:?}"#, from));
+                }
+                _ => (),
+            }
+        }
+
+        dir.rename(&self.path, &self.rotated_path(1))
+            .context(format!(r#"rotating {:?}"#, self.path))
+    }
+
+    /// Opens the folder the log file is confined to.
+    fn open_dir(&self) -> Result<WorkspaceDir> {
+        if self.create_base_dir {
+            fs::create_dir_all(&self.base_dir)
+                .context(format!(r#"creating log dir {:?}"#, self.base_dir))?;
+        }
+
+        let canon_path = fs::canonicalize(&self.base_dir)
+            .context(format!(r#"canonicalizing {:?}"#, self.base_dir))?;
+
+        WorkspaceDir::open(&canon_path, self.symlink_policy)
+            .context(format!(r#"opening {:?}"#, canon_path))
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::TestWorkspace;
+
+    fn console_logs(workspace: &TestWorkspace, max_open_files: usize) -> ConsoleLogs {
+        let config = ConsoleConfig {
+            log_dir: Some(workspace.root().join("logs")),
+            ..Default::default()
+        };
+
+        ConsoleLogs {
+            max_open_files,
+            ..ConsoleLogs::new(config, "", StoreKind::Local, SymlinkPolicy::Deny)
+        }
+    }
+
+    fn read_log(workspace: &TestWorkspace, workspace_id: &str) -> String {
+        let path = workspace
+            .root()
+            .join("logs")
+            .join(workspace_id)
+            .join(ConsoleLogs::FILE_NAME);
+
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn least_recently_used_file_is_closed() {
+        let workspace = TestWorkspace::new().unwrap();
+        let console_logs = console_logs(&workspace, 2);
+
+        console_logs.write("a", "first");
+        console_logs.write("b", "first");
+        console_logs.write("a", "second");
+        console_logs.write("c", "first");
+
+        let mut open: Vec<String> = console_logs
+            .files
+            .lock()
+            .unwrap()
+            .by_id
+            .keys()
+            .cloned()
+            .collect();
+        open.sort();
+
+        assert_eq!(open, vec!["a", "c"]);
+
+        // Closed files are appended to once they are reopened.
+        console_logs.write("b", "second");
+
+        assert_eq!(read_log(&workspace, "b"), "first\nsecond\n");
+        assert_eq!(console_logs.files.lock().unwrap().by_id.len(), 2);
+    }
+
+    #[test]
+    fn files_being_written_are_not_closed() {
+        let workspace = TestWorkspace::new().unwrap();
+        let console_logs = console_logs(&workspace, 1);
+
+        let busy = console_logs.file("a").unwrap();
+        console_logs.write("b", "first");
+
+        assert!(console_logs.files.lock().unwrap().by_id.contains_key("a"));
+
+        drop(busy);
+        console_logs.write("c", "first");
+
+        let files = console_logs.files.lock().unwrap();
+        assert!(!files.by_id.contains_key("a"));
+        assert!(!files.by_id.contains_key("b"));
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{
+    config::RuntimeConfig, root::WorkspaceStores, ConsoleLogs, HostRouter, InFlightRequests,
+    Metrics, RateLimiter, Tracer,
+};
+use std::sync::{atomic::AtomicBool, Arc};
+use utilities::{result::Result, setup::CommonSetup};
+
+/// State shared by all the connections handled by the runtime server.
+pub struct ServerContext {
+    pub setup: Arc<CommonSetup>,
+    pub config: RuntimeConfig,
+    pub rate_limiter: RateLimiter,
+    pub host_router: Option<HostRouter>,
+    pub stores: WorkspaceStores,
+    pub metrics: Arc<Metrics>,
+    pub console_logs: Arc<ConsoleLogs>,
+    pub tracer: Arc<Tracer>,
+    pub in_flight: Arc<InFlightRequests>,
+    /// Set once a shutdown signal is received.
+    pub shutting_down: AtomicBool,
+}
+
+impl ServerContext {
+    /// Creates a new server context, loading the host mapping if host routing is enabled.
+    pub fn new(setup: Arc<CommonSetup>, config: RuntimeConfig) -> Result<Self> {
+        let host_router = match &config.host_routing {
+            Some(host_routing) => Some(HostRouter::new(host_routing)?),
+            None => None,
+        };
+
+        let stores = WorkspaceStores::new(config.store, config.symlinks);
+
+        let console_logs = ConsoleLogs::new(
+            config.console.clone(),
+            &setup.config.volume.root,
+            config.store,
+            config.symlinks,
+        );
+
+        let tracer = Tracer::new(config.tracing.clone());
+
+        Ok(Self {
+            setup,
+            config,
+            rate_limiter: RateLimiter::default(),
+            host_router,
+            stores,
+            metrics: Arc::new(Metrics::new()?),
+            console_logs: Arc::new(console_logs),
+            tracer: Arc::new(tracer),
+            in_flight: Arc::new(InFlightRequests::default()),
+            shutting_down: AtomicBool::new(false),
+        })
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::config::CorsConfig;
+use regex::Regex;
+use std::sync::{Arc, Mutex};
+use utilities::hyper::{
+    header::{self, HeaderValue},
+    Body, Method, Request, Response, StatusCode,
+};
+
+/// Applies the CORS policy of an api natively, before any script runs.
+pub struct Cors {
+    config: CorsConfig,
+    allow_any_origin: bool,
+    origin_patterns: Vec<Regex>,
+}
+
+/// The CORS policy that applies to the response of a request, if its api has one.
+///
+/// Added to the extensions of every request by the server. The api handler sets the policy once the api is resolved and
+/// the server applies it to whatever response it receives, so native, script and error responses are all decorated.
+#[derive(Clone, Default)]
+pub struct ResponseCors(Arc<Mutex<Option<(Arc<Cors>, Option<HeaderValue>)>>>);
+
+impl Cors {
+    /// Creates a CORS policy from the `cors` section of an api manifest.
+    pub fn new(config: CorsConfig) -> Self {
+        let allow_any_origin = config.allow_origins.iter().any(|origin| origin == "*");
+
+        let origin_patterns = config
+            .allow_origins
+            .iter()
+            .filter(|origin| *origin != "*")
+            .map(|origin| {
+                // SEC: Only the wildcard is special and it can only match host characters.
+                let pattern = regex::escape(origin).replace(r"\*", r"[A-Za-z0-9.-]+");
+
+                // SEC: Ensuring the pattern matches against the whole origin.
+                Regex::new(&format!(r"^{}$", pattern)).unwrap()
+            })
+            .collect();
+
+        Self {
+            config,
+            allow_any_origin,
+            origin_patterns,
+        }
+    }
+
+    /// Checks if the request is a CORS preflight request.
+    pub fn is_preflight(request: &Request<Body>) -> bool {
+        let headers = request.headers();
+
+        request.method() == Method::OPTIONS
+            && headers.contains_key(header::ORIGIN)
+            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Creates the response to a preflight request.
+    ///
+    /// Preflight requests that do not satisfy the policy get a `403 Forbidden` without any CORS header.
+    pub fn preflight_response(&self, request: &Request<Body>) -> Response<Body> {
+        let headers = request.headers();
+        let mut response = Response::new(Body::empty());
+
+        let origin = headers.get(header::ORIGIN);
+        let requested_method = Self::header_str(headers.get(header::ACCESS_CONTROL_REQUEST_METHOD));
+        let requested_headers =
+            Self::header_str(headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS));
+
+        // Check origin, method and headers against the policy.
+        let allowed = self.allows_origin(origin)
+            && Self::list_allows(&self.config.allow_methods, requested_method)
+            && requested_headers
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .all(|name| Self::list_allows(&self.config.allow_headers, name));
+
+        if !allowed {
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            return response;
+        }
+
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        self.add_origin_headers(origin, &mut response);
+
+        let response_headers = response.headers_mut();
+
+        // A wildcard list reflects what was asked for.
+        let allow_methods = if self.config.allow_methods.iter().any(|m| m == "*") {
+            requested_method.to_string()
+        } else {
+            self.config.allow_methods.join(", ")
+        };
+
+        let allow_headers = if self.config.allow_headers.iter().any(|h| h == "*") {
+            requested_headers.to_string()
+        } else {
+            self.config.allow_headers.join(", ")
+        };
+
+        if let Ok(value) = HeaderValue::from_str(&allow_methods) {
+            response_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, value);
+        }
+
+        if !allow_headers.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&allow_headers) {
+                response_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, value);
+            }
+        }
+
+        if let Some(max_age) = self.config.max_age {
+            response_headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+
+        response
+    }
+
+    /// Adds CORS headers to an actual (non-preflight) response if the request origin is allowed.
+    pub fn decorate(&self, origin: Option<&HeaderValue>, response: &mut Response<Body>) {
+        if !self.allows_origin(origin) {
+            return;
+        }
+
+        self.add_origin_headers(origin, response);
+
+        if !self.config.expose_headers.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&self.config.expose_headers.join(", ")) {
+                response
+                    .headers_mut()
+                    .insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, value);
+            }
+        }
+    }
+
+    /// Checks if the origin is allowed by the policy.
+    fn allows_origin(&self, origin: Option<&HeaderValue>) -> bool {
+        let origin = match origin.and_then(|value| value.to_str().ok()) {
+            Some(origin) => origin,
+            None => return false,
+        };
+
+        self.allow_any_origin || self.origin_patterns.iter().any(|re| re.is_match(origin))
+    }
+
+    /// Adds the allow-origin and allow-credentials headers.
+    fn add_origin_headers(&self, origin: Option<&HeaderValue>, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+
+        // Credentialed requests cannot use the `*` wildcard, so the origin is echoed instead.
+        if self.allow_any_origin && !self.config.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+        } else if let Some(origin) = origin {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+
+        if self.config.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Checks if a case-insensitive list contains the value or the `*` wildcard.
+    fn list_allows(list: &[String], value: &str) -> bool {
+        list.iter()
+            .any(|item| item == "*" || item.eq_ignore_ascii_case(value))
+    }
+
+    fn header_str(value: Option<&HeaderValue>) -> &str {
+        value.and_then(|value| value.to_str().ok()).unwrap_or("")
+    }
+}
+
+impl ResponseCors {
+    /// Sets the policy and the origin of the request.
+    pub fn set(&self, cors: Arc<Cors>, origin: Option<HeaderValue>) {
+        *self.0.lock().unwrap() = Some((cors, origin));
+    }
+
+    /// Adds CORS headers to the response if a policy was set and it allows the origin.
+    pub fn decorate(&self, response: &mut Response<Body>) {
+        if let Some((cors, origin)) = &*self.0.lock().unwrap() {
+            cors.decorate(origin.as_ref(), response);
+        }
+    }
+}
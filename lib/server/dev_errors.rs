@@ -0,0 +1,312 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{root::RootManager, runtimes::InvalidManifest, ApiLabels, ServerContext};
+use serde::Serialize;
+use serde_json::Value;
+use std::{fmt, path::PathBuf};
+use tera::errors::JsError;
+use utilities::{
+    errors::HandlerError,
+    hyper::{header, Body, Request, Response, StatusCode},
+};
+
+/// Describes a failed request in detail, for local development.
+///
+/// Only served in dev mode, since it shows workspace code and permissions to the client.
+#[derive(Debug, Serialize)]
+pub struct DevErrorPage {
+    pub status: u16,
+    pub kind: DevErrorKind,
+    pub message: String,
+    /// The workspace file the error comes from, if known.
+    pub location: Option<DevStackFrame>,
+    pub stack: Vec<DevStackFrame>,
+    pub route: DevRoute,
+    /// The `permissions` section of the api manifest, if the api was resolved.
+    pub permissions: Option<Value>,
+    #[serde(skip)]
+    html: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DevErrorKind {
+    Script,
+    Permission,
+    Manifest,
+    Server,
+}
+
+/// A frame of a script stack, with its file relative to the workspace root if it is a workspace file.
+#[derive(Debug, Clone, Serialize)]
+pub struct DevStackFrame {
+    pub function: Option<String>,
+    pub file: String,
+    pub line: Option<i64>,
+    pub column: Option<i64>,
+    pub in_workspace: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DevRoute {
+    pub method: String,
+    pub path: String,
+    pub workspace_id: String,
+    /// The api folder the request resolved to, empty if it was not resolved.
+    pub folder: String,
+}
+
+/// What the error page needs to know about the request, taken before the request is handled.
+pub struct DevRequest {
+    route: DevRoute,
+    accepts_html: bool,
+}
+
+impl DevRequest {
+    pub fn new(request: &Request<Body>) -> Self {
+        let accepts_html = request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.contains("text/html"));
+
+        Self {
+            route: DevRoute {
+                method: request.method().to_string(),
+                path: request.uri().path().to_string(),
+                ..Default::default()
+            },
+            accepts_html,
+        }
+    }
+}
+
+impl DevErrorPage {
+    const STYLE: &'static str = "body{font-family:sans-serif;margin:2em;color:#222}\
+        pre,code{font-family:monospace}\
+        .message{background:#fde8e8;padding:1em;white-space:pre-wrap}\
+        .internal{color:#888}\
+        th{text-align:left;padding-right:1em}\
+        .note{color:#888;font-size:small}";
+
+    /// Describes a handler error, before it is turned into a response.
+    pub fn new(
+        err: &HandlerError,
+        request: DevRequest,
+        labels: Option<&ApiLabels>,
+        context: &ServerContext,
+    ) -> Self {
+        let mut route = request.route;
+        if let Some(labels) = labels {
+            let (workspace_id, folder) = labels.get();
+            route.workspace_id = workspace_id;
+            route.folder = folder;
+        }
+
+        let mut page = Self {
+            status: err.as_hyper_response().status().as_u16(),
+            kind: DevErrorKind::Server,
+            message: format!("{:?}", err.system_error()),
+            location: None,
+            stack: vec![],
+            permissions: Self::load_permissions(&route, context),
+            route,
+            html: request.accepts_html,
+        };
+
+        if let HandlerError::Internal { src, .. } = err {
+            if let Some(js_err) = src.downcast_ref::<JsError>() {
+                // Permission errors are answered with `401 Unauthorized` like outside of dev mode.
+                if js_err.message.contains("CustomError::Permission") {
+                    page.kind = DevErrorKind::Permission;
+                    page.status = StatusCode::UNAUTHORIZED.as_u16();
+                } else {
+                    page.kind = DevErrorKind::Script;
+                }
+
+                page.message = js_err.message.clone();
+                page.location = js_err.script_resource_name.as_ref().map(|file| {
+                    DevStackFrame::new(None, file, js_err.line_number, js_err.start_column)
+                });
+                page.stack = js_err
+                    .frames
+                    .iter()
+                    .filter_map(|frame| {
+                        let file = frame.file_name.as_ref()?;
+                        Some(DevStackFrame::new(
+                            frame.function_name.clone(),
+                            file,
+                            frame.line_number,
+                            frame.column_number,
+                        ))
+                    })
+                    .collect();
+            } else if let Some(invalid) = src.downcast_ref::<InvalidManifest>() {
+                page.kind = DevErrorKind::Manifest;
+                page.message = invalid.message.clone();
+                page.location = Some(DevStackFrame {
+                    function: None,
+                    file: invalid.path.display().to_string(),
+                    line: invalid.line.map(|line| line as i64),
+                    column: None,
+                    in_workspace: true,
+                });
+            }
+        }
+
+        page
+    }
+
+    /// Creates an HTML response for browsers or a JSON one for other clients.
+    pub fn as_hyper_response(&self) -> Response<Body> {
+        let (content_type, body) = if self.html {
+            ("text/html; charset=utf-8", self.to_html())
+        } else {
+            (
+                "application/json",
+                serde_json::to_string_pretty(self).unwrap_or_default(),
+            )
+        };
+
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() =
+            StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(content_type),
+        );
+
+        response
+    }
+
+    /// Reads the permissions of the resolved api from its manifest.
+    fn load_permissions(route: &DevRoute, context: &ServerContext) -> Option<Value> {
+        if route.folder.is_empty() {
+            return None;
+        }
+
+        let root_mgr = RootManager::open(
+            &context.setup.config.volume.root,
+            &route.workspace_id,
+            &context.stores,
+        )
+        .ok()?;
+
+        let path: PathBuf = [&route.folder, "api.yaml"].iter().collect();
+        let content = root_mgr.read_file_from_workspace(&path).ok()?;
+        let manifest: serde_yaml::Value = serde_yaml::from_str(&content).ok()?;
+
+        serde_json::to_value(&manifest["permissions"]).ok()
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{} {}</title>\n<style>{}</style>\n</head>\n<body>\n",
+            self.status,
+            escape_html(&self.message),
+            Self::STYLE
+        );
+
+        html.push_str(&format!(
+            "<h1>{} {:?} error</h1>\n<pre class=\"message\">{}</pre>\n",
+            self.status,
+            self.kind,
+            escape_html(&self.message)
+        ));
+
+        if let Some(location) = &self.location {
+            html.push_str(&format!(
+                "<p>at <code>{}</code></p>\n",
+                escape_html(&location.to_string())
+            ));
+        }
+
+        if !self.stack.is_empty() {
+            html.push_str("<h2>Stack</h2>\n<ol>\n");
+            for frame in &self.stack {
+                let class = if frame.in_workspace {
+                    "workspace"
+                } else {
+                    "internal"
+                };
+                html.push_str(&format!(
+                    "<li class=\"{}\"><code>{}</code></li>\n",
+                    class,
+                    escape_html(&frame.to_string())
+                ));
+            }
+            html.push_str("</ol>\n");
+        }
+
+        html.push_str(&format!(
+            "<h2>Route</h2>\n<table>\n<tr><th>Request</th><td>{} {}</td></tr>\n<tr><th>Workspace</th><td>{}</td></tr>\n<tr><th>Api folder</th><td>{}</td></tr>\n</table>\n",
+            escape_html(&self.route.method),
+            escape_html(&self.route.path),
+            escape_html(&self.route.workspace_id),
+            escape_html(&self.route.folder),
+        ));
+
+        if let Some(permissions) = &self.permissions {
+            html.push_str(&format!(
+                "<h2>Permissions</h2>\n<pre>{}</pre>\n",
+                escape_html(&serde_json::to_string_pretty(permissions).unwrap_or_default())
+            ));
+        }
+
+        html.push_str(
+            "<p class=\"note\">Shown because the server runs in dev mode.</p>\n</body>\n</html>\n",
+        );
+        html
+    }
+}
+
+impl DevStackFrame {
+    /// Maps a file of the script runtime to the workspace file it was loaded from.
+    ///
+    /// Modules are loaded as absolute paths from the workspace root, and scripts by their relative path inside a
+    /// wrapper that adds a line above the code. Anything else comes from the runtime itself.
+    fn new(function: Option<String>, file: &str, line: Option<i64>, column: Option<i64>) -> Self {
+        let (file, line, in_workspace) = if let Some(path) = file.strip_prefix("file:///") {
+            (path.to_string(), line, true)
+        } else if file.contains(':') || file.starts_with('(') || file.starts_with('<') {
+            (file.to_string(), line, false)
+        } else {
+            (file.to_string(), line.map(|line| (line - 1).max(1)), true)
+        };
+
+        Self {
+            function,
+            file,
+            line,
+            column,
+            in_workspace,
+        }
+    }
+}
+
+impl fmt::Display for DevStackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(function) = &self.function {
+            write!(f, "{} ", function)?;
+        }
+
+        write!(f, "{}", self.file)?;
+
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
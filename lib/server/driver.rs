@@ -1,75 +1,76 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
-use log::error;
-use std::{cell::RefCell, convert::Infallible, rc::Rc};
-use tokio::{net::TcpStream, sync::mpsc};
-use utilities::{
-    errors::{self},
-    http,
-    hyper::{rt::Executor, server::conn::Http, service::service_fn, Body, Request, Response},
-};
-
-#[derive(Clone)]
-struct LocalExecutor;
+use crate::{Metrics, RuntimeServer, ServerContext};
+use log::debug;
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::io::{AsyncRead, AsyncWrite};
+use utilities::hyper::{server::conn::Http, service::service_fn, Body, Request};
 
 pub struct HttpDriver;
 
+/// The address of the client a request came from.
+///
+/// Added to the extensions of every request the driver receives.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteAddr(pub SocketAddr);
+
 impl HttpDriver {
-    pub async fn drive(
-        tcp_stream: TcpStream,
-        request_tx: mpsc::Sender<Request<Body>>,
-        response_rx: mpsc::Receiver<Response<Body>>,
-    ) {
-        let response_rx = Rc::new(RefCell::new(response_rx));
+    /// Serves the http connection of a plain or TLS stream.
+    ///
+    /// `alpn_protocol` is the protocol negotiated during the TLS handshake. Plain connections speak HTTP/1
+    /// or, if HTTP/2 is enabled, h2c with prior knowledge.
+    ///
+    /// Every request is dispatched to a thread of its own, so the streams of an HTTP/2 connection are handled concurrently.
+    pub async fn drive<S>(
+        stream: S,
+        remote_addr: SocketAddr,
+        alpn_protocol: Option<Vec<u8>>,
+        context: Arc<ServerContext>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let _connection = Metrics::track(&context.metrics.active_connections);
 
-        let end_request_tx = request_tx.clone();
+        let config = &context.config.http2;
 
         // Set up http handling context.
-        Http::new()
-            .with_executor(LocalExecutor)
-            .serve_connection(
-                tcp_stream,
-                service_fn(move |request| {
-                    let request_tx = request_tx.clone();
-                    let response_rx = Rc::clone(&response_rx);
-
-                    async move {
-                        let mut response_rx = response_rx.borrow_mut();
+        let mut http = Http::new();
+        http.http2_max_concurrent_streams(config.max_concurrent_streams)
+            .http2_initial_stream_window_size(config.initial_stream_window_size)
+            .http2_initial_connection_window_size(config.initial_connection_window_size);
 
-                        let mut response =
-                            http::internal_error(errors::new_error("")).as_hyper_response();
+        match alpn_protocol.as_deref() {
+            Some(b"h2") => {
+                http.http2_only(true);
+            }
+            Some(_) => {
+                http.http1_only(true);
+            }
+            None if !config.enabled => {
+                http.http1_only(true);
+            }
+            None => (),
+        }
 
-                        // Send request.
-                        if let Err(err) = request_tx.send(request).await {
-                            error!("{:?}", err);
-                            return Ok(response);
-                        }
+        let result = http
+            .serve_connection(
+                stream,
+                service_fn(move |mut request: Request<Body>| {
+                    let context = Arc::clone(&context);
 
-                        // Wait for response.
-                        if let Some(resp) = response_rx.recv().await {
-                            response = resp;
-                        } else {
-                            error!("no response recieved");
-                        }
+                    async move {
+                        request.extensions_mut().insert(RemoteAddr(remote_addr));
 
-                        Ok::<_, Infallible>(response)
+                        Ok::<_, Infallible>(RuntimeServer::dispatch(request, context).await)
                     }
                 }),
             )
-            .await
-            .expect("serving connection");
-
-        // This signals the handler to end the runtime as response sent is now handled.
-        end_request_tx.send(Request::default()).await.unwrap();
-    }
-}
+            .with_upgrades()
+            .await;
 
-impl<F> Executor<F> for LocalExecutor
-where
-    F: std::future::Future + 'static,
-{
-    fn execute(&self, fut: F) {
-        // This will spawn into the currently running `LocalSet`.
-        tokio::task::spawn_local(fut);
+        // Clients going away mid-request are routine.
+        if let Err(err) = result {
+            debug!("serving connection from {} = {:?}", remote_addr, err);
+        }
     }
 }
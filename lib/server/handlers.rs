@@ -1,5 +1,7 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
 mod api;
+mod websocket;
 
 pub(crate) use api::*;
+pub(crate) use websocket::*;
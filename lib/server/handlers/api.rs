@@ -1,14 +1,20 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
-use crate::runtimes::ApiRuntime;
+use crate::{
+    config::{RateLimitConfig, RateLimitKey},
+    extensions::{self, ResponseSender},
+    root::InvalidWorkspaceId,
+    runtimes::{ApiRuntime, ResolvedApi},
+    Cors, LimitedBody, MisdirectedRequest, PayloadTooLarge, RateLimit, RateLimiter, ResponseCors,
+    ServerContext,
+};
 use std::{rc::Rc, sync::Arc};
 use tokio::sync::mpsc::Sender;
 use utilities::{
     errors::{self, HandlerError, HandlerErrorMessage},
     http,
-    hyper::{Body, Request, Response, StatusCode},
+    hyper::{header, Body, Request, Response, StatusCode},
     result::HandlerResult,
-    setup::CommonSetup,
 };
 
 /// The /api/ route handler.
@@ -19,15 +25,104 @@ impl ApiHandler {
     pub async fn handle(
         request: Request<Body>,
         response_tx: Rc<Sender<Response<Body>>>,
-        setup: Arc<CommonSetup>,
+        context: Arc<ServerContext>,
     ) -> HandlerResult<()> {
+        // Resolve api folder and manifest.
+        let resolved = match Self::resolve(&request, &context, &response_tx).await? {
+            Some(resolved) => resolved,
+            None => return Ok(()),
+        };
+
+        // Apply the api's CORS policy natively.
+        if let Some(config) = &resolved.settings.cors {
+            let cors = Arc::new(Cors::new(config.clone()));
+
+            // Preflight requests are answered without starting a runtime.
+            if Cors::is_preflight(&request) {
+                let response = cors.preflight_response(&request);
+                return Self::send_response(&response_tx, response).await;
+            }
+
+            // The server decorates the response of the request, whoever sends it.
+            if let Some(response_cors) = request.extensions().get::<ResponseCors>() {
+                let origin = request.headers().get(header::ORIGIN).cloned();
+                response_cors.set(cors, origin);
+            }
+        }
+
+        // Enforce the workspace and api rate limits that do not depend on credentials.
+        let (rate_limits, verified_rate_limits) = Self::rate_limits(&request, &resolved);
+        if let Err(limited) = context.rate_limiter.check(&rate_limits) {
+            return Self::send_response(&response_tx, limited.as_hyper_response()).await;
+        }
+
+        // Enforce the request body size limit, before reading the body if possible.
+        let body_limit = resolved
+            .settings
+            .body
+            .max_size
+            .unwrap_or(context.config.max_body_size);
+
+        if let Err(too_large) = LimitedBody::check_content_length(&request, body_limit) {
+            return Self::send_response(&response_tx, too_large.as_hyper_response()).await;
+        }
+
+        let (parts, body) = request.into_parts();
+        let body = LimitedBody::new(body, body_limit);
+
+        let permissions = resolved.manifest.permissions.as_ref();
+        let can_read = permissions.map_or(false, |permissions| permissions.http_event.request_read);
+        let can_respond =
+            permissions.map_or(false, |permissions| permissions.http_event.response_send);
+
+        // Either hand the body to scripts as a stream or buffer it for the http event.
+        // SEC: A streamed body is only handed over if the api may read it, the http event gates buffered ones.
+        let (request, request_body) = if resolved.settings.body.streaming {
+            (
+                Request::from_parts(parts, Body::empty()),
+                can_read.then(|| body),
+            )
+        } else {
+            match body.read_to_end().await {
+                Ok(bytes) => (Request::from_parts(parts, Body::from(bytes)), None),
+                Err(err) => {
+                    if let Some(too_large) = err.downcast_ref::<PayloadTooLarge>() {
+                        let response = too_large.as_hyper_response();
+                        return Self::send_response(&response_tx, response).await;
+                    }
+
+                    return Err(http::internal_error(err));
+                }
+            }
+        };
+
+        // Streamed responses are subject to the same permission as regular ones.
+        let response_sender =
+            can_respond.then(|| Rc::new(ResponseSender::new(Rc::clone(&response_tx))));
+
+        let runtime_extensions = vec![
+            extensions::stream(
+                request_body,
+                can_read,
+                response_sender.clone(),
+                Rc::clone(&response_tx),
+            ),
+            extensions::sse(response_sender, context.config.sse.clone()),
+        ];
+
         // Create api runtime.
-        let mut api_rt = ApiRuntime::new(request, response_tx, setup)
-            .await
-            .map_err(http::internal_error)?;
+        let mut api_rt = ApiRuntime::new(
+            request,
+            resolved,
+            Rc::clone(&response_tx),
+            runtime_extensions,
+            Arc::clone(&context),
+        )
+        .await
+        .map_err(http::internal_error)?;
 
-        // Execute api runtime.
-        if !api_rt.execute().await.map_err(http::internal_error)? {
+        // Execute auth and middlewares.
+        if !api_rt.authorize().await.map_err(http::internal_error)? {
             // If result is false, then one of auth or middleware failed.
             return Err(HandlerError::Client {
                 ctx: HandlerErrorMessage::AuthMiddleware,
@@ -36,6 +131,107 @@ impl ApiHandler {
             });
         }
 
+        // Enforce the rate limits keyed on the credentials the auth script accepted.
+        if let Err(limited) = context.rate_limiter.check(&verified_rate_limits) {
+            return Self::send_response(&response_tx, limited.as_hyper_response()).await;
+        }
+
+        // Run index.
+        api_rt.run_index().await.map_err(http::internal_error)?;
+
         Ok(())
     }
+
+    /// Resolves the api of a request.
+    ///
+    /// Misdirected requests and invalid workspace ids are answered natively, in which case there is nothing left to handle.
+    pub(crate) async fn resolve(
+        request: &Request<Body>,
+        context: &ServerContext,
+        response_tx: &Sender<Response<Body>>,
+    ) -> HandlerResult<Option<ResolvedApi>> {
+        match ResolvedApi::resolve(request, context) {
+            Ok(resolved) => Ok(Some(resolved)),
+            Err(err) => {
+                if let Some(misdirected) = err.downcast_ref::<MisdirectedRequest>() {
+                    let response = misdirected.as_hyper_response();
+                    Self::send_response(response_tx, response).await?;
+                    return Ok(None);
+                }
+
+                if let Some(invalid) = err.downcast_ref::<InvalidWorkspaceId>() {
+                    let response = invalid.as_hyper_response();
+                    Self::send_response(response_tx, response).await?;
+                    return Ok(None);
+                }
+
+                Err(http::internal_error(err))
+            }
+        }
+    }
+
+    /// Gets the workspace and api rate limits of the request's client.
+    ///
+    /// Limits keyed on credentials are returned second, to be checked once the auth script has accepted the
+    /// credentials. The others are keyed on the client's ip address and checked before any script runs.
+    pub(crate) fn rate_limits(
+        request: &Request<Body>,
+        resolved: &ResolvedApi,
+    ) -> (Vec<RateLimit>, Vec<RateLimit>) {
+        let authenticates = resolved.manifest.authentication.enabled;
+        let api_scope = format!("/{}", resolved.relative_folder_path);
+
+        let configs = [
+            ("", &resolved.workspace_config.rate_limit),
+            (api_scope.as_str(), &resolved.settings.rate_limit),
+        ];
+
+        let mut limits = vec![];
+        let mut verified_limits = vec![];
+
+        for (scope, config) in configs.iter() {
+            let config = match config {
+                Some(config) => config,
+                None => continue,
+            };
+
+            let verified = authenticates && config.key != RateLimitKey::Ip;
+            let limit = RateLimit {
+                key: Self::bucket_key(request, resolved, scope, config, verified),
+                config: config.clone(),
+            };
+
+            if verified {
+                verified_limits.push(limit);
+            } else {
+                limits.push(limit);
+            }
+        }
+
+        (limits, verified_limits)
+    }
+
+    /// Gets the key of the bucket that tracks a client's requests to a workspace or one of its apis.
+    fn bucket_key(
+        request: &Request<Body>,
+        resolved: &ResolvedApi,
+        scope: &str,
+        config: &RateLimitConfig,
+        verified: bool,
+    ) -> String {
+        let client = RateLimiter::client_key(request, &config.key, verified);
+
+        // Components are separated by newlines because none of them can contain one.
+        format!("{}\n{}\n{}", resolved.workspace_id, scope, client)
+    }
+
+    /// Sends a response created natively by the handler.
+    pub(crate) async fn send_response(
+        response_tx: &Sender<Response<Body>>,
+        response: Response<Body>,
+    ) -> HandlerResult<()> {
+        response_tx.send(response).await.map_err(|err| {
+            http::internal_error(errors::new_error(format!("sending response: {}", err)))
+        })
+    }
 }
@@ -0,0 +1,194 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use super::ApiHandler;
+use crate::{
+    extensions::{self, WebSocket},
+    runtimes::ApiRuntime,
+    ServerContext,
+};
+use log::error;
+use std::{rc::Rc, sync::Arc, time::Duration};
+use tokio::sync::mpsc::Sender;
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::derive_accept_key,
+        protocol::{Role, WebSocketConfig as ProtocolConfig},
+    },
+    WebSocketStream,
+};
+use utilities::{
+    errors::{self, HandlerError, HandlerErrorMessage},
+    http,
+    hyper::{header, upgrade, Body, Request, Response, StatusCode},
+    result::{Context, HandlerResult},
+};
+
+/// The handler of websocket upgrades to /api/ routes.
+pub struct WebSocketHandler;
+
+impl WebSocketHandler {
+    /// Checks if the request asks to be upgraded to a websocket connection.
+    pub fn is_upgrade(request: &Request<Body>) -> bool {
+        let headers = request.headers();
+
+        let connection_upgrade = headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+
+        let upgrade_websocket = headers
+            .get(header::UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.eq_ignore_ascii_case("websocket"));
+
+        connection_upgrade && upgrade_websocket
+    }
+
+    /// Accepts the upgrade and runs the websocket handler of the api for the lifetime of the connection.
+    pub async fn handle(
+        mut request: Request<Body>,
+        response_tx: Rc<Sender<Response<Body>>>,
+        context: Arc<ServerContext>,
+    ) -> HandlerResult<()> {
+        // Resolve api folder and manifest.
+        let resolved = match ApiHandler::resolve(&request, &context, &response_tx).await? {
+            Some(resolved) => resolved,
+            None => return Ok(()),
+        };
+
+        // SEC: Websocket connections must be explicitly permitted by the api.
+        if !resolved.settings.permissions.http_event.websocket {
+            let response = Self::status_response(StatusCode::FORBIDDEN);
+            return ApiHandler::send_response(&response_tx, response).await;
+        }
+
+        // Enforce the rate limits that do not depend on credentials. A connection counts as one request.
+        let (rate_limits, verified_rate_limits) = ApiHandler::rate_limits(&request, &resolved);
+        if let Err(limited) = context.rate_limiter.check(&rate_limits) {
+            return ApiHandler::send_response(&response_tx, limited.as_hyper_response()).await;
+        }
+
+        // Validate the handshake.
+        let accept_key = match Self::accept_key(&request) {
+            Some(accept_key) => accept_key,
+            None => {
+                let response = Self::status_response(StatusCode::BAD_REQUEST);
+                return ApiHandler::send_response(&response_tx, response).await;
+            }
+        };
+
+        let on_upgrade = upgrade::on(&mut request);
+
+        // Manifest limits take precedence over the server defaults.
+        let server_config = &context.config.websocket;
+        let api_config = &resolved.settings.websocket;
+
+        let max_message_size = api_config
+            .max_message_size
+            .unwrap_or(server_config.max_message_size);
+
+        let idle_timeout = Duration::from_secs(
+            api_config
+                .idle_timeout
+                .unwrap_or(server_config.idle_timeout),
+        );
+
+        let socket = Rc::new(WebSocket::new(max_message_size, idle_timeout));
+
+        // The connection carries no request body.
+        let (parts, _) = request.into_parts();
+        let request = Request::from_parts(parts, Body::empty());
+
+        // Create api runtime.
+        let mut api_rt = ApiRuntime::new(
+            request,
+            resolved,
+            Rc::clone(&response_tx),
+            vec![extensions::websocket(Rc::clone(&socket))],
+            Arc::clone(&context),
+        )
+        .await
+        .map_err(http::internal_error)?;
+
+        // Auth and middlewares decide whether the upgrade is accepted.
+        if !api_rt.authorize().await.map_err(http::internal_error)? {
+            return Err(HandlerError::Client {
+                ctx: HandlerErrorMessage::AuthMiddleware,
+                code: StatusCode::UNAUTHORIZED,
+                src: errors::new_error("one of authorisation or middleware failed"),
+            });
+        }
+
+        // Enforce the rate limits keyed on the credentials the auth script accepted.
+        if let Err(limited) = context.rate_limiter.check(&verified_rate_limits) {
+            return ApiHandler::send_response(&response_tx, limited.as_hyper_response()).await;
+        }
+
+        // Accept the upgrade.
+        let response = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_ACCEPT, accept_key)
+            .body(Body::empty())
+            .context("building switching protocols response")
+            .map_err(http::internal_error)?;
+
+        ApiHandler::send_response(&response_tx, response).await?;
+
+        let upgraded = match on_upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                // The switching protocols response has been sent, so there is nobody left to respond to.
+                error!("upgrading to websocket = {:?}", err);
+                return Ok(());
+            }
+        };
+
+        let protocol_config = ProtocolConfig {
+            max_message_size: Some(max_message_size),
+            max_frame_size: Some(max_message_size),
+            ..Default::default()
+        };
+
+        socket
+            .attach(
+                WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(protocol_config))
+                    .await,
+            )
+            .await;
+
+        // Run the handler until it has nothing left to do.
+        if let Err(err) = api_rt.run_websocket_index().await {
+            error!("running websocket handler = {:?}", err);
+            let _ = socket.close(1011, "internal error").await;
+        }
+
+        // The handler may have returned without closing the connection.
+        let _ = socket.close(1000, "").await;
+
+        Ok(())
+    }
+
+    /// Gets the accept key for a valid version 13 handshake.
+    fn accept_key(request: &Request<Body>) -> Option<String> {
+        let headers = request.headers();
+
+        let version = headers.get(header::SEC_WEBSOCKET_VERSION)?;
+        if version != "13" {
+            return None;
+        }
+
+        let key = headers.get(header::SEC_WEBSOCKET_KEY)?;
+
+        Some(derive_accept_key(key.as_bytes()))
+    }
+
+    fn status_response(status: StatusCode) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = status;
+        response
+    }
+}
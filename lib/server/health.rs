@@ -0,0 +1,82 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{config::StoreKind, ServerContext};
+use serde_json::json;
+use std::sync::atomic::Ordering;
+use tokio::fs;
+use utilities::hyper::{header, Body, Method, Request, Response, StatusCode};
+
+/// Answers the probes of orchestrators without starting a runtime.
+///
+/// - `/_health/live` succeeds as long as the server accepts requests.
+/// - `/_health/ready` fails while the volume root is inaccessible, the server handles too many requests at once or it is shutting down.
+pub struct Health;
+
+impl Health {
+    pub const LIVE_PATH: &'static str = "/_health/live";
+    pub const READY_PATH: &'static str = "/_health/ready";
+
+    /// Responds to a health request. Returns `None` for any other request.
+    pub async fn respond(
+        request: &Request<Body>,
+        context: &ServerContext,
+    ) -> Option<Response<Body>> {
+        if request.method() != Method::GET && request.method() != Method::HEAD {
+            return None;
+        }
+
+        match request.uri().path() {
+            Self::LIVE_PATH => Some(Self::response(true, json!({}))),
+            Self::READY_PATH => Some(Self::ready(context).await),
+            _ => None,
+        }
+    }
+
+    async fn ready(context: &ServerContext) -> Response<Body> {
+        // Workspaces in memory do not need the volume.
+        let volume = context.config.store == StoreKind::Memory
+            || fs::metadata(&context.setup.config.volume.root)
+                .await
+                .is_ok();
+
+        let active_threads = context.metrics.active_threads.get();
+        let workers = active_threads < context.config.health.max_active_threads as i64;
+
+        let shutting_down = context.shutting_down.load(Ordering::Relaxed);
+
+        Self::response(
+            volume && workers && !shutting_down,
+            json!({
+                "volume": volume,
+                "workers": workers,
+                "active_threads": active_threads,
+                "shutting_down": shutting_down,
+            }),
+        )
+    }
+
+    fn response(ok: bool, checks: serde_json::Value) -> Response<Body> {
+        let body = json!({
+            "status": if ok { "ok" } else { "unavailable" },
+            "checks": checks,
+        });
+
+        let mut response = Response::new(Body::from(body.to_string()));
+
+        if !ok {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+        headers.insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static("no-store"),
+        );
+
+        response
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::config::{HostMapping, HostPrecedence, HostRoutingConfig};
+use std::{collections::HashMap, error::Error, fmt};
+use utilities::{
+    http,
+    hyper::{header, Body, Request, Response, StatusCode},
+    result::Result,
+};
+
+/// Maps requests to workspace ids by their `Host` header or the workspace id header.
+pub struct HostRouter {
+    precedence: HostPrecedence,
+    hosts: HashMap<String, String>,
+    subdomains_of: Vec<String>,
+}
+
+/// The error of a request whose workspace cannot be determined.
+#[derive(Debug)]
+pub struct MisdirectedRequest {
+    pub host: Option<String>,
+}
+
+impl HostRouter {
+    /// Creates a host router from the host routing config and its mapping file.
+    pub fn new(config: &HostRoutingConfig) -> Result<Self> {
+        let mapping = HostMapping::load(config)?;
+
+        // Hosts are case-insensitive.
+        let hosts = mapping
+            .hosts
+            .into_iter()
+            .map(|(host, workspace_id)| (host.to_ascii_lowercase(), workspace_id))
+            .collect();
+
+        let subdomains_of = mapping
+            .subdomains_of
+            .iter()
+            .map(|domain| format!(".{}", domain.trim_start_matches('.').to_ascii_lowercase()))
+            .collect();
+
+        Ok(Self {
+            precedence: config.precedence.clone(),
+            hosts,
+            subdomains_of,
+        })
+    }
+
+    /// Gets the workspace id of a request according to the precedence rules.
+    pub fn workspace_id(&self, request: &Request<Body>) -> Result<String> {
+        let header_id = http::get_header_value(request, http::WORKSPACE_ID_HEADER)
+            .ok()
+            .filter(|id| !id.is_empty());
+        let host = Self::host(request);
+        let host_id = host.as_deref().and_then(|host| self.lookup(host));
+
+        let workspace_id = match self.precedence {
+            HostPrecedence::Header => header_id.or(host_id),
+            HostPrecedence::Host => host_id.or(header_id),
+        };
+
+        workspace_id.ok_or_else(|| MisdirectedRequest { host }.into())
+    }
+
+    /// Finds the workspace id a host maps to, trying custom domains before subdomains.
+    fn lookup(&self, host: &str) -> Option<String> {
+        if let Some(workspace_id) = self.hosts.get(host) {
+            return Some(workspace_id.clone());
+        }
+
+        self.subdomains_of.iter().find_map(|suffix| {
+            let label = host.strip_suffix(suffix.as_str())?;
+
+            // Only direct subdomains map to workspaces.
+            (!label.is_empty() && !label.contains('.')).then(|| label.to_string())
+        })
+    }
+
+    /// Gets the lowercased host of a request without its port.
+    ///
+    /// HTTP/2 requests carry it in the uri authority instead of the `Host` header.
+    fn host(request: &Request<Body>) -> Option<String> {
+        let host = match request.headers().get(header::HOST) {
+            Some(value) => value.to_str().ok()?,
+            None => request.uri().host()?,
+        };
+
+        let host = match host.rsplit_once(':') {
+            // Bracketed IPv6 addresses contain colons of their own.
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+
+        Some(host.to_ascii_lowercase())
+    }
+}
+
+impl MisdirectedRequest {
+    /// Creates a `421 Misdirected Request` response.
+    pub fn as_hyper_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.to_string()));
+        *response.status_mut() = StatusCode::MISDIRECTED_REQUEST;
+        response
+    }
+}
+
+impl fmt::Display for MisdirectedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.host {
+            Some(host) => write!(f, r#"host "{}" is not mapped to a workspace"#, host),
+            None => write!(f, "request has neither a host nor a workspace id"),
+        }
+    }
+}
+
+impl Error for MisdirectedRequest {}
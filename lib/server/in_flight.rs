@@ -0,0 +1,176 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{ApiLabels, RequestId};
+use deno_core::v8::IsolateHandle;
+use futures::future::AbortHandle;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+use utilities::hyper::Method;
+
+/// The requests the server is handling, so they can be inspected and killed from the admin listener.
+#[derive(Default)]
+pub struct InFlightRequests {
+    next_id: AtomicU64,
+    requests: Mutex<BTreeMap<u64, Arc<InFlightRequest>>>,
+}
+
+/// A request the server is handling.
+///
+/// Added to the extensions of every request by the server. It stays registered until the thread of the request is done,
+/// which includes streamed bodies and upgraded connections.
+pub struct InFlightRequest {
+    pub id: u64,
+    pub request_id: RequestId,
+    pub method: Method,
+    pub path: String,
+    pub labels: ApiLabels,
+    pub started: Instant,
+    phase: Mutex<RequestPhase>,
+    abort_handle: AbortHandle,
+    isolate_handle: Mutex<Option<IsolateHandle>>,
+    killed: AtomicBool,
+}
+
+/// What a request is busy with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestPhase {
+    Routing,
+    RuntimeCreation,
+    Auth,
+    Middlewares,
+    Index,
+}
+
+/// Unregisters a request when dropped.
+pub struct InFlightGuard {
+    registry: Arc<InFlightRequests>,
+    id: u64,
+}
+
+impl InFlightRequests {
+    /// Registers a request that gets stopped through `abort_handle` when killed.
+    pub fn register(
+        self: &Arc<Self>,
+        request_id: RequestId,
+        method: Method,
+        path: String,
+        labels: ApiLabels,
+        abort_handle: AbortHandle,
+    ) -> (Arc<InFlightRequest>, InFlightGuard) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let request = Arc::new(InFlightRequest {
+            id,
+            request_id,
+            method,
+            path,
+            labels,
+            started: Instant::now(),
+            phase: Mutex::new(RequestPhase::Routing),
+            abort_handle,
+            isolate_handle: Mutex::new(None),
+            killed: AtomicBool::new(false),
+        });
+
+        self.requests
+            .lock()
+            .unwrap()
+            .insert(id, Arc::clone(&request));
+
+        let guard = InFlightGuard {
+            registry: Arc::clone(self),
+            id,
+        };
+
+        (request, guard)
+    }
+
+    /// Gets the requests being handled, from the oldest to the newest.
+    pub fn list(&self) -> Vec<Arc<InFlightRequest>> {
+        self.requests.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Kills a request. Returns false if there is no such request.
+    pub fn kill(&self, id: u64) -> bool {
+        match self.requests.lock().unwrap().get(&id) {
+            Some(request) => {
+                request.kill();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl InFlightRequest {
+    pub fn set_phase(&self, phase: RequestPhase) {
+        *self.phase.lock().unwrap() = phase;
+    }
+
+    pub fn phase(&self) -> RequestPhase {
+        *self.phase.lock().unwrap()
+    }
+
+    /// Sets the isolate the scripts of the request run in, so that killing the request can interrupt them.
+    pub fn set_isolate_handle(&self, isolate_handle: IsolateHandle) {
+        *self.isolate_handle.lock().unwrap() = Some(isolate_handle);
+    }
+
+    /// Stops handling the request.
+    ///
+    /// Its future is dropped at the next await point and a script that is busy running is terminated.
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+        self.abort_handle.abort();
+
+        if let Some(isolate_handle) = &*self.isolate_handle.lock().unwrap() {
+            isolate_handle.terminate_execution();
+        }
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+    }
+
+    /// Describes the request for the admin listener.
+    pub fn to_json(&self) -> Value {
+        let (workspace_id, folder) = self.labels.get();
+
+        json!({
+            "id": self.id,
+            "request_id": self.request_id.0,
+            "method": self.method.as_str(),
+            "path": self.path,
+            "workspace": workspace_id,
+            "folder": folder,
+            "phase": self.phase().as_str(),
+            "age_ms": self.started.elapsed().as_millis() as u64,
+            "killed": self.is_killed(),
+        })
+    }
+}
+
+impl RequestPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestPhase::Routing => "routing",
+            RequestPhase::RuntimeCreation => "runtime_creation",
+            RequestPhase::Auth => "auth",
+            RequestPhase::Middlewares => "middlewares",
+            RequestPhase::Index => "index",
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.registry.requests.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::AccessLog;
+use env_logger::filter::{self, Filter};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use utilities::result::{Context, Result};
+
+/// The server logger. Writes JSON lines filtered by `RUST_LOG`, unless a level is set at runtime.
+pub struct RuntimeLogger {
+    filter: Filter,
+    inner: env_logger::Logger,
+}
+
+/// The level set at runtime, as `LevelFilter as usize + 1`, or `0` if `RUST_LOG` applies.
+static LEVEL_OVERRIDE: AtomicUsize = AtomicUsize::new(0);
+
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+impl RuntimeLogger {
+    /// Installs the logger as the global logger.
+    pub fn init() -> Result<()> {
+        let filter = filter::Builder::from_env(env_logger::DEFAULT_FILTER_ENV).build();
+
+        // Records are filtered here so the level can change, the inner logger lets everything through.
+        let inner = env_logger::Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .format(AccessLog::format)
+            .build();
+
+        log::set_max_level(filter.filter());
+        log::set_boxed_logger(Box::new(Self { filter, inner })).context("installing logger")?;
+
+        Ok(())
+    }
+
+    /// Sets the level of all records, or goes back to `RUST_LOG` with `None`.
+    pub fn set_level(level: Option<LevelFilter>) {
+        let value = level.map_or(0, |level| level as usize + 1);
+        LEVEL_OVERRIDE.store(value, Ordering::Relaxed);
+
+        // The filter of `RUST_LOG` can be more verbose for some targets than its default level.
+        log::set_max_level(level.unwrap_or(LevelFilter::Trace));
+    }
+
+    /// Gets the level set at runtime, if any.
+    pub fn level() -> Option<LevelFilter> {
+        match LEVEL_OVERRIDE.load(Ordering::Relaxed) {
+            0 => None,
+            value => LEVELS.get(value - 1).copied(),
+        }
+    }
+}
+
+impl Log for RuntimeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match Self::level() {
+            Some(level) => metadata.level() <= level,
+            None => self.filter.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use utilities::result::{Context, Result};
+
+/// The metrics of the runtime server, rendered in the Prometheus text format on the admin listener.
+pub struct Metrics {
+    registry: Registry,
+    /// Requests by workspace, api path and response status.
+    pub requests: IntCounterVec,
+    /// Seconds until the response head was sent, by workspace and api path.
+    pub request_duration: HistogramVec,
+    /// Seconds taken to create the JavaScript runtime of a request.
+    pub runtime_construction: Histogram,
+    /// Seconds taken by each script phase of a request, by workspace and phase.
+    pub script_phase_duration: HistogramVec,
+    /// Requests denied because a script lacked a permission, by workspace.
+    pub permission_denials: IntCounterVec,
+    /// Panics caught while accepting connections.
+    pub panics: IntCounter,
+    /// Threads currently handling a request.
+    pub active_threads: IntGauge,
+    /// Client connections currently open.
+    pub active_connections: IntGauge,
+}
+
+/// The workspace and api path a request resolved to, filled in once the request is resolved.
+///
+/// Added to the extensions of every request by the server so that metrics can be labelled after the fact.
+#[derive(Debug, Default, Clone)]
+pub struct ApiLabels(Arc<Mutex<Option<(String, String)>>>);
+
+/// The phases of running the scripts of an api.
+#[derive(Debug, Clone, Copy)]
+pub enum ScriptPhase {
+    Auth,
+    Middlewares,
+    Index,
+}
+
+/// Decrements a gauge when dropped.
+pub struct GaugeGuard(IntGauge);
+
+impl Metrics {
+    /// Creates and registers the metrics.
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("gigamono_runtime".to_string()), None)
+            .context("creating metrics registry")?;
+
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "requests_total",
+                "Requests by workspace, api path and status.",
+            ),
+            &["workspace", "path", "status"],
+        )
+        .context("creating requests metric")?;
+
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "request_duration_seconds",
+                "Seconds until the response head was sent.",
+            ),
+            &["workspace", "path"],
+        )
+        .context("creating request duration metric")?;
+
+        let runtime_construction = Histogram::with_opts(HistogramOpts::new(
+            "runtime_construction_seconds",
+            "Seconds taken to create the JavaScript runtime of a request.",
+        ))
+        .context("creating runtime construction metric")?;
+
+        let script_phase_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "script_phase_duration_seconds",
+                "Seconds taken by the auth, middlewares and index script phases.",
+            ),
+            &["workspace", "phase"],
+        )
+        .context("creating script phase metric")?;
+
+        let permission_denials = IntCounterVec::new(
+            Opts::new(
+                "permission_denials_total",
+                "Requests denied because a script lacked a permission.",
+            ),
+            &["workspace"],
+        )
+        .context("creating permission denials metric")?;
+
+        let panics = IntCounter::new(
+            "panics_total",
+            "Panics caught while serving connections and requests.",
+        )
+        .context("creating panics metric")?;
+
+        let active_threads =
+            IntGauge::new("active_threads", "Threads currently handling a request.")
+                .context("creating active threads metric")?;
+
+        let active_connections =
+            IntGauge::new("active_connections", "Client connections currently open.")
+                .context("creating active connections metric")?;
+
+        registry
+            .register(Box::new(requests.clone()))
+            .and_then(|_| registry.register(Box::new(request_duration.clone())))
+            .and_then(|_| registry.register(Box::new(runtime_construction.clone())))
+            .and_then(|_| registry.register(Box::new(script_phase_duration.clone())))
+            .and_then(|_| registry.register(Box::new(permission_denials.clone())))
+            .and_then(|_| registry.register(Box::new(panics.clone())))
+            .and_then(|_| registry.register(Box::new(active_threads.clone())))
+            .and_then(|_| registry.register(Box::new(active_connections.clone())))
+            .context("registering metrics")?;
+
+        Ok(Self {
+            registry,
+            requests,
+            request_duration,
+            runtime_construction,
+            script_phase_duration,
+            permission_denials,
+            panics,
+            active_threads,
+            active_connections,
+        })
+    }
+
+    /// Records a request once its response head is ready.
+    pub fn observe_request(&self, labels: &ApiLabels, status: u16, duration: Duration) {
+        let (workspace_id, path) = labels.get();
+
+        self.requests
+            .with_label_values(&[&workspace_id, &path, &status.to_string()])
+            .inc();
+
+        self.request_duration
+            .with_label_values(&[&workspace_id, &path])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Records the duration of a script phase.
+    pub fn observe_script_phase(&self, workspace_id: &str, phase: ScriptPhase, duration: Duration) {
+        self.script_phase_duration
+            .with_label_values(&[workspace_id, phase.as_str()])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Increments a gauge until the returned guard is dropped.
+    pub fn track(gauge: &IntGauge) -> GaugeGuard {
+        gauge.inc();
+        GaugeGuard(gauge.clone())
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<(String, Vec<u8>)> {
+        let encoder = TextEncoder::new();
+        let mut buffer = vec![];
+
+        encoder
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("encoding metrics")?;
+
+        Ok((encoder.format_type().to_string(), buffer))
+    }
+}
+
+impl ApiLabels {
+    /// Sets the workspace and api path of the request.
+    pub fn set(&self, workspace_id: &str, path: &str) {
+        *self.0.lock().unwrap() = Some((workspace_id.to_string(), path.to_string()));
+    }
+
+    /// Gets the workspace and api path of the request, both empty if it was never resolved.
+    pub fn get(&self) -> (String, String) {
+        self.0.lock().unwrap().clone().unwrap_or_default()
+    }
+}
+
+impl ScriptPhase {
+    /// Gets the label of the phase.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScriptPhase::Auth => "auth",
+            ScriptPhase::Middlewares => "middlewares",
+            ScriptPhase::Index => "index",
+        }
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{
+    config::{RateLimitConfig, RateLimitKey},
+    RemoteAddr,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Instant,
+};
+use utilities::hyper::{
+    header::{self, HeaderValue},
+    Body, Request, Response, StatusCode,
+};
+
+/// A token-bucket rate limiter whose state is shared by all worker threads.
+///
+/// The number of buckets is capped, evicting the least recently used ones first.
+pub struct RateLimiter {
+    max_buckets: usize,
+    buckets: Mutex<Buckets>,
+}
+
+/// A rate limit and the key of the bucket it is tracked in.
+pub struct RateLimit {
+    pub key: String,
+    pub config: RateLimitConfig,
+}
+
+/// The state of the rate limit a request exceeded.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub limit: u64,
+    pub retry_after: u64,
+    pub reset: u64,
+}
+
+/// Buckets by key, along with the order they were last used in.
+#[derive(Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    by_use: BTreeMap<u64, String>,
+    uses: u64,
+}
+
+struct Bucket {
+    config: RateLimitConfig,
+    tokens: f64,
+    updated: Instant,
+    last_use: u64,
+}
+
+impl RateLimiter {
+    /// The default maximum number of buckets.
+    pub const MAX_BUCKETS: usize = 100_000;
+
+    /// Creates a rate limiter that tracks at most `max_buckets` clients and scopes at a time.
+    pub fn new(max_buckets: usize) -> Self {
+        Self {
+            max_buckets: max_buckets.max(1),
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    /// Takes a token from the bucket of every limit or none at all if any of the buckets is empty.
+    pub fn check(&self, limits: &[RateLimit]) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        for limit in limits {
+            let bucket = buckets.touch(&limit.key, &limit.config, now, self.max_buckets);
+
+            // Start over if the limit has been reconfigured.
+            if bucket.config != limit.config {
+                let last_use = bucket.last_use;
+                *bucket = Bucket::new(&limit.config, now, last_use);
+            }
+
+            bucket.refill(now);
+
+            if bucket.tokens < 1.0 {
+                return Err(RateLimited {
+                    limit: bucket.config.capacity,
+                    retry_after: bucket.secs_until(1.0),
+                    reset: bucket.secs_until(bucket.config.capacity as f64),
+                });
+            }
+        }
+
+        for limit in limits {
+            if let Some(bucket) = buckets.by_key.get_mut(&limit.key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the value that identifies the client of a request.
+    ///
+    /// SEC: Header values are chosen by the client, so a fresh value per request would get a fresh bucket. They are
+    /// only used once the auth script of the api has accepted them, as `verified` says, and the ip address is used
+    /// until then. Values are hashed so that credentials are not kept around.
+    pub fn client_key(request: &Request<Body>, key: &RateLimitKey, verified: bool) -> String {
+        let header_name = match key {
+            RateLimitKey::Header(name) if verified => Some(name.as_str()),
+            RateLimitKey::Principal if verified => Some(header::AUTHORIZATION.as_str()),
+            _ => None,
+        };
+
+        let header_value = header_name.and_then(|name| request.headers().get(name));
+
+        if let Some(value) = header_value {
+            let digest = Sha256::digest(value.as_bytes());
+            return format!("{:?}:{}", key, hex::encode(digest));
+        }
+
+        // Fall back to the client's ip address.
+        match request.extensions().get::<RemoteAddr>() {
+            Some(RemoteAddr(addr)) => format!("ip:{}", addr.ip()),
+            None => String::from("ip:unknown"),
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Self::MAX_BUCKETS)
+    }
+}
+
+impl Buckets {
+    /// Gets the bucket of a key, creating it if needed, and marks it as the most recently used one.
+    fn touch(
+        &mut self,
+        key: &str,
+        config: &RateLimitConfig,
+        now: Instant,
+        max_buckets: usize,
+    ) -> &mut Bucket {
+        self.uses += 1;
+        let last_use = self.uses;
+
+        if let Some(bucket) = self.by_key.get_mut(key) {
+            self.by_use.remove(&bucket.last_use);
+            bucket.last_use = last_use;
+        } else {
+            // Make room for the new bucket.
+            while self.by_key.len() >= max_buckets {
+                let oldest = match self.by_use.keys().next() {
+                    Some(oldest) => *oldest,
+                    None => break,
+                };
+
+                if let Some(evicted) = self.by_use.remove(&oldest) {
+                    self.by_key.remove(&evicted);
+                }
+            }
+
+            self.by_key
+                .insert(key.to_string(), Bucket::new(config, now, last_use));
+        }
+
+        self.by_use.insert(last_use, key.to_string());
+        self.by_key.get_mut(key).unwrap()
+    }
+}
+
+impl RateLimited {
+    /// Creates a `429 Too Many Requests` response with `Retry-After` and `RateLimit-*` headers.
+    pub fn as_hyper_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from("too many requests"));
+        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+
+        let headers = response.headers_mut();
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(self.retry_after));
+        headers.insert("RateLimit-Limit", HeaderValue::from(self.limit));
+        headers.insert("RateLimit-Remaining", HeaderValue::from(0));
+        headers.insert("RateLimit-Reset", HeaderValue::from(self.reset));
+
+        response
+    }
+}
+
+impl Bucket {
+    fn new(config: &RateLimitConfig, now: Instant, last_use: u64) -> Self {
+        Self {
+            config: config.clone(),
+            tokens: config.capacity as f64,
+            updated: now,
+            last_use,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.config.refill_per_second)
+            .min(self.config.capacity as f64);
+        self.updated = now;
+    }
+
+    /// Gets the number of seconds until the bucket holds the given number of tokens.
+    fn secs_until(&self, tokens: f64) -> u64 {
+        if self.config.refill_per_second <= 0.0 {
+            return u64::MAX;
+        }
+
+        ((tokens - self.tokens).max(0.0) / self.config.refill_per_second).ceil() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(capacity: u64, refill_per_second: f64) -> RateLimitConfig {
+        RateLimitConfig {
+            capacity,
+            refill_per_second,
+            key: RateLimitKey::Ip,
+        }
+    }
+
+    fn limit(key: &str, config: &RateLimitConfig) -> RateLimit {
+        RateLimit {
+            key: key.to_string(),
+            config: config.clone(),
+        }
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::builder().uri("/api/users");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        let mut request = builder.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(RemoteAddr("10.0.0.1:4000".parse().unwrap()));
+
+        request
+    }
+
+    #[test]
+    fn bucket_refills_up_to_capacity() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(&config(4, 2.0), now, 0);
+        bucket.tokens = 0.0;
+
+        bucket.refill(now + Duration::from_millis(500));
+        assert_eq!(bucket.tokens, 1.0);
+        assert_eq!(bucket.secs_until(4.0), 2);
+
+        bucket.refill(now + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 4.0);
+    }
+
+    #[test]
+    fn empty_bucket_rejects_requests() {
+        let rate_limiter = RateLimiter::default();
+        let config = config(2, 0.0);
+
+        assert!(rate_limiter.check(&[limit("a", &config)]).is_ok());
+        assert!(rate_limiter.check(&[limit("a", &config)]).is_ok());
+
+        let limited = rate_limiter.check(&[limit("a", &config)]).unwrap_err();
+        assert_eq!(limited.limit, 2);
+        assert_eq!(limited.retry_after, u64::MAX);
+    }
+
+    #[test]
+    fn unverified_headers_do_not_get_buckets_of_their_own() {
+        let key = RateLimitKey::Header("x-api-key".to_string());
+
+        let first = RateLimiter::client_key(&request(&[("x-api-key", "one")]), &key, false);
+        let second = RateLimiter::client_key(&request(&[("x-api-key", "two")]), &key, false);
+        assert_eq!(first, "ip:10.0.0.1");
+        assert_eq!(first, second);
+
+        let principal = RateLimiter::client_key(
+            &request(&[("authorization", "one")]),
+            &RateLimitKey::Principal,
+            false,
+        );
+        assert_eq!(principal, "ip:10.0.0.1");
+    }
+
+    #[test]
+    fn verified_credentials_are_hashed() {
+        let key = RateLimiter::client_key(
+            &request(&[("authorization", "Bearer secret")]),
+            &RateLimitKey::Principal,
+            true,
+        );
+
+        assert!(key.starts_with("Principal:"));
+        assert!(!key.contains("secret"));
+
+        let other = RateLimiter::client_key(
+            &request(&[("authorization", "Bearer other")]),
+            &RateLimitKey::Principal,
+            true,
+        );
+        assert_ne!(key, other);
+    }
+
+    #[test]
+    fn least_recently_used_bucket_is_evicted() {
+        let rate_limiter = RateLimiter::new(2);
+        let config = config(1, 0.0);
+
+        assert!(rate_limiter.check(&[limit("a", &config)]).is_ok());
+        assert!(rate_limiter.check(&[limit("b", &config)]).is_ok());
+
+        // Using `a` again makes `b` the least recently used bucket.
+        assert!(rate_limiter.check(&[limit("a", &config)]).is_err());
+        assert!(rate_limiter.check(&[limit("c", &config)]).is_ok());
+
+        let buckets = rate_limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), 2);
+        assert_eq!(buckets.by_use.len(), 2);
+        assert!(buckets.by_key.contains_key("a"));
+        assert!(!buckets.by_key.contains_key("b"));
+    }
+}
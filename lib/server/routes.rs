@@ -1,34 +1,48 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
-use crate::handlers::ApiHandler;
+use crate::{
+    handlers::{ApiHandler, WebSocketHandler},
+    ServerContext,
+};
 use std::{rc::Rc, sync::Arc};
 use tokio::sync::mpsc::Sender;
 use utilities::{
     errors::{self, HandlerError, HandlerErrorMessage},
     hyper::{Body, Request, Response, StatusCode},
     result::HandlerResult,
-    setup::CommonSetup,
 };
 
 pub struct Router;
 
 impl Router {
     pub async fn route(
-        request: Request<Body>,
+        mut request: Request<Body>,
         response_tx: Rc<Sender<Response<Body>>>,
-        setup: Arc<CommonSetup>,
+        context: Arc<ServerContext>,
     ) -> HandlerResult<()> {
+        // Continues the trace of the caller. Spans started while handling the request are its children.
+        let mut span = context.tracer.start_request(&request);
+        request.extensions_mut().insert(span.as_parent());
+
         let path = request.uri().path();
 
         // Routing.
-        if path.starts_with("/api/") {
-            ApiHandler::handle(request, response_tx, setup).await
+        let result = if path.starts_with("/api/") && WebSocketHandler::is_upgrade(&request) {
+            WebSocketHandler::handle(request, response_tx, context).await
+        } else if path.starts_with("/api/") {
+            ApiHandler::handle(request, response_tx, context).await
         } else {
             Err(HandlerError::Client {
                 ctx: HandlerErrorMessage::NotFound,
                 code: StatusCode::NOT_FOUND,
                 src: errors::new_error(format!(r#"resource not found "{}""#, path)),
             })
+        };
+
+        if let Err(err) = &result {
+            span.set_error(&format!("{:?}", err.system_error()));
         }
+
+        result
     }
 }
@@ -1,18 +1,30 @@
 // Copyright 2021 the Gigamono authors. All rights reserved. GPL-3.0 License.
 
-use crate::{HttpDriver, Router};
-use futures::{Future, FutureExt};
+use crate::{
+    config::RuntimeConfig, AccessLog, AdminServer, ApiLabels, DevErrorPage, DevRequest, Health,
+    HttpDriver, Metrics, RequestId, ResponseCors, Router, ServerContext, TlsTerminator,
+};
+use futures::{
+    future::{AbortHandle, Abortable},
+    Future, FutureExt,
+};
 use log::{error, info};
 use std::rc::Rc;
 use std::thread;
-use std::{panic::AssertUnwindSafe, sync::Arc};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
 use tera::errors::JsError;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::runtime::Builder;
+use tokio::signal::{self, unix::SignalKind};
 use tokio::sync::mpsc::{self, Sender};
 use tokio::task::LocalSet;
+use tokio::time;
 use utilities::errors::{self, HandlerError, HandlerErrorMessage};
-use utilities::hyper::{Body, Request, Response, StatusCode};
+use utilities::hyper::{header::HeaderValue, Body, Request, Response, StatusCode};
 use utilities::result::HandlerResult;
 use utilities::{http, ip};
 use utilities::{
@@ -21,77 +33,307 @@ use utilities::{
 };
 
 pub struct RuntimeServer {
-    setup: Arc<CommonSetup>,
+    context: Arc<ServerContext>,
+    tls: Option<Arc<TlsTerminator>>,
+    admin: Option<Arc<AdminServer>>,
 }
 
 impl RuntimeServer {
-    pub fn new(setup: Arc<CommonSetup>) -> Self {
-        Self { setup }
+    /// Creates a new runtime server, loading its TLS certificates if TLS is enabled and its admin token if the admin listener is.
+    ///
+    /// Fails if dev mode is enabled and the server does not bind to a loopback address.
+    pub fn new(setup: Arc<CommonSetup>, config: RuntimeConfig) -> Result<Self> {
+        // SEC: Dev error pages show workspace code and permissions, so they must not reach other hosts.
+        if config.dev {
+            let addr = ip::parse_socket_address(&setup.config.engines.runtime.socket_address)?;
+            if !addr.ip().is_loopback() {
+                return errors::new_error_t(format!(
+                    "dev mode is only allowed on loopback addresses, not {}",
+                    addr
+                ));
+            }
+        }
+
+        let tls = match &config.tls {
+            Some(tls_config) => {
+                let mut tls_config = tls_config.clone();
+
+                // h2 must not be offered if the server cannot speak it.
+                if !config.http2.enabled {
+                    tls_config
+                        .alpn_protocols
+                        .retain(|protocol| protocol != "h2");
+                }
+
+                Some(Arc::new(TlsTerminator::new(tls_config)?))
+            }
+            None => None,
+        };
+
+        let admin_config = config.admin.clone();
+        let context = Arc::new(ServerContext::new(setup, config)?);
+
+        let admin = match admin_config {
+            Some(admin_config) => Some(Arc::new(AdminServer::new(
+                Arc::clone(&context),
+                admin_config,
+            )?)),
+            None => None,
+        };
+
+        Ok(Self {
+            context,
+            tls,
+            admin,
+        })
     }
 
+    /// Accepts client connections until the server is told to shut down.
+    ///
+    /// On `SIGTERM` or `SIGINT`, the server reports itself as not ready and keeps serving for the shutdown delay before returning.
     pub async fn listen(&self) -> Result<()> {
         // Get socket address.
-        let addr = ip::parse_socket_address(&self.setup.config.engines.runtime.socket_address)?;
+        let addr =
+            ip::parse_socket_address(&self.context.setup.config.engines.runtime.socket_address)?;
 
         info!(r#"Socket address = "{}""#, addr);
 
         // Bind to address.
         let tcp_listener = TcpListener::bind(addr).await.unwrap();
 
-        // Accept client connections infinitely
+        // Pick up renewed certificates without restarting.
+        if let Some(tls) = &self.tls {
+            tokio::spawn(Arc::clone(tls).watch());
+        }
+
+        tokio::spawn(Arc::clone(&self.context.tracer).export());
+
+        if let Some(admin) = &self.admin {
+            let admin = Arc::clone(admin);
+            tokio::spawn(async move {
+                if let Err(err) = admin.listen().await {
+                    error!("admin listener = {:?}", err);
+                }
+            });
+        }
+
+        let shutdown = Self::shutdown_signal();
+        tokio::pin!(shutdown);
+
+        // Accept client connections until a shutdown signal.
+        loop {
+            tokio::select! {
+                result = &mut shutdown => {
+                    result?;
+                    break;
+                }
+                // Handle connection and catch panics.
+                _ = self.connection_panic_wrap(Self::accept_connection, &tcp_listener) => (),
+            }
+        }
+
+        self.context.shutting_down.store(true, Ordering::Relaxed);
+
+        let delay = Duration::from_secs(self.context.config.health.shutdown_delay);
+
+        info!("Shutting down in {:?}", delay);
+
+        // Keep serving while load balancers notice the server is not ready.
+        let deadline = time::sleep(delay);
+        tokio::pin!(deadline);
+
         loop {
-            // Handle connection and catch panics.
-            self.connection_panic_wrap(Self::accept_connection, &tcp_listener)
-                .await
+            tokio::select! {
+                _ = &mut deadline => return Ok(()),
+                _ = self.connection_panic_wrap(Self::accept_connection, &tcp_listener) => (),
+            }
+        }
+    }
+
+    /// Resolves once the process receives `SIGTERM` or `SIGINT`.
+    async fn shutdown_signal() -> Result<()> {
+        let mut terminate =
+            signal::unix::signal(SignalKind::terminate()).context("listening for SIGTERM")?;
+
+        tokio::select! {
+            _ = terminate.recv() => (),
+            result = signal::ctrl_c() => result.context("listening for SIGINT")?,
         }
+
+        Ok(())
     }
 
     async fn accept_connection(&self, tcp_listener: &TcpListener) {
         // Accept client connection.
-        let (tcp_stream, _) = tcp_listener.accept().await.unwrap();
+        let (tcp_stream, remote_addr) = tcp_listener.accept().await.unwrap();
 
-        // Clone setup object.
-        let setup = Arc::clone(&self.setup);
+        // Clone server context.
+        let context = Arc::clone(&self.context);
 
-        // TODO(appcypher): Need hard or soft limit on thread spawn.
-        // Spawn a thread for each client connection.
-        thread::spawn(move || {
-            // Create a thread local tokio runtime.
-            let tokio_rt = Builder::new_current_thread()
-                .build()
-                .context("creating a new tokio runtime")
-                .unwrap();
+        let tls_acceptor = self
+            .tls
+            .as_ref()
+            .map(|tls| (tls.acceptor(), tls.handshake_timeout()));
 
-            // Create a local task set to run tasks on current thread because V8 Isolate (and some other objects) are !Send.
-            let local = LocalSet::new();
+        // Connections are served on the main runtime, only their requests get threads of their own.
+        // The handshake happens in the task so a slow client cannot hold up the accept loop.
+        let panics = self.context.metrics.panics.clone();
 
-            // Start handler task in new runtime.
-            local.block_on(&tokio_rt, Self::handler(tcp_stream, setup, &local));
+        tokio::spawn(async move {
+            let serve = async move {
+                match tls_acceptor {
+                    Some((tls_acceptor, handshake_timeout)) => {
+                        // A client that never finishes the handshake would otherwise hold the connection forever.
+                        match time::timeout(handshake_timeout, tls_acceptor.accept(tcp_stream))
+                            .await
+                        {
+                            Ok(Ok(tls_stream)) => {
+                                let alpn_protocol =
+                                    tls_stream.get_ref().1.alpn_protocol().map(Vec::from);
+                                HttpDriver::drive(tls_stream, remote_addr, alpn_protocol, context)
+                                    .await
+                            }
+                            Ok(Err(err)) => {
+                                error!("tls handshake with {} = {:?}", remote_addr, err)
+                            }
+                            Err(_) => error!("tls handshake with {} timed out", remote_addr),
+                        }
+                    }
+                    None => HttpDriver::drive(tcp_stream, remote_addr, None, context).await,
+                }
+            };
+
+            // Handle connection and catch panics, which would otherwise only end the task.
+            if let Err(err) = AssertUnwindSafe(serve).catch_unwind().await {
+                panics.inc();
+                let _ = http::handle_panic_error_t::<()>(err);
+            }
         });
     }
 
-    async fn handler(tcp_stream: TcpStream, setup: Arc<CommonSetup>, local: &LocalSet) {
-        // Request channel.
-        let (request_tx, mut request_rx) = mpsc::channel(1);
+    /// Handles a request on a thread of its own and resolves with its response.
+    ///
+    /// V8 isolates cannot be interleaved on one thread, so every request, including each stream of an HTTP/2 connection, gets its own.
+    /// The thread lives on after the response is sent until the api runtime and the tasks it spawned are done with streamed
+    /// bodies and upgraded connections.
+    /// Requests can also be dispatched without a listener, as the command-line interface does.
+    pub async fn dispatch(
+        mut request: Request<Body>,
+        context: Arc<ServerContext>,
+    ) -> Response<Body> {
+        // Probes are answered natively and left out of access logs and metrics.
+        if let Some(response) = Health::respond(&request, &context).await {
+            return response;
+        }
+
+        let start = Instant::now();
+
+        // Filled in by the handler once the request is resolved.
+        let labels = ApiLabels::default();
+        request.extensions_mut().insert(labels.clone());
+
+        let request_id = RequestId::from_header(request.headers().get(RequestId::HEADER));
+        request.extensions_mut().insert(request_id.clone());
+
+        // Set by the api handler if the api has a CORS policy.
+        let response_cors = ResponseCors::default();
+        request.extensions_mut().insert(response_cors.clone());
+
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        let thread_request_id = request_id.clone();
+
+        // Lets the admin listener inspect and kill the request.
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let (in_flight, in_flight_guard) = context.in_flight.register(
+            request_id.clone(),
+            method.clone(),
+            path.clone(),
+            labels.clone(),
+            abort_handle,
+        );
+        request.extensions_mut().insert(Arc::clone(&in_flight));
 
         // Response Channel.
-        let (response_tx, response_rx) = mpsc::channel(1);
-
-        // Spawn task on local thread.
-        local.spawn_local(HttpDriver::drive(
-            tcp_stream,
-            request_tx.clone(),
-            response_rx,
-        ));
-
-        // Route and handle request if there is one.
-        if let Some(request) = request_rx.recv().await {
-            Self::handler_error_wrap(Router::route, request, response_tx, setup).await;
+        let (response_tx, mut response_rx) = mpsc::channel(1);
+
+        let metrics = Arc::clone(&context.metrics);
+
+        // TODO(appcypher): Need hard or soft limit on thread spawn.
+        // Spawn a thread for each request.
+        thread::spawn(move || {
+            let _thread = Metrics::track(&context.metrics.active_threads);
+            let _in_flight = in_flight_guard;
+
+            let panics = context.metrics.panics.clone();
+
+            // Tag every log record of the thread with the request id.
+            thread_request_id.enter();
+
+            // Handle request and catch panics, which would otherwise only end the thread.
+            let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                // Create a thread local tokio runtime.
+                let tokio_rt = Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .context("creating a new tokio runtime")
+                    .unwrap();
+
+                // Create a local task set to run tasks on current thread because V8 Isolate (and some other objects) are !Send.
+                let local = LocalSet::new();
+
+                // Route and handle request in new runtime. Killing the request drops the handler.
+                let _ = local.block_on(
+                    &tokio_rt,
+                    Abortable::new(
+                        Self::handler_error_wrap(Router::route, request, response_tx, context),
+                        abort_registration,
+                    ),
+                );
+
+                // Tasks spawned by the handler, like event stream heartbeats, are cancelled if the local set is dropped.
+                tokio_rt.block_on(local);
+            }));
+
+            if let Err(err) = result {
+                panics.inc();
+                let _ = http::handle_panic_error_t::<()>(err);
+            }
+        });
+
+        // Wait for response.
+        let mut response = match response_rx.recv().await {
+            Some(response) => response,
+            None if in_flight.is_killed() => {
+                let mut response = Response::new(Body::from("request was killed"));
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                response
+            }
+            None => {
+                error!("no response recieved");
+                http::internal_error(errors::new_error("")).as_hyper_response()
+            }
+        };
+
+        response_cors.decorate(&mut response);
+
+        let duration = start.elapsed();
+        metrics.observe_request(&labels, response.status().as_u16(), duration);
+
+        AccessLog {
+            request_id: &request_id,
+            method: &method,
+            path: &path,
+            labels: &labels,
+            duration,
         }
+        .log(&response);
 
-        // This is here to prevent the runtime started by `local.block_on` from ending early before
-        // the driver has properly handled the response we just sent.
-        request_rx.recv().await.unwrap();
+        if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+            response.headers_mut().insert(RequestId::HEADER, value);
+        }
+
+        response
     }
 
     #[inline]
@@ -104,6 +346,7 @@ impl RuntimeServer {
             .catch_unwind()
             .await
         {
+            self.context.metrics.panics.inc();
             let _ = http::handle_panic_error_t::<()>(err);
         }
     }
@@ -113,30 +356,56 @@ impl RuntimeServer {
         func: F,
         request: Request<Body>,
         response_tx: Sender<Response<Body>>,
-        setup: Arc<CommonSetup>,
+        context: Arc<ServerContext>,
     ) where
-        F: FnOnce(Request<Body>, Rc<Sender<Response<Body>>>, Arc<CommonSetup>) -> Fut,
+        F: FnOnce(Request<Body>, Rc<Sender<Response<Body>>>, Arc<ServerContext>) -> Fut,
         Fut: Future<Output = HandlerResult<()>>,
     {
         let response_tx = Rc::new(response_tx);
-        match func(request, Rc::clone(&response_tx), setup).await {
+        let labels = request.extensions().get::<ApiLabels>().cloned();
+        let metrics = Arc::clone(&context.metrics);
+
+        // Error pages of dev mode describe the request, which the handler consumes.
+        let dev = context
+            .config
+            .dev
+            .then(|| (DevRequest::new(&request), Arc::clone(&context)));
+
+        match func(request, Rc::clone(&response_tx), context).await {
             Ok(_) => (),
             Err(mut err) => {
                 // Log error.
                 error!("{:?}", err.system_error());
 
+                // Described before permission errors lose their script stack.
+                let dev_page = dev.map(|(dev_request, context)| {
+                    DevErrorPage::new(&err, dev_request, labels.as_ref(), &context)
+                });
+
                 // Customize js errors that are permission errors.
-                Self::customize_permission_error(&mut err);
+                if Self::customize_permission_error(&mut err) {
+                    let (workspace_id, _) = labels.unwrap_or_default().get();
+                    metrics
+                        .permission_denials
+                        .with_label_values(&[&workspace_id])
+                        .inc();
+                }
 
                 // Send handler error.
-                if let Err(err) = response_tx.try_send(err.as_hyper_response()) {
+                let response = match dev_page {
+                    Some(dev_page) => dev_page.as_hyper_response(),
+                    None => err.as_hyper_response(),
+                };
+
+                if let Err(err) = response_tx.try_send(response) {
                     error!("{:?}", err);
                 };
             }
         }
     }
 
-    fn customize_permission_error(mut handler_err: &mut HandlerError) {
+    /// Turns js permission errors into `401 Unauthorized` errors. Returns true if the error was one.
+    fn customize_permission_error(mut handler_err: &mut HandlerError) -> bool {
         if let HandlerError::Internal { src, .. } = &mut handler_err {
             if let Some(js_err) = src.downcast_ref::<JsError>() {
                 if js_err.message.contains("CustomError::Permission") {
@@ -145,8 +414,12 @@ impl RuntimeServer {
                         code: StatusCode::UNAUTHORIZED,
                         src: errors::new_error("permission error from JavaScript land"),
                     };
+
+                    return true;
                 }
             }
         };
+
+        false
     }
 }
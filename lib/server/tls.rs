@@ -0,0 +1,216 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::config::TlsConfig;
+use log::{error, info};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use rustls_pemfile::Item;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use utilities::{
+    errors,
+    result::{Context, Result},
+};
+
+/// Terminates TLS connections with certificates that are reloaded when their files change.
+pub struct TlsTerminator {
+    config: TlsConfig,
+    acceptor: TlsAcceptor,
+    resolver: Arc<CertResolver>,
+}
+
+/// Selects the certificate of a connection by the server name the client asks for.
+struct CertResolver {
+    store: RwLock<CertStore>,
+}
+
+/// The loaded certificates and the modification times of their files.
+struct CertStore {
+    default: Arc<CertifiedKey>,
+    domains: HashMap<String, Arc<CertifiedKey>>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl TlsTerminator {
+    /// Loads the certificates and creates the terminator.
+    pub fn new(config: TlsConfig) -> Result<Self> {
+        let resolver = Arc::new(CertResolver {
+            store: RwLock::new(CertStore::load(&config)?),
+        });
+
+        let mut server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(&resolver) as Arc<dyn ResolvesServerCert>);
+
+        server_config.alpn_protocols = config
+            .alpn_protocols
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect();
+
+        Ok(Self {
+            config,
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            resolver,
+        })
+    }
+
+    /// Gets an acceptor that can be moved to the thread handling a connection.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.clone()
+    }
+
+    /// Gets the time a client has to complete the handshake.
+    pub fn handshake_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.handshake_timeout)
+    }
+
+    /// Reloads the certificates whenever one of their files changes.
+    ///
+    /// A certificate that fails to load is logged and the previous ones keep being served.
+    pub async fn watch(self: Arc<Self>) {
+        let mut interval = time::interval(Duration::from_secs(self.config.reload_interval.max(1)));
+
+        loop {
+            interval.tick().await;
+
+            let modified = CertStore::modified_times(&self.config);
+            if self.resolver.store.read().unwrap().modified == modified {
+                continue;
+            }
+
+            match CertStore::load(&self.config) {
+                Ok(store) => {
+                    *self.resolver.store.write().unwrap() = store;
+                    info!("Reloaded tls certificates");
+                }
+                Err(err) => error!("reloading tls certificates = {:?}", err),
+            }
+        }
+    }
+}
+
+impl CertStore {
+    /// Loads every certificate in the config.
+    fn load(config: &TlsConfig) -> Result<Self> {
+        // Taken before reading so that a change during the load triggers another one.
+        let modified = Self::modified_times(config);
+
+        let default = Self::load_certified_key(&config.cert_path, &config.key_path)?;
+
+        let mut domains = HashMap::new();
+        for certificate in config.certificates.iter() {
+            let key = Self::load_certified_key(&certificate.cert_path, &certificate.key_path)?;
+
+            for domain in certificate.domains.iter() {
+                domains.insert(domain.to_ascii_lowercase(), Arc::clone(&key));
+            }
+        }
+
+        Ok(Self {
+            default,
+            domains,
+            modified,
+        })
+    }
+
+    /// Loads a certificate chain and its private key.
+    fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<Arc<CertifiedKey>> {
+        let mut cert_reader = BufReader::new(
+            File::open(cert_path).context(format!("opening certificate file {:?}", cert_path))?,
+        );
+
+        let certs = rustls_pemfile::certs(&mut cert_reader)
+            .context(format!("reading certificate file {:?}", cert_path))?
+            .into_iter()
+            .map(Certificate)
+            .collect::<Vec<_>>();
+
+        if certs.is_empty() {
+            return errors::new_error_t(format!("no certificate found in {:?}", cert_path));
+        }
+
+        let key = Self::load_private_key(key_path)?;
+
+        let signing_key = sign::any_supported_type(&key)
+            .map_err(|_| errors::new_error(format!("unsupported private key in {:?}", key_path)))?;
+
+        Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+    }
+
+    /// Loads the first private key in a PEM file.
+    fn load_private_key(key_path: &Path) -> Result<PrivateKey> {
+        let mut key_reader = BufReader::new(
+            File::open(key_path).context(format!("opening private key file {:?}", key_path))?,
+        );
+
+        loop {
+            match rustls_pemfile::read_one(&mut key_reader)
+                .context(format!("reading private key file {:?}", key_path))?
+            {
+                Some(Item::RSAKey(key)) | Some(Item::PKCS8Key(key)) | Some(Item::ECKey(key)) => {
+                    return Ok(PrivateKey(key))
+                }
+                Some(_) => continue,
+                None => {
+                    return errors::new_error_t(format!("no private key found in {:?}", key_path))
+                }
+            }
+        }
+    }
+
+    /// Gets the modification times of all the certificate and key files in the config.
+    fn modified_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+        let mut paths: Vec<&PathBuf> = vec![&config.cert_path, &config.key_path];
+        for certificate in config.certificates.iter() {
+            paths.push(&certificate.cert_path);
+            paths.push(&certificate.key_path);
+        }
+
+        paths
+            .into_iter()
+            .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+            .collect()
+    }
+
+    /// Finds the certificate for a server name, trying an exact match before a wildcard one.
+    fn find(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let server_name = match server_name {
+            Some(server_name) => server_name.to_ascii_lowercase(),
+            None => return Arc::clone(&self.default),
+        };
+
+        if let Some(key) = self.domains.get(&server_name) {
+            return Arc::clone(key);
+        }
+
+        let wildcard = server_name
+            .split_once('.')
+            .map(|(_, parent)| format!("*.{}", parent));
+
+        match wildcard.and_then(|wildcard| self.domains.get(&wildcard)) {
+            Some(key) => Arc::clone(key),
+            None => Arc::clone(&self.default),
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let store = self.store.read().ok()?;
+
+        Some(store.find(client_hello.server_name()))
+    }
+}
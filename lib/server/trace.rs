@@ -0,0 +1,340 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{config::TracingConfig, RequestId};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    convert::TryInto,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::SystemTime,
+};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use utilities::hyper::{Body, Request};
+
+/// Creates the spans of requests and queues them for export.
+pub struct Tracer {
+    pub config: Option<TracingConfig>,
+    spans_tx: Option<Sender<SpanData>>,
+    pub(crate) spans_rx: Mutex<Option<Receiver<SpanData>>>,
+}
+
+/// The W3C trace context of a span, as carried by the `traceparent` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+/// A span of work within a trace. It ends and gets queued for export when dropped.
+pub struct Span(Option<ActiveSpan>);
+
+struct ActiveSpan {
+    tracer: Arc<Tracer>,
+    data: SpanData,
+}
+
+/// The tracer and context of a span, to start its children with.
+///
+/// Parents are passed around explicitly, since the work of a request moves between tasks and threads across awaits.
+/// The default parent starts spans that do nothing, like outside of requests or with tracing disabled.
+#[derive(Clone, Default)]
+pub struct SpanParent(Option<(Arc<Tracer>, TraceContext)>);
+
+/// The span the scripts of a runtime currently run in, for the ops they call.
+///
+/// Put in the op state of the runtime, and updated by the runtime as it goes from one script to the next.
+#[derive(Default)]
+pub struct ScriptSpan(RefCell<SpanParent>);
+
+/// A finished span.
+#[derive(Debug, Clone)]
+pub struct SpanData {
+    pub name: String,
+    pub kind: SpanKind,
+    pub context: TraceContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(String, String)>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Internal,
+    Server,
+    Client,
+}
+
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl Tracer {
+    /// Creates a tracer. Spans are only recorded if tracing is configured.
+    pub fn new(config: Option<TracingConfig>) -> Self {
+        let (spans_tx, spans_rx) = match &config {
+            Some(config) => {
+                let (spans_tx, spans_rx) = mpsc::channel(config.max_queue_size.max(1));
+                (Some(spans_tx), Some(spans_rx))
+            }
+            None => (None, None),
+        };
+
+        Self {
+            config,
+            spans_tx,
+            spans_rx: Mutex::new(spans_rx),
+        }
+    }
+
+    /// Starts the server span of a request.
+    ///
+    /// The trace is continued from the `traceparent` header of the request if it has a valid one.
+    pub fn start_request(self: &Arc<Self>, request: &Request<Body>) -> Span {
+        let parent = request
+            .headers()
+            .get(TraceContext::TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(TraceContext::from_traceparent);
+
+        let (context, parent_span_id) = match parent {
+            Some(parent) => (parent.child(), Some(parent.span_id)),
+            None => (TraceContext::new_root(), None),
+        };
+
+        let mut span = Span::open(
+            Arc::clone(self),
+            format!("HTTP {}", request.method()),
+            SpanKind::Server,
+            context,
+            parent_span_id,
+        );
+
+        span.set_attribute("http.method", request.method().as_str());
+        span.set_attribute("http.target", request.uri().path());
+
+        if let Some(request_id) = request.extensions().get::<RequestId>() {
+            span.set_attribute("request.id", &request_id.0);
+        }
+
+        span
+    }
+
+    /// Whether `db.statement` attributes are recorded, which is off by default as statements may hold secrets.
+    pub fn records_db_statements(&self) -> bool {
+        self.config
+            .as_ref()
+            .map_or(false, |config| config.record_db_statements)
+    }
+
+    /// Queues a finished span for export, dropping it if the queue is full.
+    fn record(&self, data: SpanData) {
+        if let Some(spans_tx) = &self.spans_tx {
+            if data.context.sampled {
+                let _ = spans_tx.try_send(data);
+            }
+        }
+    }
+}
+
+impl TraceContext {
+    pub const TRACEPARENT_HEADER: &'static str = "traceparent";
+
+    /// Parses a `traceparent` header value like `00-<trace id>-<parent id>-<flags>`.
+    ///
+    /// Returns `None` for malformed values and all-zero ids, in which case a new trace should be started.
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+
+        let (version, trace_id, span_id, flags) = match parts.as_slice() {
+            [version, trace_id, span_id, flags] => (*version, *trace_id, *span_id, *flags),
+            // Later versions may append fields.
+            [version, trace_id, span_id, flags, ..] if *version != "00" => {
+                (*version, *trace_id, *span_id, *flags)
+            }
+            _ => return None,
+        };
+
+        let is_lower_hex = |part: &str, len: usize| {
+            part.len() == len
+                && part
+                    .bytes()
+                    .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+        };
+
+        if !is_lower_hex(version, 2)
+            || version == "ff"
+            || !is_lower_hex(trace_id, 32)
+            || !is_lower_hex(span_id, 16)
+            || !is_lower_hex(flags, 2)
+        {
+            return None;
+        }
+
+        let trace_id: [u8; 16] = hex::decode(trace_id).ok()?.try_into().ok()?;
+        let span_id: [u8; 8] = hex::decode(span_id).ok()?.try_into().ok()?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+
+        Some(Self {
+            trace_id,
+            span_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+
+    /// Formats the context as a `traceparent` header value.
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex::encode(self.trace_id),
+            hex::encode(self.span_id),
+            self.sampled as u8
+        )
+    }
+
+    /// Creates the context of a span that starts a new trace.
+    fn new_root() -> Self {
+        let random = Self::random_bytes();
+
+        Self {
+            trace_id: random[..16].try_into().unwrap(),
+            span_id: random[16..24].try_into().unwrap(),
+            sampled: true,
+        }
+    }
+
+    /// Creates the context of a child span in the same trace.
+    fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id,
+            span_id: Self::random_bytes()[..8].try_into().unwrap(),
+            sampled: self.sampled,
+        }
+    }
+
+    /// Gets bytes that are unique across the process and unlikely to clash with other processes.
+    fn random_bytes() -> [u8; 32] {
+        let mut hasher = Sha256::new();
+
+        hasher.update(format!(
+            "{:?}-{}-{:?}-{}",
+            SystemTime::now(),
+            process::id(),
+            thread::current().id(),
+            ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        hasher.finalize().into()
+    }
+}
+
+impl Span {
+    /// Gets the parent to start the children of the span with.
+    pub fn as_parent(&self) -> SpanParent {
+        SpanParent(
+            self.0
+                .as_ref()
+                .map(|span| (Arc::clone(&span.tracer), span.data.context)),
+        )
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: &str) {
+        if let Some(span) = &mut self.0 {
+            span.data
+                .attributes
+                .push((key.to_string(), value.to_string()));
+        }
+    }
+
+    /// Marks the span as failed.
+    pub fn set_error(&mut self, message: &str) {
+        if let Some(span) = &mut self.0 {
+            span.data.error = Some(message.to_string());
+        }
+    }
+
+    fn open(
+        tracer: Arc<Tracer>,
+        name: String,
+        kind: SpanKind,
+        context: TraceContext,
+        parent_span_id: Option<[u8; 8]>,
+    ) -> Self {
+        Self(Some(ActiveSpan {
+            tracer,
+            data: SpanData {
+                name,
+                kind,
+                context,
+                parent_span_id,
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes: vec![],
+                error: None,
+            },
+        }))
+    }
+}
+
+impl SpanParent {
+    /// Starts a child span.
+    pub fn start(&self, name: &str) -> Span {
+        self.start_as(name, SpanKind::Internal)
+    }
+
+    /// Starts a child span of a specific kind.
+    pub fn start_as(&self, name: &str, kind: SpanKind) -> Span {
+        match &self.0 {
+            Some((tracer, parent)) if tracer.spans_tx.is_some() => Span::open(
+                Arc::clone(tracer),
+                name.to_string(),
+                kind,
+                parent.child(),
+                Some(parent.span_id),
+            ),
+            _ => Span(None),
+        }
+    }
+
+    /// Gets the `traceparent` of the span, for propagation to outgoing calls.
+    pub fn traceparent(&self) -> Option<String> {
+        self.0.as_ref().map(|(_, context)| context.to_traceparent())
+    }
+
+    /// Whether `db.statement` attributes should be recorded on child spans.
+    pub fn records_db_statements(&self) -> bool {
+        self.0
+            .as_ref()
+            .map_or(false, |(tracer, _)| tracer.records_db_statements())
+    }
+}
+
+impl ScriptSpan {
+    /// Gets the span of the script that is running.
+    pub fn get(&self) -> SpanParent {
+        self.0.borrow().clone()
+    }
+
+    /// Sets the span of the script that is about to run.
+    pub fn set(&self, parent: SpanParent) {
+        *self.0.borrow_mut() = parent;
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut span) = self.0.take() {
+            span.data.end = SystemTime::now();
+            span.tracer.record(span.data);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use crate::{config::TracingConfig, SpanData, SpanKind, Tracer};
+use hyper::Client;
+use log::{debug, error};
+use serde_json::{json, Value};
+use std::{
+    mem,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time;
+use utilities::{
+    errors,
+    hyper::{header, Body, Method, Request},
+    result::{Context, Result},
+};
+
+impl Tracer {
+    /// Sends queued spans to the collector in batches, whenever a batch is full or the export interval has passed.
+    ///
+    /// Does nothing if tracing is disabled or spans are already being exported.
+    pub async fn export(self: Arc<Self>) {
+        let (config, mut spans_rx) = match (&self.config, self.spans_rx.lock().unwrap().take()) {
+            (Some(config), Some(spans_rx)) => (config.clone(), spans_rx),
+            _ => return,
+        };
+
+        let client = Client::new();
+        let mut interval = time::interval(Duration::from_secs(config.export_interval.max(1)));
+        let mut batch = Vec::with_capacity(config.max_batch_size);
+
+        loop {
+            let closed = tokio::select! {
+                span = spans_rx.recv() => match span {
+                    Some(span) => {
+                        batch.push(span);
+
+                        if batch.len() < config.max_batch_size {
+                            continue;
+                        }
+
+                        false
+                    }
+                    None => true,
+                },
+                _ = interval.tick() => false,
+            };
+
+            if !batch.is_empty() {
+                let spans = mem::take(&mut batch);
+
+                if let Err(err) = Self::send(&client, &config, &spans).await {
+                    error!("exporting {} spans = {:?}", spans.len(), err);
+                }
+            }
+
+            if closed {
+                return;
+            }
+        }
+    }
+
+    async fn send(
+        client: &Client<hyper::client::HttpConnector>,
+        config: &TracingConfig,
+        spans: &[SpanData],
+    ) -> Result<()> {
+        let url = format!("{}/v1/traces", config.endpoint.trim_end_matches('/'));
+        let body = Self::encode(config, spans).to_string();
+
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri(&url)
+            .header(header::CONTENT_TYPE, "application/json");
+
+        for (name, value) in &config.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        let request = builder
+            .body(Body::from(body))
+            .context(format!(r#"creating trace export request to "{}""#, url))?;
+
+        let response = client
+            .request(request)
+            .await
+            .context(format!(r#"sending spans to "{}""#, url))?;
+
+        if !response.status().is_success() {
+            return errors::new_error_t(format!(
+                r#"collector at "{}" responded with {}"#,
+                url,
+                response.status()
+            ));
+        }
+
+        debug!("Exported {} spans", spans.len());
+
+        Ok(())
+    }
+
+    /// Encodes spans as an OTLP `ExportTraceServiceRequest` in its JSON encoding.
+    pub fn encode(config: &TracingConfig, spans: &[SpanData]) -> Value {
+        let spans: Vec<Value> = spans.iter().map(Self::encode_span).collect();
+
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [Self::encode_attribute("service.name", &config.service_name)],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+
+    fn encode_span(span: &SpanData) -> Value {
+        let nanos = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos())
+                .unwrap_or(0)
+                .to_string()
+        };
+
+        // The numbers of the span kinds and status codes in the OTLP protos.
+        let kind = match span.kind {
+            SpanKind::Internal => 1,
+            SpanKind::Server => 2,
+            SpanKind::Client => 3,
+        };
+
+        let status = match &span.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 0 }),
+        };
+
+        let attributes: Vec<Value> = span
+            .attributes
+            .iter()
+            .map(|(key, value)| Self::encode_attribute(key, value))
+            .collect();
+
+        json!({
+            "traceId": hex::encode(span.context.trace_id),
+            "spanId": hex::encode(span.context.span_id),
+            "parentSpanId": span.parent_span_id.map(hex::encode).unwrap_or_default(),
+            "name": span.name,
+            "kind": kind,
+            "startTimeUnixNano": nanos(span.start),
+            "endTimeUnixNano": nanos(span.end),
+            "attributes": attributes,
+            "status": status,
+        })
+    }
+
+    fn encode_attribute(key: &str, value: &str) -> Value {
+        json!({ "key": key, "value": { "stringValue": value } })
+    }
+}
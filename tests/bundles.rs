@@ -0,0 +1,223 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use engine_runtime::{
+    config::DeployConfig,
+    deploy::{Bundle, Deployer, InvalidBundle},
+    harness::TestWorkspace,
+    root::WorkspaceVersions,
+};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use tar::{EntryType, Header};
+
+const FILES: &[(&str, &[u8])] = &[
+    ("config.yaml", b"name: test\n"),
+    ("lib/util.js", b"export const value = 1;\n"),
+];
+
+fn keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+
+    Keypair { secret, public }
+}
+
+fn config(trusted: &Keypair) -> DeployConfig {
+    DeployConfig {
+        public_keys: vec![hex::encode(trusted.public.as_bytes())],
+        ..Default::default()
+    }
+}
+
+/// Writes a manifest listing the hashes of files.
+fn manifest(version: &str, files: &[(&str, &[u8])]) -> String {
+    let mut manifest = format!("version: {}\nfiles:\n", version);
+
+    for (path, contents) in files {
+        writeln!(
+            manifest,
+            "  {}: {}",
+            path,
+            hex::encode(Sha256::digest(contents))
+        )
+        .unwrap();
+    }
+
+    manifest
+}
+
+fn sign(keypair: &Keypair, manifest: &str) -> Vec<u8> {
+    hex::encode(keypair.sign(manifest.as_bytes()).to_bytes()).into_bytes()
+}
+
+/// Packs files into a tar archive.
+fn pack(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+
+    for (path, contents) in files {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        builder.append_data(&mut header, path, *contents).unwrap();
+    }
+
+    builder.into_inner().unwrap()
+}
+
+/// Packs a bundle of files whose manifest is signed by a key.
+fn bundle(keypair: &Keypair, version: &str, files: &[(&str, &[u8])]) -> Vec<u8> {
+    let manifest = manifest(version, files);
+    let signature = sign(keypair, &manifest);
+
+    let mut entries = files.to_vec();
+    entries.push((Bundle::MANIFEST_FILENAME, manifest.as_bytes()));
+    entries.push((Bundle::SIGNATURE_FILENAME, &signature[..]));
+
+    pack(&entries)
+}
+
+fn assert_invalid(bytes: Vec<u8>, config: &DeployConfig) {
+    let err = match Bundle::try_from(bytes, config) {
+        Ok(_) => panic!("bundle was accepted"),
+        Err(err) => err,
+    };
+
+    assert!(err.downcast_ref::<InvalidBundle>().is_some(), "{}", err);
+}
+
+#[test]
+fn signed_bundle_is_accepted() {
+    let keypair = keypair(1);
+
+    let bundle = Bundle::try_from(bundle(&keypair, "v1", FILES), &config(&keypair)).unwrap();
+
+    assert_eq!(bundle.manifest.version, "v1");
+    assert_eq!(bundle.files().unwrap().len(), FILES.len());
+}
+
+#[test]
+fn bundle_without_signature_is_rejected() {
+    let keypair = keypair(1);
+    let manifest = manifest("v1", FILES);
+
+    let mut entries = FILES.to_vec();
+    entries.push((Bundle::MANIFEST_FILENAME, manifest.as_bytes()));
+
+    assert_invalid(pack(&entries), &config(&keypair));
+}
+
+#[test]
+fn bundle_signed_by_untrusted_key_is_rejected() {
+    let signer = keypair(1);
+    let trusted = keypair(2);
+
+    assert_invalid(bundle(&signer, "v1", FILES), &config(&trusted));
+}
+
+#[test]
+fn tampered_manifest_is_rejected() {
+    let keypair = keypair(1);
+    let signature = sign(&keypair, &manifest("v1", FILES));
+    let tampered = manifest("v2", FILES);
+
+    let mut entries = FILES.to_vec();
+    entries.push((Bundle::MANIFEST_FILENAME, tampered.as_bytes()));
+    entries.push((Bundle::SIGNATURE_FILENAME, &signature[..]));
+
+    assert_invalid(pack(&entries), &config(&keypair));
+}
+
+#[test]
+fn hash_mismatch_is_rejected() {
+    let keypair = keypair(1);
+    let manifest = manifest("v1", FILES);
+    let signature = sign(&keypair, &manifest);
+
+    let entries = vec![
+        ("config.yaml", &b"name: changed\n"[..]),
+        FILES[1],
+        (Bundle::MANIFEST_FILENAME, manifest.as_bytes()),
+        (Bundle::SIGNATURE_FILENAME, &signature[..]),
+    ];
+
+    assert_invalid(pack(&entries), &config(&keypair));
+}
+
+#[test]
+fn file_missing_from_archive_is_rejected() {
+    let keypair = keypair(1);
+    let manifest = manifest("v1", FILES);
+    let signature = sign(&keypair, &manifest);
+
+    let entries = vec![
+        FILES[0],
+        (Bundle::MANIFEST_FILENAME, manifest.as_bytes()),
+        (Bundle::SIGNATURE_FILENAME, &signature[..]),
+    ];
+
+    assert_invalid(pack(&entries), &config(&keypair));
+}
+
+#[test]
+fn file_missing_from_manifest_is_rejected() {
+    let keypair = keypair(1);
+    let manifest = manifest("v1", FILES);
+    let signature = sign(&keypair, &manifest);
+
+    let mut entries = FILES.to_vec();
+    entries.push(("extra.js", &b"export {};\n"[..]));
+    entries.push((Bundle::MANIFEST_FILENAME, manifest.as_bytes()));
+    entries.push((Bundle::SIGNATURE_FILENAME, &signature[..]));
+
+    assert_invalid(pack(&entries), &config(&keypair));
+}
+
+#[test]
+fn deploy_switches_to_new_version_and_prunes_old_ones() {
+    let workspace = TestWorkspace::new().unwrap();
+    let root = workspace.root().to_str().unwrap();
+    let keypair = keypair(1);
+
+    let config = DeployConfig {
+        keep_versions: 1,
+        ..config(&keypair)
+    };
+
+    for version in ["v1", "v2", "v3"] {
+        let deployed = Deployer::deploy(
+            bundle(&keypair, version, FILES),
+            root,
+            TestWorkspace::ID,
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(deployed, version);
+    }
+
+    let versions = WorkspaceVersions::open(root, TestWorkspace::ID).unwrap();
+    assert_eq!(versions.current().unwrap().as_deref(), Some("v3"));
+
+    let names: Vec<String> = versions
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|version| version.name)
+        .collect();
+
+    assert_eq!(names, vec!["v2", "v3"]);
+    assert_eq!(
+        std::fs::read_to_string(
+            workspace
+                .path()
+                .join(WorkspaceVersions::CURRENT)
+                .join("lib/util.js")
+        )
+        .unwrap(),
+        "export const value = 1;\n"
+    );
+}
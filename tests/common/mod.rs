@@ -0,0 +1,46 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+// Each test file uses its own subset of the helpers.
+#![allow(dead_code)]
+
+use engine_runtime::{
+    config::SymlinkPolicy,
+    harness::TestWorkspace,
+    root::{RootManager, WorkspaceVersions},
+};
+use std::{fs, path::PathBuf};
+
+/// Opens the test workspace at its current version, if it is versioned.
+pub fn root_mgr(workspace: &TestWorkspace, policy: SymlinkPolicy) -> RootManager {
+    RootManager::new(
+        workspace.root().to_str().unwrap(),
+        TestWorkspace::ID,
+        policy,
+    )
+    .unwrap()
+}
+
+/// Opens the draft of the test workspace.
+pub fn draft(workspace: &TestWorkspace, policy: SymlinkPolicy) -> RootManager {
+    RootManager::draft(
+        workspace.root().to_str().unwrap(),
+        TestWorkspace::ID,
+        policy,
+    )
+    .unwrap()
+}
+
+/// Gets the versions of the test workspace.
+pub fn versions(workspace: &TestWorkspace) -> WorkspaceVersions {
+    WorkspaceVersions::open(workspace.root().to_str().unwrap(), TestWorkspace::ID).unwrap()
+}
+
+/// Creates an `outside` folder next to the test workspace, holding a `secret` file.
+pub fn outside(workspace: &TestWorkspace) -> PathBuf {
+    let outside = workspace.root().join("outside");
+
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("secret"), "outside").unwrap();
+
+    outside
+}
@@ -0,0 +1,7 @@
+const response = await globalThis.__bootstrap.httpStream.responseStart(201, {
+  "x-greeting": "hello",
+});
+
+await response.write("hello ");
+await response.write("world");
+response.end();
@@ -0,0 +1,2 @@
+// Requests to this api are answered natively before its index runs.
+export {};
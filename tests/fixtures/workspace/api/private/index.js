@@ -0,0 +1,2 @@
+// Never reached, the middleware rejects every request.
+export {};
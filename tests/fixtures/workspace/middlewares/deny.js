@@ -0,0 +1 @@
+() => false
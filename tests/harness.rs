@@ -0,0 +1,262 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use engine_runtime::{
+    config::RuntimeConfig,
+    harness::{TestHarness, TestWorkspace},
+};
+use utilities::{
+    http,
+    hyper::{header, header::HeaderValue, Body, Method, StatusCode},
+};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/workspace");
+
+async fn harness() -> TestHarness {
+    let workspace = TestWorkspace::from_fixtures(FIXTURES).unwrap();
+
+    TestHarness::new(workspace).await.unwrap()
+}
+
+#[tokio::test]
+async fn paths_outside_api_are_not_found() {
+    let harness = harness().await;
+
+    let response = harness.get("/status").await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn missing_api_is_an_error() {
+    let harness = harness().await;
+
+    let response = harness.get("/api/missing").await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn invalid_workspace_id_is_rejected() {
+    let harness = harness().await;
+
+    let mut request = harness
+        .request(Method::GET, "/api/open")
+        .body(Body::empty())
+        .unwrap();
+
+    request.headers_mut().insert(
+        http::WORKSPACE_ID_HEADER,
+        HeaderValue::from_static("../test"),
+    );
+
+    let response = harness.send(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn empty_workspace_id_is_rejected() {
+    let harness = harness().await;
+
+    let mut request = harness
+        .request(Method::GET, "/api/open")
+        .body(Body::empty())
+        .unwrap();
+
+    request
+        .headers_mut()
+        .insert(http::WORKSPACE_ID_HEADER, HeaderValue::from_static(""));
+
+    let response = harness.send(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn preflight_is_answered_natively() {
+    let harness = harness().await;
+
+    let request = harness
+        .request(Method::OPTIONS, "/api/open")
+        .header(header::ORIGIN, "https://example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = harness.send(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://example.com"
+    );
+}
+
+#[tokio::test]
+async fn body_over_api_limit_is_rejected() {
+    let harness = harness().await;
+
+    let request = harness
+        .request(Method::POST, "/api/open")
+        .body(Body::from("more than eight bytes"))
+        .unwrap();
+
+    let response = harness.send(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn streamed_body_over_api_limit_is_rejected() {
+    let workspace = TestWorkspace::from_fixtures(FIXTURES).unwrap();
+    workspace
+        .write(
+            "api/streamed/api.yaml",
+            "authentication:\n  enabled: false\nmiddlewares: []\nbody:\n  max_size: 8\n  streaming: true\npermissions:\n  http_event:\n    request_read: true\n",
+        )
+        .unwrap();
+    workspace
+        .write(
+            "api/streamed/index.js",
+            "for await (const _ of globalThis.__bootstrap.httpStream.requestBodyChunks()) {}\n",
+        )
+        .unwrap();
+
+    let harness = TestHarness::new(workspace).await.unwrap();
+
+    // Without a Content-Length header, the limit is only exceeded while the script reads the body.
+    let request = harness
+        .request(Method::POST, "/api/streamed")
+        .body(Body::from("more than eight bytes"))
+        .unwrap();
+
+    let response = harness.send(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn streamed_body_needs_read_permission() {
+    let workspace = TestWorkspace::from_fixtures(FIXTURES).unwrap();
+    workspace
+        .write(
+            "api/unreadable/api.yaml",
+            "authentication:\n  enabled: false\nmiddlewares: []\nbody:\n  streaming: true\npermissions:\n  http_event:\n    response_send: true\n",
+        )
+        .unwrap();
+    workspace
+        .write(
+            "api/unreadable/index.js",
+            "const stream = globalThis.__bootstrap.httpStream;\nlet status = 200;\ntry {\n  await stream.requestBodyRead();\n} catch {\n  status = 403;\n}\n(await stream.responseStart(status)).end();\n",
+        )
+        .unwrap();
+
+    let harness = TestHarness::new(workspace).await.unwrap();
+
+    let request = harness
+        .request(Method::POST, "/api/unreadable")
+        .body(Body::from("secret"))
+        .unwrap();
+
+    let response = harness.send(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn index_sends_response() {
+    let harness = harness().await;
+
+    let response = harness.get("/api/hello").await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()["x-greeting"], "hello");
+    assert_eq!(
+        TestHarness::body_string(response).await.unwrap(),
+        "hello world"
+    );
+}
+
+#[tokio::test]
+async fn rejecting_middleware_stops_request() {
+    let harness = harness().await;
+
+    let response = harness.get("/api/private").await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn requests_over_rate_limit_are_rejected() {
+    let workspace = TestWorkspace::from_fixtures(FIXTURES).unwrap();
+    workspace
+        .write(
+            "api/limited/api.yaml",
+            "authentication:\n  enabled: false\nmiddlewares:\n  - script: middlewares/deny.js\nrate_limit:\n  capacity: 1\n  refill_per_second: 0.001\n",
+        )
+        .unwrap();
+    workspace
+        .write("api/limited/index.js", "export {};\n")
+        .unwrap();
+
+    let harness = TestHarness::new(workspace).await.unwrap();
+
+    let response = harness.get("/api/limited").await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = harness.get("/api/limited").await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn dev_mode_describes_manifest_errors() {
+    let workspace = TestWorkspace::new().unwrap();
+    workspace
+        .write("api/broken/api.yaml", "middlewares: [\n")
+        .unwrap();
+
+    let config = RuntimeConfig {
+        dev: true,
+        ..Default::default()
+    };
+
+    let harness = TestHarness::with_config(workspace, config).await.unwrap();
+
+    let response = harness.get("/api/broken").await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let body = TestHarness::body_string(response).await.unwrap();
+    let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+    assert_eq!(page["kind"], "manifest");
+    assert_eq!(page["location"]["file"], "api/broken/api.yaml");
+    assert_eq!(page["route"]["path"], "/api/broken");
+}
+
+#[test]
+fn dev_mode_cannot_be_set_in_config_file() {
+    let content = "engines:\n  runtime:\n    dev: true\n";
+
+    assert!(RuntimeConfig::try_from(content).is_err());
+    assert!(!RuntimeConfig::try_from("engines:\n  runtime: {}\n").unwrap().dev);
+}
+
+#[test]
+fn tracing_endpoint_must_be_plain_http() {
+    let config = |endpoint: &str| {
+        format!(
+            "engines:\n  runtime:\n    tracing:\n      endpoint: {}\n",
+            endpoint
+        )
+    };
+
+    assert!(RuntimeConfig::try_from(&config("http://127.0.0.1:4318")).is_ok());
+
+    for endpoint in [
+        "https://collector.example.com",
+        "127.0.0.1:4318",
+        "not a url",
+    ] {
+        assert!(RuntimeConfig::try_from(&config(endpoint)).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+mod common;
+
+use engine_runtime::{config::SymlinkPolicy, harness::TestWorkspace};
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+#[test]
+fn write_creates_parents_and_replaces_contents() {
+    let workspace = TestWorkspace::new().unwrap();
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    root_mgr
+        .write_file_to_workspace(Path::new("a/b/file"), b"first")
+        .unwrap();
+    root_mgr
+        .write_file_to_workspace(Path::new("a/b/file"), b"second")
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(workspace.path().join("a/b/file")).unwrap(),
+        "second"
+    );
+
+    // No temporary files are left next to the written file.
+    let names: Vec<String> = root_mgr
+        .list_dir(Path::new("a/b"))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+
+    assert_eq!(names, vec!["file"]);
+}
+
+#[test]
+fn write_replaces_symlink_instead_of_following_it() {
+    let workspace = TestWorkspace::new().unwrap();
+    fs::write(workspace.path().join("target"), "target").unwrap();
+    symlink("target", workspace.path().join("link")).unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::AllowWithinWorkspace);
+
+    root_mgr
+        .write_file_to_workspace(Path::new("link"), b"written")
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(workspace.path().join("target")).unwrap(),
+        "target"
+    );
+    assert!(!fs::symlink_metadata(workspace.path().join("link"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+}
+
+#[test]
+fn paths_outside_workspace_are_rejected() {
+    let workspace = TestWorkspace::new().unwrap();
+    let outside = common::outside(&workspace);
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    for path in ["../outside/file", "a/../../outside/file", "/etc/passwd"] {
+        assert!(root_mgr
+            .write_file_to_workspace(Path::new(path), b"escaped")
+            .is_err());
+    }
+
+    assert!(root_mgr.create_dir(Path::new("../outside/folder")).is_err());
+    assert!(!outside.join("file").exists());
+    assert!(!outside.join("folder").exists());
+}
+
+#[test]
+fn list_dir_is_sorted_and_marks_folders() {
+    let workspace = TestWorkspace::new().unwrap();
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    root_mgr
+        .write_file_to_workspace(Path::new("b"), b"")
+        .unwrap();
+    root_mgr.create_dir(Path::new("a/nested")).unwrap();
+    root_mgr
+        .write_file_to_workspace(Path::new("c"), b"")
+        .unwrap();
+
+    let entries: Vec<(String, bool)> = root_mgr
+        .list_dir(Path::new(""))
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.name, entry.is_dir))
+        .collect();
+
+    assert_eq!(
+        entries,
+        vec![
+            ("a".to_string(), true),
+            ("b".to_string(), false),
+            ("c".to_string(), false),
+        ]
+    );
+}
+
+#[test]
+fn metadata_and_exists_describe_paths() {
+    let workspace = TestWorkspace::new().unwrap();
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    root_mgr
+        .write_file_to_workspace(Path::new("folder/file"), b"12345")
+        .unwrap();
+
+    let file = root_mgr.metadata(Path::new("folder/file")).unwrap();
+    assert!(!file.is_dir);
+    assert_eq!(file.len, 5);
+
+    assert!(root_mgr.metadata(Path::new("folder")).unwrap().is_dir);
+
+    assert!(root_mgr.exists(Path::new("folder/file")).unwrap());
+    assert!(!root_mgr.exists(Path::new("folder/missing")).unwrap());
+}
+
+#[test]
+fn exists_fails_on_denied_symlink() {
+    let workspace = TestWorkspace::new().unwrap();
+    fs::write(workspace.path().join("target"), "target").unwrap();
+    symlink("target", workspace.path().join("link")).unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    assert!(root_mgr.exists(Path::new("link")).is_err());
+}
+
+#[test]
+fn remove_deletes_files_and_folders() {
+    let workspace = TestWorkspace::new().unwrap();
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    root_mgr
+        .write_file_to_workspace(Path::new("file"), b"")
+        .unwrap();
+    root_mgr
+        .write_file_to_workspace(Path::new("folder/nested/file"), b"")
+        .unwrap();
+
+    root_mgr.remove(Path::new("file")).unwrap();
+    root_mgr.remove(Path::new("folder")).unwrap();
+
+    assert!(!workspace.path().join("file").exists());
+    assert!(!workspace.path().join("folder").exists());
+    assert!(root_mgr.remove(Path::new("missing")).is_err());
+}
+
+#[test]
+fn remove_deletes_symlink_but_not_its_target() {
+    let workspace = TestWorkspace::new().unwrap();
+    let outside = common::outside(&workspace);
+    fs::write(outside.join("file"), "outside").unwrap();
+    symlink(&outside, workspace.path().join("link")).unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    root_mgr.remove(Path::new("link")).unwrap();
+
+    assert!(fs::symlink_metadata(workspace.path().join("link")).is_err());
+    assert!(outside.join("file").exists());
+}
+
+#[test]
+fn workspace_root_cannot_be_removed() {
+    let workspace = TestWorkspace::new().unwrap();
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    for path in ["", ".", "..", "folder/.."] {
+        assert!(root_mgr.remove(Path::new(path)).is_err());
+    }
+
+    assert!(workspace.path().exists());
+}
+
+#[test]
+fn versions_are_read_only() {
+    let workspace = TestWorkspace::new().unwrap();
+    common::versions(&workspace)
+        .create("v1", vec![(PathBuf::from("file"), b"v1".to_vec())])
+        .unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    assert!(root_mgr
+        .write_file_to_workspace(Path::new("file"), b"changed")
+        .is_err());
+    assert!(root_mgr.create_dir(Path::new("folder")).is_err());
+    assert!(root_mgr.remove(Path::new("file")).is_err());
+    assert_eq!(
+        root_mgr
+            .read_file_from_workspace(Path::new("file"))
+            .unwrap(),
+        "v1"
+    );
+
+    // The draft is still writable.
+    let draft = common::draft(&workspace, SymlinkPolicy::Deny);
+
+    draft
+        .write_file_to_workspace(Path::new("file"), b"draft")
+        .unwrap();
+}
@@ -0,0 +1,170 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+mod common;
+
+use engine_runtime::{config::SymlinkPolicy, harness::TestWorkspace};
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+#[test]
+fn deny_policy_rejects_symlinks_within_workspace() {
+    let workspace = TestWorkspace::new().unwrap();
+    fs::create_dir(workspace.path().join("real")).unwrap();
+    fs::write(workspace.path().join("real").join("file"), "inside").unwrap();
+    symlink("real", workspace.path().join("link")).unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    assert_eq!(
+        root_mgr
+            .read_file_from_workspace(Path::new("real/file"))
+            .unwrap(),
+        "inside"
+    );
+    assert!(root_mgr
+        .read_file_from_workspace(Path::new("link/file"))
+        .is_err());
+}
+
+#[test]
+fn allow_policy_follows_symlinks_within_workspace() {
+    let workspace = TestWorkspace::new().unwrap();
+    fs::create_dir(workspace.path().join("real")).unwrap();
+    fs::write(workspace.path().join("real").join("file"), "inside").unwrap();
+    symlink("real", workspace.path().join("relative")).unwrap();
+    symlink(
+        workspace.path().canonicalize().unwrap().join("real"),
+        workspace.path().join("absolute"),
+    )
+    .unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::AllowWithinWorkspace);
+
+    for path in ["relative/file", "absolute/file"] {
+        assert_eq!(
+            root_mgr.read_file_from_workspace(Path::new(path)).unwrap(),
+            "inside"
+        );
+    }
+}
+
+#[test]
+fn allow_policy_rejects_symlinks_leaving_workspace() {
+    let workspace = TestWorkspace::new().unwrap();
+    let outside = common::outside(&workspace);
+    symlink(&outside, workspace.path().join("absolute")).unwrap();
+    symlink("../outside", workspace.path().join("relative")).unwrap();
+    symlink("../outside/secret", workspace.path().join("file")).unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::AllowWithinWorkspace);
+
+    for path in [
+        "absolute/secret",
+        "relative/secret",
+        "file",
+        "../outside/secret",
+    ] {
+        assert!(root_mgr.read_file_from_workspace(Path::new(path)).is_err());
+    }
+
+    assert!(root_mgr
+        .write_file_to_workspace(Path::new("absolute/new"), b"new")
+        .is_err());
+    assert!(!outside.join("new").exists());
+}
+
+#[test]
+fn writes_and_removes_never_go_through_the_last_symlink() {
+    let workspace = TestWorkspace::new().unwrap();
+    let outside = common::outside(&workspace);
+    symlink("../outside/secret", workspace.path().join("file")).unwrap();
+    symlink("../outside", workspace.path().join("folder")).unwrap();
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::AllowWithinWorkspace);
+
+    root_mgr
+        .write_file_to_workspace(Path::new("file"), b"replaced")
+        .unwrap();
+    root_mgr.remove(Path::new("folder")).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(outside.join("secret")).unwrap(),
+        "outside"
+    );
+    assert_eq!(
+        fs::read_to_string(workspace.path().join("file")).unwrap(),
+        "replaced"
+    );
+    assert!(outside.exists());
+}
+
+/// Swaps `ws/data` between a real folder and a symlink to the outside folder until stopped.
+fn swap_data_folder(volume_root: PathBuf, stop: Arc<AtomicBool>) {
+    let data = volume_root.join(TestWorkspace::ID).join("data");
+
+    while !stop.load(Ordering::Relaxed) {
+        let _ = fs::remove_file(&data);
+        let _ = fs::create_dir(&data);
+        let _ = fs::write(data.join("secret"), "inside");
+
+        let _ = fs::remove_dir_all(&data);
+        let _ = symlink(volume_root.join("outside"), &data);
+    }
+}
+
+#[test]
+fn reads_never_escape_during_symlink_swaps() {
+    let workspace = TestWorkspace::new().unwrap();
+    common::outside(&workspace);
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::AllowWithinWorkspace);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let swapper = {
+        let (root, stop) = (workspace.root().to_path_buf(), Arc::clone(&stop));
+        thread::spawn(move || swap_data_folder(root, stop))
+    };
+
+    for _ in 0..20_000 {
+        if let Ok(content) = root_mgr.read_file_from_workspace(Path::new("data/secret")) {
+            assert_eq!(content, "inside");
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    swapper.join().unwrap();
+}
+
+#[test]
+fn writes_never_escape_during_symlink_swaps() {
+    let workspace = TestWorkspace::new().unwrap();
+    let outside = common::outside(&workspace);
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::AllowWithinWorkspace);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let swapper = {
+        let (root, stop) = (workspace.root().to_path_buf(), Arc::clone(&stop));
+        thread::spawn(move || swap_data_folder(root, stop))
+    };
+
+    for _ in 0..20_000 {
+        let _ = root_mgr.write_file_to_workspace(Path::new("data/written"), b"written");
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    swapper.join().unwrap();
+
+    let leaked = fs::read_dir(&outside)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name() != "secret");
+
+    assert!(!leaked);
+}
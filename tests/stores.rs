@@ -0,0 +1,227 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use engine_runtime::{
+    config::{StoreKind, SymlinkPolicy},
+    root::{ArchiveStore, MemoryStore, RootManager, WorkspaceStore, WorkspaceStores},
+};
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    io::{self, Cursor, Read, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tar::{EntryType, Header};
+use zip::{write::FileOptions, ZipWriter};
+
+fn modified() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)
+}
+
+fn tar_header(entry_type: EntryType, size: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_cksum();
+    header
+}
+
+/// Packs a folder and two files into a tar archive.
+fn tar_archive<W: Write>(writer: W) -> W {
+    let mut builder = tar::Builder::new(writer);
+
+    builder
+        .append_data(
+            &mut tar_header(EntryType::Directory, 0),
+            "api/",
+            io::empty(),
+        )
+        .unwrap();
+    builder
+        .append_data(
+            &mut tar_header(EntryType::Regular, 5),
+            "api/index.js",
+            &b"index"[..],
+        )
+        .unwrap();
+    builder
+        .append_data(
+            &mut tar_header(EntryType::Regular, 6),
+            "config.yaml",
+            &b"config"[..],
+        )
+        .unwrap();
+
+    builder.into_inner().unwrap()
+}
+
+/// Packs a single entry of a type into a tar archive.
+fn tar_with_entry(entry_type: EntryType) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+    let mut header = tar_header(entry_type, 0);
+
+    if entry_type == EntryType::Symlink {
+        header.set_link_name("../../etc/passwd").unwrap();
+        header.set_cksum();
+    }
+
+    builder
+        .append_data(&mut header, "entry", io::empty())
+        .unwrap();
+    builder.into_inner().unwrap()
+}
+
+/// Checks that an archive store holds the files packed by `tar_archive`.
+fn assert_unpacked(store: ArchiveStore) {
+    let root_mgr = RootManager::with_store(Arc::new(store));
+
+    assert_eq!(
+        root_mgr
+            .read_file_from_workspace(Path::new("api/index.js"))
+            .unwrap(),
+        "index"
+    );
+    assert_eq!(
+        root_mgr
+            .read_file_from_workspace(Path::new("config.yaml"))
+            .unwrap(),
+        "config"
+    );
+
+    let entries: Vec<(String, bool)> = root_mgr
+        .list_dir(Path::new(""))
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.name, entry.is_dir))
+        .collect();
+
+    assert_eq!(
+        entries,
+        vec![
+            ("api".to_string(), true),
+            ("config.yaml".to_string(), false)
+        ]
+    );
+
+    let metadata = root_mgr.metadata(Path::new("config.yaml")).unwrap();
+    assert_eq!(metadata.len, 6);
+    assert_eq!(metadata.modified, Some(modified()));
+
+    // Archives are read-only.
+    assert!(root_mgr
+        .write_file_to_workspace(Path::new("config.yaml"), b"changed")
+        .is_err());
+    assert!(root_mgr.remove(Path::new("api")).is_err());
+}
+
+#[test]
+fn tar_archives_are_unpacked() {
+    let bytes = tar_archive(vec![]);
+
+    assert_unpacked(ArchiveStore::try_from(bytes, modified()).unwrap());
+}
+
+#[test]
+fn gzipped_tar_archives_are_unpacked() {
+    let bytes = tar_archive(GzEncoder::new(vec![], Compression::default()))
+        .finish()
+        .unwrap();
+
+    assert_unpacked(ArchiveStore::try_from(bytes, modified()).unwrap());
+}
+
+#[test]
+fn zip_archives_are_unpacked() {
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+
+    writer
+        .add_directory("api/", FileOptions::default())
+        .unwrap();
+    writer
+        .start_file("api/index.js", FileOptions::default())
+        .unwrap();
+    writer.write_all(b"index").unwrap();
+    writer
+        .start_file("config.yaml", FileOptions::default())
+        .unwrap();
+    writer.write_all(b"config").unwrap();
+
+    let bytes = writer.finish().unwrap().into_inner();
+
+    assert_unpacked(ArchiveStore::try_from(bytes, modified()).unwrap());
+}
+
+#[test]
+fn symlink_and_special_entries_are_rejected() {
+    for entry_type in [EntryType::Symlink, EntryType::Link, EntryType::Fifo] {
+        assert!(ArchiveStore::try_from(tar_with_entry(entry_type), modified()).is_err());
+    }
+}
+
+#[test]
+fn entries_over_max_size_are_rejected() {
+    let size = ArchiveStore::MAX_ENTRY_SIZE + 1;
+
+    // Zeros compress well, so the archive stays small while its entry does not.
+    let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::fast()));
+    builder
+        .append_data(
+            &mut tar_header(EntryType::Regular, size),
+            "large",
+            io::repeat(0).take(size),
+        )
+        .unwrap();
+
+    let bytes = builder.into_inner().unwrap().finish().unwrap();
+
+    assert!(ArchiveStore::try_from(bytes, modified()).is_err());
+}
+
+#[test]
+fn memory_store_lists_and_removes_files() {
+    let root_mgr = RootManager::with_store(Arc::new(MemoryStore::default()));
+
+    root_mgr
+        .write_file_to_workspace(Path::new("folder/nested/file"), b"nested")
+        .unwrap();
+    root_mgr
+        .write_file_to_workspace(Path::new("file"), b"file")
+        .unwrap();
+
+    let entries: Vec<(String, bool)> = root_mgr
+        .list_dir(Path::new(""))
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.name, entry.is_dir))
+        .collect();
+
+    assert_eq!(
+        entries,
+        vec![("file".to_string(), false), ("folder".to_string(), true)]
+    );
+
+    root_mgr.remove(Path::new("folder")).unwrap();
+
+    assert!(!root_mgr.exists(Path::new("folder/nested/file")).unwrap());
+    assert!(!root_mgr.exists(Path::new("folder")).unwrap());
+    assert!(root_mgr.list_dir(Path::new("folder")).is_err());
+    assert!(root_mgr.list_dir(Path::new("file")).is_err());
+
+    assert!(root_mgr.remove(Path::new("folder")).is_err());
+    assert!(root_mgr.remove(Path::new("")).is_err());
+    assert!(root_mgr.remove(Path::new("../file")).is_err());
+}
+
+#[test]
+fn memory_stores_are_only_opened_once_created() {
+    let stores = WorkspaceStores::new(StoreKind::Memory, SymlinkPolicy::Deny);
+
+    assert!(stores.memory("test").is_err());
+
+    let created = stores.create_memory("test");
+    created.write(Path::new("file"), b"file").unwrap();
+
+    let opened = stores.memory("test").unwrap();
+    assert_eq!(opened.read(Path::new("file")).unwrap(), b"file");
+}
@@ -0,0 +1,210 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+use engine_runtime::{
+    config::{SniCertificate, TlsConfig},
+    TlsTerminator,
+};
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+use std::{
+    convert::TryFrom,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{io, time};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// A folder of self-signed certificates, generated for the domains they are named after.
+struct Certs {
+    dir: PathBuf,
+    roots: RootCertStore,
+}
+
+impl Certs {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "engine_runtime_tls_{}_{}",
+            name,
+            std::process::id()
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        Self {
+            dir,
+            roots: RootCertStore::empty(),
+        }
+    }
+
+    /// Writes a new certificate and key for `domains` as `<name>.pem` and `<name>.key`, and returns its DER encoding.
+    fn generate(&mut self, name: &str, domains: &[&str]) -> Vec<u8> {
+        let domains = domains.iter().map(|domain| domain.to_string()).collect();
+        let cert = rcgen::generate_simple_self_signed(domains).unwrap();
+
+        // Every serialization is signed anew, so the DER encoding is taken from the PEM that gets written.
+        let pem = cert.serialize_pem().unwrap();
+        let der = rustls_pemfile::certs(&mut pem.as_bytes())
+            .unwrap()
+            .remove(0);
+
+        fs::write(self.key_path(name), cert.serialize_private_key_pem()).unwrap();
+        fs::write(self.cert_path(name), &pem).unwrap();
+
+        self.roots.add(&Certificate(der.clone())).unwrap();
+
+        der
+    }
+
+    fn cert_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.pem", name))
+    }
+
+    fn key_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.key", name))
+    }
+
+    fn config(&self, default: &str, certificates: Vec<SniCertificate>) -> TlsConfig {
+        TlsConfig {
+            cert_path: self.cert_path(default),
+            key_path: self.key_path(default),
+            certificates,
+            alpn_protocols: vec!["http/1.1".into()],
+            reload_interval: 1,
+            handshake_timeout: 10,
+        }
+    }
+
+    fn sni(&self, name: &str, domains: &[&str]) -> SniCertificate {
+        SniCertificate {
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+            cert_path: self.cert_path(name),
+            key_path: self.key_path(name),
+        }
+    }
+
+    /// Completes a handshake for `server_name` and returns the certificate the server presented.
+    async fn handshake(&self, acceptor: TlsAcceptor, server_name: &str) -> Vec<u8> {
+        self.try_handshake(acceptor, server_name).await.unwrap()
+    }
+
+    async fn try_handshake(
+        &self,
+        acceptor: TlsAcceptor,
+        server_name: &str,
+    ) -> std::io::Result<Vec<u8>> {
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.roots.clone())
+            .with_no_client_auth();
+
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let server_name = ServerName::try_from(server_name).unwrap();
+
+        let (client_io, server_io) = io::duplex(64 * 1024);
+
+        let (client, server) = tokio::join!(
+            connector.connect(server_name, client_io),
+            acceptor.accept(server_io)
+        );
+
+        server?;
+        let client = client?;
+
+        Ok(client.get_ref().1.peer_certificates().unwrap()[0].0.clone())
+    }
+}
+
+impl Drop for Certs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[tokio::test]
+async fn handshake_serves_default_certificate() {
+    let mut certs = Certs::new("default");
+    let default = certs.generate("default", &["default.test"]);
+
+    let terminator = TlsTerminator::new(certs.config("default", vec![])).unwrap();
+
+    let served = certs.handshake(terminator.acceptor(), "default.test").await;
+
+    assert_eq!(served, default);
+}
+
+#[tokio::test]
+async fn sni_selects_certificate_by_domain() {
+    let mut certs = Certs::new("sni");
+    let default = certs.generate("default", &["default.test", "unknown.test"]);
+    let exact = certs.generate("exact", &["api.other.test"]);
+    let wildcard = certs.generate("wildcard", &["*.example.test"]);
+
+    let config = certs.config(
+        "default",
+        vec![
+            certs.sni("exact", &["api.other.test"]),
+            certs.sni("wildcard", &["*.example.test"]),
+        ],
+    );
+
+    let terminator = TlsTerminator::new(config).unwrap();
+
+    assert_eq!(
+        certs
+            .handshake(terminator.acceptor(), "api.other.test")
+            .await,
+        exact
+    );
+    assert_eq!(
+        certs
+            .handshake(terminator.acceptor(), "A.Example.Test")
+            .await,
+        wildcard
+    );
+    assert_eq!(
+        certs.handshake(terminator.acceptor(), "unknown.test").await,
+        default
+    );
+}
+
+#[tokio::test]
+async fn changed_certificate_files_are_reloaded() {
+    let mut certs = Certs::new("reload");
+    let first = certs.generate("default", &["default.test"]);
+
+    let terminator = Arc::new(TlsTerminator::new(certs.config("default", vec![])).unwrap());
+    tokio::spawn(Arc::clone(&terminator).watch());
+
+    assert_eq!(
+        certs.handshake(terminator.acceptor(), "default.test").await,
+        first
+    );
+
+    let second = certs.generate("default", &["default.test"]);
+    assert_ne!(first, second);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        // The files may be reloaded between writing the key and the certificate, which fails the handshake.
+        let served = certs
+            .try_handshake(terminator.acceptor(), "default.test")
+            .await;
+        if served.ok() == Some(second.clone()) {
+            break;
+        }
+
+        assert!(Instant::now() < deadline, "certificate was not reloaded");
+        time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[test]
+fn invalid_certificate_files_are_rejected() {
+    let certs = Certs::new("invalid");
+    fs::write(certs.cert_path("default"), "not a certificate").unwrap();
+    fs::write(certs.key_path("default"), "not a key").unwrap();
+
+    assert!(TlsTerminator::new(certs.config("default", vec![])).is_err());
+}
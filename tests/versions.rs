@@ -0,0 +1,102 @@
+// Copyright 2022 the Gigamono authors. All rights reserved. GPL-3.0 License.
+
+mod common;
+
+use engine_runtime::{config::SymlinkPolicy, harness::TestWorkspace, root::WorkspaceVersions};
+use std::{
+    path::{Path, PathBuf},
+    thread,
+};
+
+fn create(workspace: &TestWorkspace, name: &str) {
+    common::versions(workspace)
+        .create(
+            name,
+            vec![(PathBuf::from("file"), name.as_bytes().to_vec())],
+        )
+        .unwrap();
+}
+
+fn version_path(workspace: &TestWorkspace, name: &str) -> PathBuf {
+    workspace
+        .path()
+        .join(WorkspaceVersions::VERSIONS)
+        .join(name)
+}
+
+#[test]
+fn prune_removes_unpinned_versions() {
+    let workspace = TestWorkspace::new().unwrap();
+    create(&workspace, "v1");
+    create(&workspace, "v2");
+
+    common::versions(&workspace).prune(0).unwrap();
+
+    assert!(!version_path(&workspace, "v1").exists());
+    assert!(version_path(&workspace, "v2").exists());
+}
+
+#[test]
+fn prune_defers_removing_pinned_versions() {
+    let workspace = TestWorkspace::new().unwrap();
+    create(&workspace, "v1");
+
+    let first = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+    let second = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+
+    create(&workspace, "v2");
+    common::versions(&workspace).prune(0).unwrap();
+
+    // Stores opened before the switch still read the pruned version.
+    assert_eq!(first.version(), Some("v1"));
+    assert_eq!(
+        first.read_file_from_workspace(Path::new("file")).unwrap(),
+        "v1"
+    );
+
+    drop(first);
+    assert!(version_path(&workspace, "v1").exists());
+
+    drop(second);
+    assert!(!version_path(&workspace, "v1").exists());
+    assert!(version_path(&workspace, "v2").exists());
+}
+
+#[test]
+fn pinned_versions_that_are_not_pruned_are_kept() {
+    let workspace = TestWorkspace::new().unwrap();
+    create(&workspace, "v1");
+
+    let root_mgr = common::root_mgr(&workspace, SymlinkPolicy::Deny);
+    create(&workspace, "v2");
+    drop(root_mgr);
+
+    assert!(version_path(&workspace, "v1").exists());
+}
+
+#[test]
+fn concurrent_switches_do_not_collide() {
+    let workspace = TestWorkspace::new().unwrap();
+    create(&workspace, "v1");
+    create(&workspace, "v2");
+
+    let threads: Vec<_> = (0..4)
+        .map(|index| {
+            let root = workspace.root().to_str().unwrap().to_string();
+
+            thread::spawn(move || {
+                let versions = WorkspaceVersions::open(&root, TestWorkspace::ID).unwrap();
+
+                for _ in 0..100 {
+                    versions
+                        .switch(if index % 2 == 0 { "v1" } else { "v2" })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}